@@ -74,9 +74,15 @@ use log::info;
 use miniz_oxide::inflate::decompress_to_vec_zlib;
 use regex::Regex;
 use ripemd128::{Digest, Ripemd128};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::io::{self, prelude::*, Error, ErrorKind};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+mod progress;
+
+pub use progress::*;
 
 // The `Encrypted` field of MDict file header.
 // The possible is 0, 1, 2, 3.
@@ -188,6 +194,12 @@ impl MDictHeader {
         let calc_checksum = adler::adler32_slice(&header_buf);
         check_eq(calc_checksum, checksum, "MDict header checksum")?;
         // two 0x0 in the end of the content
+        if size < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "MDict header is too short",
+            ));
+        }
         let attrs = Self::parse_header(&header_buf[0..size - 2])?;
         info!("MDict header: {:#?}", attrs);
         let encoding = match mode {
@@ -313,6 +325,8 @@ pub struct MDictIndex<R: Read + Seek> {
     file: io::BufReader<R>,
     key_block_offset: u64,
     header: MDictHeader,
+    observer: Option<Arc<dyn MDictProgressObserver>>,
+    cancel: Option<MDictCancelToken>,
 }
 
 /// A keywords block
@@ -332,9 +346,6 @@ struct MDictKeyBlockIndex {
     comp_size: u64,
     /// Uncompressed size of this keyword block
     uncomp_size: u64,
-    /// Words list, keyword and offset of its record
-    /// from the begin of totally uncompressed record blcoks
-    words: Vec<(String, u64)>,
 }
 
 /// Index to a compressed block which contains records
@@ -344,6 +355,8 @@ pub struct MDictRecordBlockIndex {
     pub offset: u64,
     /// Compressed size of this record block
     pub comp_size: u64,
+    /// Uncompressed size of this record block
+    pub uncomp_size: u64,
 }
 
 /// Index to a record
@@ -376,11 +389,38 @@ impl<R: Read + Seek> MDictIndex<R> {
             file,
             key_block_offset,
             header,
+            observer: None,
+            cancel: None,
         })
     }
 
-    /// Read the keywords block.
-    fn read_keys(&mut self) -> io::Result<Vec<MDictKeyBlockIndex>> {
+    /// Report the progress of building index to `observer`.
+    pub fn with_progress(mut self, observer: Arc<dyn MDictProgressObserver>) -> MDictIndex<R> {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Stop building index when `cancel` is cancelled.
+    pub fn with_cancel(mut self, cancel: MDictCancelToken) -> MDictIndex<R> {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    // Check the cancel token, then report the progress.
+    fn report(&self, progress: &MDictProgress) -> io::Result<()> {
+        if let Some(cancel) = &self.cancel {
+            cancel.check()?;
+        }
+        if let Some(observer) = &self.observer {
+            observer.on_progress(progress);
+        }
+        Ok(())
+    }
+
+    /// Read the header and the index of keywords blocks.
+    ///
+    /// After this function, the cursor of file will stop at the start of keywords blocks.
+    fn read_key_section(&mut self) -> io::Result<Vec<MDictKeyBlockIndex>> {
         let unencrypted = self.header.encryption_mode.mode() & 0x1 == 0x0;
         let block_size = match self.header.version() {
             MDictFormatVersion::V1 => 4 * 4,
@@ -449,11 +489,7 @@ impl<R: Read + Seek> MDictIndex<R> {
             "Size of keyword blocks",
         )?;
         info!("Decode keywords block index in {:?}", now.elapsed());
-        let now = std::time::Instant::now();
-        let key_block = read_len(&mut self.file, key_block_size_calc as usize)?.into();
-        let keys = self.read_key_block(key_block, key_block_index)?;
-        info!("Decode keywords blocks in {:?}", now.elapsed());
-        Ok(keys)
+        Ok(key_block_index)
     }
 
     /// Search magic number 0x{0,1,2},0x0,0x0,0x0 as start of keywords block
@@ -527,69 +563,47 @@ impl<R: Read + Seek> MDictIndex<R> {
                 last_word,
                 comp_size,
                 uncomp_size,
-                // write in `read_key_block`
-                words: Vec::with_capacity(block_entries as usize),
             });
         }
         Ok(list)
     }
 
-    fn read_key_block(
-        &mut self,
-        mut block: Bytes,
-        mut index: Vec<MDictKeyBlockIndex>,
-    ) -> io::Result<Vec<MDictKeyBlockIndex>> {
+    // Read and decode a keywords block, the cursor of file should be at the start of this block.
+    // Returns keywords and the offset of their records from the begin of totally uncompressed record blocks.
+    fn read_key_block(&mut self, idx: &MDictKeyBlockIndex) -> io::Result<Vec<(String, u64)>> {
         // basically strlen+strcpy, but support 2 bytes encoding like UTF-16LE
         let split_null = if self.header.unit_size() == 2 {
             split_dual_null
         } else {
             split_single_null
         };
-        for idx in index.iter_mut() {
-            let compressed = block.split_to(idx.comp_size as usize);
-            let mut uncompressed = uncompress(compressed)?;
-            check_eq(
-                uncompressed.len() as u64,
-                idx.uncomp_size,
-                "Size of uncompressed content",
-            )?;
-            for _ in 0..idx.block_entries {
-                let offset = self.read_int(&mut uncompressed);
-                let string_encoded = split_null(&mut uncompressed);
-                let string_decoded = self.header.decode_string(string_encoded)?;
-                idx.words.push((string_decoded, offset));
-            }
-            if !uncompressed.is_empty() {
-                return Err(Error::new(
-                    ErrorKind::InvalidData,
-                    "Unexpected extra content at the end of keyword block".to_owned(),
-                ));
-            }
+        let compressed = read_len(&mut self.file, idx.comp_size as usize)?;
+        let mut uncompressed = uncompress(compressed.into())?;
+        check_eq(
+            uncompressed.len() as u64,
+            idx.uncomp_size,
+            "Size of uncompressed content",
+        )?;
+        let mut words = Vec::with_capacity(idx.block_entries as usize);
+        for _ in 0..idx.block_entries {
+            let offset = self.read_int(&mut uncompressed);
+            let string_encoded = split_null(&mut uncompressed);
+            let string_decoded = self.header.decode_string(string_encoded)?;
+            words.push((string_decoded, offset));
         }
-        Ok(index)
+        if !uncompressed.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Unexpected extra content at the end of keyword block".to_owned(),
+            ));
+        }
+        Ok(words)
     }
 
-    /// Read keywords blocks and records blocks index, and generate the Index
-    ///
-    /// This function returns a `Vec` of `MDictRecordBlockIndex` and a `Vec` of `(String, MDictRecordIndex)`
-    ///
-    /// The `MDictRecordBlockIndex` is a index to the a record block of a MDict file.
-    ///
-    /// The `String` is the keyword and The `MDictRecordIndex` is a index to the record of this keyword.
-    ///
-    /// # Error
+    /// Read the header and the index of records blocks.
     ///
-    /// This function returns [`io::Error`] if any operations failed.
-    ///
-    /// [`io::Error`] with [`ErrorKind::InvalidData`] will return if uncompression is failed, checksum is incorrect,
-    /// length of blocks or header is incorrect or string can't be decoded to UTF-8.
-    // TODO: Simplify return type
-    pub fn make_index(
-        &mut self,
-    ) -> io::Result<(Vec<MDictRecordBlockIndex>, Vec<(String, MDictRecordIndex)>)> {
-        self.file.seek(io::SeekFrom::Start(self.key_block_offset))?;
-        // read keywords block is done in `read_keys`, this function is actually read record block index.
-        let keys = self.read_keys()?;
+    /// The cursor of file should be at the start of records section, which is after the keywords blocks.
+    fn read_record_section(&mut self) -> io::Result<Vec<MDictRecordBlockIndex>> {
         let header_size = match self.header.version() {
             MDictFormatVersion::V1 => 4 * 4,
             MDictFormatVersion::V2 => 4 * 8,
@@ -598,7 +612,8 @@ impl<R: Read + Seek> MDictIndex<R> {
         let mut header = header_buf.as_slice();
         let num_blocks = self.read_int(&mut header);
         info!("record block num: {}", num_blocks);
-        let num_entries = self.read_int(&mut header) as usize;
+        let num_entries = self.read_int(&mut header);
+        info!("record entries num: {}", num_entries);
         let block_index_size = self.read_int(&mut header);
         info!("record block index size: {}", block_index_size);
         let blocks_size = self.read_int(&mut header);
@@ -619,51 +634,207 @@ impl<R: Read + Seek> MDictIndex<R> {
         let block_index = self.read_record_block_info(block_index_bytes.into())?;
         let blocks_size_calc: u64 = block_index.iter().map(|(c, _)| *c).sum();
         check_eq(blocks_size_calc, blocks_size, "Size of record block")?;
-        info!("Decode record block index in {:?}", now.elapsed());
-
-        let now = std::time::Instant::now();
-        // collect pairs of (keywords, offset in uncompressed records), drop others
-        let mut keys: Vec<_> = keys.into_iter().flat_map(|i| i.words.into_iter()).collect();
-        // This should be already sorted.
-        keys.sort_by_key(|(_, o)| *o);
         // take the start of record blocks
         let record_block_offset = self.file.seek(io::SeekFrom::Current(0))?;
-        let mut indexes = Vec::with_capacity(num_entries as usize);
         let mut blocks = Vec::with_capacity(num_blocks as usize);
         let mut comp_offset = 0;
-        let mut uncomp_offset = 0;
-        let mut keys = keys.into_iter().peekable();
-        for (bi, (comp_size, uncomp_size)) in block_index.into_iter().enumerate() {
-            let record_block = MDictRecordBlockIndex {
-                comp_size,
+        for (comp_size, uncomp_size) in block_index.into_iter() {
+            blocks.push(MDictRecordBlockIndex {
                 offset: record_block_offset + comp_offset,
-            };
-            let next_comp_offset = comp_offset + comp_size;
-            let next_uncomp_offset = uncomp_offset + uncomp_size;
-            while let Some((key, o)) = keys.next() {
-                let offset = o - uncomp_offset;
-                assert!(offset <= uncomp_size);
-                let end = match keys.peek() {
-                    Some((_, next_offset)) => *next_offset,
-                    None => next_uncomp_offset,
-                };
-                let len = end.max(next_uncomp_offset) - o;
-                let index = MDictRecordIndex {
-                    block: bi as u32,
-                    offset: offset as u32,
-                    len: len as u32,
-                };
-                indexes.push((key, index));
-                if end >= next_uncomp_offset {
-                    break;
+                comp_size,
+                uncomp_size,
+            });
+            comp_offset += comp_size;
+        }
+        info!("Decode record block index in {:?}", now.elapsed());
+        Ok(blocks)
+    }
+
+    /// Read keywords blocks and records blocks index, and generate the Index
+    ///
+    /// This function returns a `Vec` of `MDictRecordBlockIndex` and a `Vec` of `(String, MDictRecordIndex)`
+    ///
+    /// The `MDictRecordBlockIndex` is a index to the a record block of a MDict file.
+    ///
+    /// The `String` is the keyword and The `MDictRecordIndex` is a index to the record of this keyword.
+    ///
+    /// All keywords are kept in memory, use [`make_index_with`](MDictIndex::make_index_with) to process
+    /// keywords one by one.
+    ///
+    /// # Error
+    ///
+    /// This function returns [`io::Error`] if any operations failed.
+    ///
+    /// [`io::Error`] with [`ErrorKind::InvalidData`] will return if uncompression is failed, checksum is incorrect,
+    /// length of blocks or header is incorrect or string can't be decoded to UTF-8.
+    // TODO: Simplify return type
+    pub fn make_index(
+        &mut self,
+    ) -> io::Result<(Vec<MDictRecordBlockIndex>, Vec<(String, MDictRecordIndex)>)> {
+        let mut indexes = Vec::new();
+        let blocks = self.make_index_with(|key, index| {
+            indexes.push((key, index));
+            Ok(())
+        })?;
+        Ok((blocks, indexes))
+    }
+
+    /// Read keywords blocks and records blocks index, and pass each keyword to `f`
+    ///
+    /// The keywords blocks are read and decoded one by one, `f` is called with the keyword and
+    /// the index to its record in the order of the file. So the memory used by this function is bounded
+    /// by the largest keywords blocks, instead of all keywords.
+    ///
+    /// The length of a record is known by the start of the next record, which is found in the
+    /// keywords after it. Keywords may not be sorted by the offset of their records, so a keyword
+    /// is passed to `f` after the next keywords block is decoded, or later if the start of the next
+    /// record is not found yet.
+    ///
+    /// This function returns a `Vec` of `MDictRecordBlockIndex`, see [`make_index`](MDictIndex::make_index).
+    ///
+    /// The progress is reported after each phase and each keywords block if an observer is set by
+    /// [`with_progress`](MDictIndex::with_progress).
+    ///
+    /// # Error
+    ///
+    /// This function returns [`io::Error`] if any operations failed or `f` returns an error,
+    /// see [`make_index`](MDictIndex::make_index).
+    ///
+    /// [`io::Error`] wrapping [`MDictCancelled`] will return if the token set by
+    /// [`with_cancel`](MDictIndex::with_cancel) is cancelled.
+    pub fn make_index_with<F>(&mut self, mut f: F) -> io::Result<Vec<MDictRecordBlockIndex>>
+    where
+        F: FnMut(String, MDictRecordIndex) -> io::Result<()>,
+    {
+        self.walk_keys(|_, key, index, _| f(key, index))
+    }
+
+    /// Read keywords blocks, records blocks index and records, and pass each keyword with its record to `f`
+    ///
+    /// This is like [`make_index_with`](MDictIndex::make_index_with), but `f` is also called with the
+    /// uncompressed content of the record. The records are read in the order of the file and only the
+    /// last uncompressed record block is kept, so this is much faster than looking up each keyword.
+    ///
+    /// # Error
+    ///
+    /// See [`make_index_with`](MDictIndex::make_index_with).
+    pub fn make_index_with_records<F>(&mut self, mut f: F) -> io::Result<Vec<MDictRecordBlockIndex>>
+    where
+        F: FnMut(String, MDictRecordIndex, Bytes) -> io::Result<()>,
+    {
+        let mut cache = None;
+        self.walk_keys(|this, key, index, blocks| {
+            let record = this.read_record(&mut cache, blocks, &index)?;
+            f(key, index, record)
+        })
+    }
+
+    // Read the content of a record, the last uncompressed record block is kept in `cache`.
+    //
+    // This is called between reading keywords blocks, so the cursor of file is restored.
+    fn read_record(
+        &mut self,
+        cache: &mut Option<(usize, Bytes)>,
+        blocks: &[MDictRecordBlockIndex],
+        index: &MDictRecordIndex,
+    ) -> io::Result<Bytes> {
+        let mut block = index.block as usize;
+        let mut start = index.offset as usize;
+        let mut remain = index.len as usize;
+        let mut parts = Vec::new();
+        loop {
+            let content = match cache {
+                Some((cached, content)) if *cached == block => content.clone(),
+                _ => {
+                    let pos = self.file.seek(io::SeekFrom::Current(0))?;
+                    let index = blocks.get(block).ok_or_else(|| {
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("Invalid record block: {}", block),
+                        )
+                    })?;
+                    let content = read_block(&mut self.file, index)?;
+                    self.file.seek(io::SeekFrom::Start(pos))?;
+                    *cache = Some((block, content.clone()));
+                    content
                 }
+            };
+            if start > content.len() {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid offset of record: {}", start),
+                ));
+            }
+            let end = (start + remain).min(content.len());
+            parts.push(content.slice(start..end));
+            remain -= end - start;
+            if remain == 0 {
+                break;
             }
-            comp_offset = next_comp_offset;
-            uncomp_offset = next_uncomp_offset;
-            blocks.push(record_block);
+            // this record spans multiple blocks
+            block += 1;
+            start = 0;
         }
-        info!("Generate index of keyword to record in {:?}", now.elapsed());
-        Ok((blocks, indexes))
+        if parts.len() == 1 {
+            Ok(parts.pop().unwrap())
+        } else {
+            Ok(parts.concat().into())
+        }
+    }
+
+    // Decode keywords blocks and pass each keyword and the index to its record to `f`,
+    // `f` can also use this reader and the records blocks index.
+    fn walk_keys<F>(&mut self, mut f: F) -> io::Result<Vec<MDictRecordBlockIndex>>
+    where
+        F: FnMut(&mut Self, String, MDictRecordIndex, &[MDictRecordBlockIndex]) -> io::Result<()>,
+    {
+        self.file.seek(io::SeekFrom::Start(self.key_block_offset))?;
+        let key_index = self.read_key_section()?;
+        let key_blocks_offset = self.file.seek(io::SeekFrom::Current(0))?;
+        let key_blocks_size: u64 = key_index.iter().map(|i| i.comp_size).sum();
+        let mut progress = MDictProgress::new(MDictBuildPhase::Header);
+        progress.blocks_total = key_index.len() as u64;
+        progress.entries_total = key_index.iter().map(|i| i.block_entries).sum();
+        progress.bytes_done = key_blocks_offset;
+        progress.bytes_total = key_blocks_offset;
+        self.report(&progress)?;
+        // The records section is after keywords blocks, read it first, so the keywords
+        // can be mapped to their records when the keywords blocks are decoded.
+        self.file
+            .seek(io::SeekFrom::Start(key_blocks_offset + key_blocks_size))?;
+        let blocks = self.read_record_section()?;
+        let record_blocks_offset = self.file.seek(io::SeekFrom::Current(0))?;
+        progress.phase = MDictBuildPhase::RecordIndex;
+        progress.bytes_done = record_blocks_offset - key_blocks_offset - key_blocks_size;
+        progress.bytes_total = progress.bytes_done;
+        self.report(&progress)?;
+        self.file.seek(io::SeekFrom::Start(key_blocks_offset))?;
+
+        let now = std::time::Instant::now();
+        progress.phase = MDictBuildPhase::KeyBlocks;
+        progress.bytes_done = 0;
+        progress.bytes_total = key_blocks_size;
+        self.report(&progress)?;
+        let locator = RecordLocator::new(&blocks);
+        let mut pending = PendingRecords::default();
+        for idx in key_index.iter() {
+            let entries = self.read_key_block(idx)?;
+            for (key, start, end) in pending.push_block(entries) {
+                f(self, key, locator.locate(start, end)?, &blocks)?;
+            }
+            progress.blocks_done += 1;
+            progress.entries_done += idx.block_entries;
+            progress.bytes_done += idx.comp_size;
+            self.report(&progress)?;
+        }
+        for (key, start, end) in pending.finish(locator.records_size()) {
+            f(self, key, locator.locate(start, end)?, &blocks)?;
+        }
+        info!(
+            "Decode keywords blocks and generate index of keyword to record in {:?}",
+            now.elapsed()
+        );
+        Ok(blocks)
     }
 
     fn read_record_block_info(&mut self, mut block: Bytes) -> io::Result<Vec<(u64, u64)>> {
@@ -702,6 +873,132 @@ impl<R: Read + Seek> MDictIndex<R> {
     }
 }
 
+// Keywords whose records' lengths are not known yet, in the order of the file.
+//
+// A record ends at the least offset of records greater than its start. Keywords are sorted by the
+// offsets of their records except for nearby ones, so the end is found in the offsets of the
+// pending keywords and the keywords blocks before and after them.
+#[derive(Default)]
+struct PendingRecords {
+    pending: VecDeque<(String, u64)>,
+    // offsets of the last two keywords blocks
+    last_blocks: VecDeque<Vec<u64>>,
+}
+
+impl PendingRecords {
+    // Add keywords of a block, and return pending keywords before them whose records end in the
+    // offsets known by now, with the start and the end of their records.
+    fn push_block(&mut self, entries: Vec<(String, u64)>) -> Vec<(String, u64, u64)> {
+        let offsets = self.offsets(&entries);
+        let mut resolved = Vec::new();
+        while let Some((key, start)) = self.pending.pop_front() {
+            match next_offset(&offsets, start) {
+                Some(end) => resolved.push((key, start, end)),
+                None => {
+                    // later keywords are kept in the order of the file
+                    self.pending.push_front((key, start));
+                    break;
+                }
+            }
+        }
+        if self.last_blocks.len() == 2 {
+            self.last_blocks.pop_front();
+        }
+        self.last_blocks
+            .push_back(entries.iter().map(|(_, offset)| *offset).collect());
+        self.pending.extend(entries);
+        resolved
+    }
+
+    // The rest keywords after all blocks, the last record ends at the end of records.
+    fn finish(self, records_size: u64) -> Vec<(String, u64, u64)> {
+        let offsets = self.offsets(&[]);
+        self.pending
+            .into_iter()
+            .map(|(key, start)| {
+                let end = next_offset(&offsets, start).unwrap_or(records_size);
+                (key, start, end)
+            })
+            .collect()
+    }
+
+    // Sorted offsets of the last blocks, pending keywords and `entries`
+    fn offsets(&self, entries: &[(String, u64)]) -> Vec<u64> {
+        let mut offsets: Vec<u64> = self.last_blocks.iter().flatten().copied().collect();
+        offsets.extend(
+            self.pending
+                .iter()
+                .chain(entries)
+                .map(|(_, offset)| *offset),
+        );
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+}
+
+// The least offset in `offsets` greater than `start`
+fn next_offset(offsets: &[u64], start: u64) -> Option<u64> {
+    let i = match offsets.binary_search(&start) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    };
+    offsets.get(i).copied()
+}
+
+// Map the offset of records in the totally uncompressed record blocks to `MDictRecordIndex`
+struct RecordLocator {
+    // the end offset of each record block in the uncompressed records
+    block_ends: Vec<u64>,
+}
+
+impl RecordLocator {
+    fn new(blocks: &[MDictRecordBlockIndex]) -> RecordLocator {
+        let mut records_size = 0;
+        let block_ends = blocks
+            .iter()
+            .map(|b| {
+                records_size += b.uncomp_size;
+                records_size
+            })
+            .collect();
+        RecordLocator { block_ends }
+    }
+
+    fn records_size(&self) -> u64 {
+        self.block_ends.last().copied().unwrap_or(0)
+    }
+
+    fn block_start(&self, block: usize) -> u64 {
+        if block == 0 {
+            0
+        } else {
+            self.block_ends[block - 1]
+        }
+    }
+
+    // Locate the record from `start` to `end`
+    fn locate(&self, start: u64, end: u64) -> io::Result<MDictRecordIndex> {
+        if start > end || end > self.records_size() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid offset of record: {}..{}", start, end),
+            ));
+        }
+        // A record may span multiple blocks, it's in the block containing its start.
+        let block = match self.block_ends.binary_search(&start) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+        .min(self.block_ends.len().saturating_sub(1));
+        Ok(MDictRecordIndex {
+            block: block as u32,
+            offset: (start - self.block_start(block)) as u32,
+            len: (end - start) as u32,
+        })
+    }
+}
+
 // read until one \0
 fn split_single_null(buf: &mut Bytes) -> Bytes {
     for i in 0..buf.len() {
@@ -787,18 +1084,61 @@ fn uncompress(mut block: Bytes) -> io::Result<Bytes> {
 fn read_len<R: Read>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
     let mut buf = Vec::with_capacity(len);
     reader.take(len as u64).read_to_end(&mut buf)?;
-    Ok(buf)
+    check_read_len(buf, len)
+}
+
+// A truncated file is an error instead of a short buffer
+fn check_read_len(buf: Vec<u8>, len: usize) -> io::Result<Vec<u8>> {
+    if buf.len() == len {
+        Ok(buf)
+    } else {
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("Expect {} bytes, got {}", len, buf.len()),
+        ))
+    }
+}
+
+// A corrupt index or file is an error instead of a panic when splitting the record
+fn check_record_offset(key: &MDictRecordIndex, uncompressed: &Bytes) -> io::Result<()> {
+    if key.offset as usize > uncompressed.len() {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Invalid offset of record: {}, the record block has {} bytes",
+                key.offset,
+                uncompressed.len()
+            ),
+        ))
+    } else {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "async")]
-use tokio::{io::AsyncSeek, prelude::*};
+use futures_util::{
+    io::{AsyncBufRead, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+    stream::{Stream, TryStreamExt},
+};
+
+/// Adapters to use readers of tokio with the asynchronous API of this crate.
+///
+/// The asynchronous API is generic over the [`AsyncRead`] and [`AsyncSeek`] traits of
+/// `futures`, so it works with any runtime. A `tokio::fs::File` can be converted by
+/// [`TokioAsyncReadCompatExt::compat`](compat::TokioAsyncReadCompatExt::compat).
+///
+/// This module is only available with the "tokio-compat" crate feature.
+#[cfg(feature = "tokio-compat")]
+pub mod compat {
+    pub use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+}
 
 #[cfg(feature = "async")]
 // read len bytes from this reader and return it as `Vec<u8>`
 async fn read_len_async<R: AsyncRead + Unpin>(reader: &mut R, len: usize) -> io::Result<Vec<u8>> {
     let mut buf = Vec::with_capacity(len);
     reader.take(len as u64).read_to_end(&mut buf).await?;
-    Ok(buf)
+    check_read_len(buf, len)
 }
 
 #[cfg(not(feature = "async"))]
@@ -830,6 +1170,7 @@ where
         comp_size,
         uncompressed.len()
     );
+    check_record_offset(key, &uncompressed)?;
     let mut data = uncompressed.split_off(key.offset as usize);
     data.truncate(key.len as usize);
     Ok(data)
@@ -847,6 +1188,9 @@ where
 /// may failed or return random data.
 ///
 /// This is the asynchronous version of this function. To use blocking version, unselect the "async" crate feature
+///
+/// The `reader` can be any type implementing the `futures` IO traits, so this function doesn't depend on
+/// a specific runtime. Readers of tokio can be used through the adapters in `compat`.
 pub async fn lookup<AR>(
     mut reader: AR,
     key: &MDictRecordIndex,
@@ -858,7 +1202,307 @@ where
     reader.seek(io::SeekFrom::Start(block.offset)).await?;
     let compressed = read_len_async(&mut reader, block.comp_size as usize).await?;
     let mut uncompressed = uncompress(compressed.into())?;
+    check_record_offset(key, &uncompressed)?;
     let mut data = uncompressed.split_off(key.offset as usize);
     data.truncate(key.len as usize);
     Ok(data)
 }
+
+// The part of a record to read, split by the record blocks containing it.
+struct RecordRange {
+    // blocks containing the rest of the range
+    blocks: std::vec::IntoIter<MDictRecordBlockIndex>,
+    // offset of the range in the uncompressed content of the next block
+    skip: u64,
+    // remaining length of the range
+    remain: u64,
+}
+
+impl RecordRange {
+    fn new<B: RangeBounds<u64>>(
+        key: &MDictRecordIndex,
+        blocks: &[MDictRecordBlockIndex],
+        range: B,
+    ) -> RecordRange {
+        let len = key.len as u64;
+        let start = match range.start_bound() {
+            Bound::Included(s) => *s,
+            Bound::Excluded(s) => s.saturating_add(1),
+            Bound::Unbounded => 0,
+        }
+        .min(len);
+        let end = match range.end_bound() {
+            Bound::Included(e) => e.saturating_add(1),
+            Bound::Excluded(e) => *e,
+            Bound::Unbounded => len,
+        }
+        .min(len)
+        .max(start);
+        // skip the blocks before the range and only keep the blocks containing the range
+        let mut skip = key.offset as u64 + start;
+        let mut blocks = blocks.iter();
+        let mut wanted = Vec::new();
+        let mut size = 0;
+        for block in &mut blocks {
+            if skip < block.uncomp_size {
+                wanted.push(*block);
+                size = block.uncomp_size - skip;
+                break;
+            }
+            skip -= block.uncomp_size;
+        }
+        for block in blocks {
+            if size >= end - start {
+                break;
+            }
+            wanted.push(*block);
+            size += block.uncomp_size;
+        }
+        RecordRange {
+            blocks: wanted.into_iter(),
+            skip,
+            remain: end - start,
+        }
+    }
+
+    // Return the next block to read, and the range of its uncompressed content belongs to the record.
+    fn next_block(&mut self) -> Option<(MDictRecordBlockIndex, usize, usize)> {
+        if self.remain == 0 {
+            return None;
+        }
+        let block = self.blocks.next()?;
+        let start = self.skip;
+        let end = (start + self.remain).min(block.uncomp_size);
+        self.skip = 0;
+        self.remain -= end - start;
+        Some((block, start as usize, end as usize))
+    }
+}
+
+// read and uncompress a record block
+fn read_block<R: Read + Seek>(reader: &mut R, block: &MDictRecordBlockIndex) -> io::Result<Bytes> {
+    reader.seek(io::SeekFrom::Start(block.offset))?;
+    let compressed = read_len(reader, block.comp_size as usize)?;
+    let uncompressed = uncompress(compressed.into())?;
+    check_eq(
+        uncompressed.len() as u64,
+        block.uncomp_size,
+        "Size of uncompressed record block",
+    )?;
+    Ok(uncompressed)
+}
+
+#[cfg(not(feature = "async"))]
+/// A reader of a record, which uncompresses the record blocks one by one.
+///
+/// This is created by [`lookup_reader`].
+pub struct MDictRecordReader<R> {
+    reader: R,
+    range: RecordRange,
+    buf: Bytes,
+}
+
+#[cfg(not(feature = "async"))]
+impl<R: Read + Seek> Read for MDictRecordReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            match self.range.next_block() {
+                Some((block, start, end)) => {
+                    self.buf = read_block(&mut self.reader, &block)?.slice(start..end);
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.buf.len());
+        buf[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.advance(len);
+        Ok(len)
+    }
+}
+
+#[cfg(not(feature = "async"))]
+/// Read a range of the record of the given record index.
+///
+/// **This reader should contain valid Mdict file.**
+///
+/// Unlike [`lookup`], this function returns a reader over the record and only one record block is
+/// kept in memory at a time, and the record can span multiple record blocks.
+/// This is useful for large records such as audios and videos in mdd file.
+///
+/// The `blocks` should start with the `MDictRecordBlockIndex` by the index of `key.block` and contain
+/// the following blocks of it, the `range` is the range of bytes in the record, which will be clamped
+/// to the length of this record.
+///
+/// This is the blocking version of this function. To use asynchronous version, select the "async" crate feature
+pub fn lookup_reader<R, B>(
+    reader: R,
+    key: &MDictRecordIndex,
+    blocks: &[MDictRecordBlockIndex],
+    range: B,
+) -> MDictRecordReader<R>
+where
+    R: Read + Seek,
+    B: RangeBounds<u64>,
+{
+    MDictRecordReader {
+        reader,
+        range: RecordRange::new(key, blocks, range),
+        buf: Bytes::new(),
+    }
+}
+
+#[cfg(feature = "async")]
+// read and uncompress a record block
+async fn read_block_async<AR: AsyncRead + AsyncSeek + Unpin>(
+    reader: &mut AR,
+    block: &MDictRecordBlockIndex,
+) -> io::Result<Bytes> {
+    reader.seek(io::SeekFrom::Start(block.offset)).await?;
+    let compressed = read_len_async(reader, block.comp_size as usize).await?;
+    let uncompressed = uncompress(compressed.into())?;
+    check_eq(
+        uncompressed.len() as u64,
+        block.uncomp_size,
+        "Size of uncompressed record block",
+    )?;
+    Ok(uncompressed)
+}
+
+#[cfg(feature = "async")]
+/// Read a range of the record of the given record index as a stream of chunks.
+///
+/// **This reader should contain valid Mdict file.**
+///
+/// Each item of the stream is the part of the record in one record block, see [`lookup_reader`].
+///
+/// This function is only available with the "async" crate feature.
+pub fn lookup_stream<AR, B>(
+    reader: AR,
+    key: &MDictRecordIndex,
+    blocks: &[MDictRecordBlockIndex],
+    range: B,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    AR: AsyncRead + AsyncSeek + Unpin,
+    B: RangeBounds<u64>,
+{
+    let range = RecordRange::new(key, blocks, range);
+    futures_util::stream::try_unfold((reader, range), |(mut reader, mut range)| async move {
+        match range.next_block() {
+            Some((block, start, end)) => {
+                let data = read_block_async(&mut reader, &block).await?;
+                Ok(Some((data.slice(start..end), (reader, range))))
+            }
+            None => Ok(None),
+        }
+    })
+}
+
+#[cfg(feature = "async")]
+/// Read a range of the record of the given record index.
+///
+/// **This reader should contain valid Mdict file.**
+///
+/// Unlike [`lookup`], this function returns a reader over the record and only one record block is
+/// kept in memory at a time, and the record can span multiple record blocks.
+/// This is useful for large records such as audios and videos in mdd file.
+///
+/// The `blocks` should start with the `MDictRecordBlockIndex` by the index of `key.block` and contain
+/// the following blocks of it, the `range` is the range of bytes in the record, which will be clamped
+/// to the length of this record.
+///
+/// This is the asynchronous version of this function. To use blocking version, unselect the "async" crate feature
+pub fn lookup_reader<AR, B>(
+    reader: AR,
+    key: &MDictRecordIndex,
+    blocks: &[MDictRecordBlockIndex],
+    range: B,
+) -> impl AsyncBufRead
+where
+    AR: AsyncRead + AsyncSeek + Unpin,
+    B: RangeBounds<u64>,
+{
+    Box::pin(lookup_stream(reader, key, blocks, range)).into_async_read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(offsets: &[u64]) -> Vec<(String, u64)> {
+        offsets.iter().map(|o| (format!("k{}", o), *o)).collect()
+    }
+
+    fn ends(resolved: Vec<(String, u64, u64)>) -> Vec<(u64, u64)> {
+        resolved
+            .into_iter()
+            .map(|(_, start, end)| (start, end))
+            .collect()
+    }
+
+    #[test]
+    fn locate_records_across_blocks() {
+        let block = |uncomp_size| MDictRecordBlockIndex {
+            offset: 0,
+            comp_size: 0,
+            uncomp_size,
+        };
+        let locator = RecordLocator::new(&[block(10), block(10)]);
+        let locate = |start, end| {
+            let index = locator.locate(start, end).unwrap();
+            (index.block, index.offset, index.len)
+        };
+        assert_eq!(locate(12, 20), (1, 2, 8));
+        assert_eq!(locate(0, 5), (0, 0, 5));
+        // spans two blocks
+        assert_eq!(locate(5, 12), (0, 5, 7));
+        assert_eq!(locate(10, 10), (1, 0, 0));
+        assert!(locator.locate(12, 21).is_err());
+        assert!(locator.locate(12, 5).is_err());
+    }
+
+    #[test]
+    fn pending_records_sorted_by_offset() {
+        let mut pending = PendingRecords::default();
+        assert!(pending.push_block(entries(&[0, 5, 5])).is_empty());
+        // the last keywords of a block end at the first record of the next block
+        assert_eq!(
+            ends(pending.push_block(entries(&[9, 12]))),
+            vec![(0, 5), (5, 9), (5, 9)]
+        );
+        assert_eq!(ends(pending.finish(20)), vec![(9, 12), (12, 20)]);
+    }
+
+    #[test]
+    fn pending_records_not_sorted_by_offset() {
+        let mut pending = PendingRecords::default();
+        // the records of keywords in the order of the file start at 12, 0, 5 and 0
+        assert!(pending.push_block(entries(&[12, 0])).is_empty());
+        // the record at 12 is the last one, so the keywords after it are kept in order
+        assert!(pending.push_block(entries(&[5, 0])).is_empty());
+        assert_eq!(
+            ends(pending.finish(20)),
+            vec![(12, 20), (0, 5), (5, 12), (0, 5)]
+        );
+
+        let mut pending = PendingRecords::default();
+        pending.push_block(entries(&[0, 9]));
+        // nearby records in the next block are found
+        assert_eq!(
+            ends(pending.push_block(entries(&[4, 12]))),
+            vec![(0, 4), (9, 12)]
+        );
+        assert_eq!(ends(pending.finish(15)), vec![(4, 9), (12, 15)]);
+    }
+
+    #[test]
+    fn cancelled_is_not_interrupted() {
+        let cancel = MDictCancelToken::new();
+        assert!(cancel.clone().check().is_ok());
+        cancel.clone().cancel();
+        let e = cancel.check().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(MDictCancelled::is(&e));
+        assert!(!MDictCancelled::is(&Error::new(ErrorKind::Other, "other")));
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// The phase of building an index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MDictBuildPhase {
+    /// The header and the index of keywords blocks are decoded
+    Header,
+    /// The index of records blocks is decoded
+    RecordIndex,
+    /// The keywords blocks are decoded
+    KeyBlocks,
+    /// The keywords are inserted into a database, this is reported by indexers
+    DatabaseInsert,
+}
+
+/// The progress of building an index
+///
+/// `blocks_*` and `entries_*` count the keywords blocks and keywords of the file,
+/// `bytes_*` count the bytes of the file processed in the current phase.
+#[derive(Clone, Debug)]
+pub struct MDictProgress {
+    pub phase: MDictBuildPhase,
+    pub blocks_done: u64,
+    pub blocks_total: u64,
+    pub entries_done: u64,
+    pub entries_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+}
+
+impl MDictProgress {
+    pub(crate) fn new(phase: MDictBuildPhase) -> MDictProgress {
+        MDictProgress {
+            phase,
+            blocks_done: 0,
+            blocks_total: 0,
+            entries_done: 0,
+            entries_total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+        }
+    }
+}
+
+/// Observer of the progress of building an index
+///
+/// The observer may be called from another thread by indexers, so it should be `Send` and `Sync`.
+/// Any closure `Fn(&MDictProgress)` can be used as an observer.
+pub trait MDictProgressObserver: Send + Sync {
+    fn on_progress(&self, progress: &MDictProgress);
+}
+
+impl<F> MDictProgressObserver for F
+where
+    F: Fn(&MDictProgress) + Send + Sync,
+{
+    fn on_progress(&self, progress: &MDictProgress) {
+        self(progress)
+    }
+}
+
+/// A token to cancel building an index
+///
+/// The token can be cloned and shared between threads, all clones are cancelled together.
+/// Building is stopped with [`MDictCancelled`] at the next keywords block after
+/// [`cancel`](MDictCancelToken::cancel) is called.
+#[derive(Clone, Debug, Default)]
+pub struct MDictCancelToken(Arc<AtomicBool>);
+
+impl MDictCancelToken {
+    pub fn new() -> MDictCancelToken {
+        MDictCancelToken::default()
+    }
+
+    /// Request to cancel building.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns [`MDictCancelled`] if this token is cancelled.
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            Err(io::Error::new(io::ErrorKind::Other, MDictCancelled))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error of building an index which is cancelled by [`MDictCancelToken`]
+///
+/// It's wrapped in [`io::Error`] with [`io::ErrorKind::Other`], since
+/// [`io::ErrorKind::Interrupted`] means the operation can be retried.
+/// Use [`MDictCancelled::is`] to tell it from other errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MDictCancelled;
+
+impl MDictCancelled {
+    /// Whether `error` is caused by cancelling.
+    pub fn is(error: &io::Error) -> bool {
+        error
+            .get_ref()
+            .map_or(false, |e| e.downcast_ref::<MDictCancelled>().is_some())
+    }
+}
+
+impl fmt::Display for MDictCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Index building is cancelled")
+    }
+}
+
+impl Error for MDictCancelled {}
@@ -3,10 +3,15 @@
 //! This simple program load mdx and mdd files and then print all keywords in
 //! mdx file to stdout.
 //!
+//! If the path is a directory, all dictionaries in it are found and their mdx files are
+//! printed, which can be used as the config file of `mdict_server`. Broken dictionaries
+//! are reported to stderr.
+//!
 //! # Usage
 //!
 //! ```shell
 //! mdict_dump [PATH TO MDX FILE]
+//! mdict_dump [PATH TO DIRECTORY] > config
 //! ```
 //!
 //! # panic
@@ -21,6 +26,14 @@ fn main() {
         env::set_var("RUST_LOG", "info");
     }
     pretty_env_logger::init();
+    if Path::new(&file).is_dir() {
+        let report = mdict_scan(&file).unwrap();
+        for e in &report.errors {
+            eprintln!("{}: {}", e.path.to_string_lossy(), e.error);
+        }
+        print!("{}", report.listing());
+        return;
+    }
     let mdx_file = Path::new(&file).canonicalize().unwrap();
     let index = MDictMemIndex::new(mdx_file).unwrap();
     for i in index.keyword_iter() {
@@ -0,0 +1,97 @@
+//! Benchmarks of `MDictMemIndex` with a large dictionary, which is not bundled:
+//!
+//! ```shell
+//! MDICT_BENCH=path/to/dict.mdx cargo bench -p mdict_index --bench mem_index
+//! ```
+//!
+//! Each query is run with keywords sampled from the dictionary, the average time of a query is
+//! printed.
+
+#[cfg(not(feature = "async"))]
+use mdict_index::*;
+#[cfg(not(feature = "async"))]
+use std::{
+    env, io,
+    time::{Duration, Instant},
+};
+
+// Keywords sampled from the dictionary
+#[cfg(not(feature = "async"))]
+const SAMPLES: usize = 100;
+
+// Run `query` with every key until a second is passed, print the average time.
+#[cfg(not(feature = "async"))]
+fn bench<T, F>(name: &str, keys: &[String], mut query: F)
+where
+    F: FnMut(&str) -> io::Result<Vec<T>>,
+{
+    let start = Instant::now();
+    let mut queries = 0;
+    let mut results = 0;
+    while queries == 0 || start.elapsed() < Duration::from_secs(1) {
+        for key in keys {
+            results += query(key).unwrap().len();
+            queries += 1;
+        }
+    }
+    println!(
+        "{:<28} {:>12.3?} {:>8} results",
+        name,
+        start.elapsed() / queries as u32,
+        results / queries
+    );
+}
+
+#[cfg(not(feature = "async"))]
+fn main() {
+    let mdx_file = match env::var_os("MDICT_BENCH") {
+        Some(mdx_file) => mdx_file,
+        None => {
+            eprintln!("Set MDICT_BENCH to the mdx file of a large dictionary");
+            return;
+        }
+    };
+    let start = Instant::now();
+    let index = MDictMemIndex::new(&mdx_file).unwrap();
+    let keywords: Vec<String> = index.keyword_iter().collect();
+    println!(
+        "{:<28} {:>12.3?} {:>8} keywords",
+        "build",
+        start.elapsed(),
+        keywords.len()
+    );
+    let step = (keywords.len() / SAMPLES).max(1);
+    let keys: Vec<String> = keywords.into_iter().step_by(step).collect();
+    let prefixes: Vec<String> = keys.iter().map(|k| k.chars().take(2).collect()).collect();
+
+    bench("lookup_word", &keys, |key| index.lookup_word(key));
+    bench("keywords_after 20", &keys, |key| {
+        index.keywords_after(key, 20)
+    });
+    bench("keywords_before 20", &keys, |key| {
+        index.keywords_before(key, 20)
+    });
+    bench("suggest 20", &prefixes, |prefix| index.suggest(prefix, 20));
+    for &(metric, name) in &[
+        (MDictDistance::Levenshtein, "levenshtein"),
+        (MDictDistance::Damerau, "damerau"),
+    ] {
+        for max_distance in 1..=2 {
+            bench(
+                &format!("fuzzy_search {} {}", name, max_distance),
+                &keys,
+                |key| index.fuzzy_search(key, max_distance, metric, 20),
+            );
+        }
+    }
+    bench("search_pattern prefix*", &prefixes, |prefix| {
+        index.search_pattern(&MDictPattern::Glob(format!("{}*", prefix)), None, 20)
+    });
+    bench("search_pattern *suffix", &prefixes, |suffix| {
+        index.search_pattern(&MDictPattern::Glob(format!("*{}", suffix)), None, 20)
+    });
+}
+
+// Queries of `MDictAsyncLookup` are not benchmarked.
+#[cfg(feature = "async")]
+fn main() {}
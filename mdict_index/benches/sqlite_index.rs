@@ -0,0 +1,67 @@
+//! Benchmarks of building `MDictSqliteIndex` with a large dictionary, which is not bundled:
+//!
+//! ```shell
+//! MDICT_BENCH=path/to/dict.mdx cargo bench -p mdict_index --features sqlite --bench sqlite_index
+//! ```
+//!
+//! The index is rebuilt in a temporary directory with each set of options, the average time of
+//! a build is printed.
+
+use mdict_index::*;
+use std::{
+    env, fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// Build the index with `options` until 10 seconds are passed, print the average time.
+fn bench(name: &str, mdx_file: &Path, options: MDictBuildOptions) {
+    let options = options.rebuild(true);
+    let start = Instant::now();
+    let mut builds = 0;
+    while builds == 0 || start.elapsed() < Duration::from_secs(10) {
+        futures_executor::block_on(async {
+            let index = MDictSqliteIndex::with_options(mdx_file, &options)
+                .await
+                .unwrap();
+            index.close().await;
+        });
+        builds += 1;
+    }
+    println!(
+        "{:<28} {:>12.3?} {:>8} builds",
+        name,
+        start.elapsed() / builds,
+        builds
+    );
+}
+
+fn main() {
+    let mdx_file = match env::var_os("MDICT_BENCH") {
+        Some(mdx_file) => mdx_file,
+        None => {
+            eprintln!("Set MDICT_BENCH to the mdx file of a large dictionary");
+            return;
+        }
+    };
+    let mdx_file = Path::new(&mdx_file);
+    let cache_dir = env::temp_dir().join("mdict_bench");
+    let options = MDictBuildOptions::new().cache_dir(&cache_dir);
+
+    bench("build", mdx_file, options.clone());
+    bench("build full_text", mdx_file, options.clone().full_text(true));
+    bench(
+        "build chinese",
+        mdx_file,
+        options
+            .clone()
+            .chinese(Arc::new(MDictChineseConverter::new())),
+    );
+    bench(
+        "build phonetic",
+        mdx_file,
+        options.phonetic(Arc::new(MDictDoubleMetaphone::new())),
+    );
+    let _ = fs::remove_dir_all(&cache_dir);
+}
@@ -0,0 +1,5 @@
+CREATE VIRTUAL TABLE mdx_fts USING fts5 (
+    keyword unindexed,
+    text,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
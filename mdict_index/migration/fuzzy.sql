@@ -0,0 +1,5 @@
+-- Distinct keywords with their length in chars for fuzzy search, keywords of similar length
+-- are scanned in the covering index if the key is too short for trigrams.
+INSERT INTO mdx_keyword (keyword, length)
+SELECT DISTINCT keyword, length(keyword) FROM mdx_index ORDER BY keyword;
+CREATE INDEX mdx_keyword_length ON mdx_keyword (length, keyword);
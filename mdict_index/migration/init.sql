@@ -5,24 +5,45 @@ CREATE TABLE meta (
 CREATE TABLE mdx_block (
     block_index integer primary key not null,
     block_offset bigint not null,
-    block_size bigint not null
+    block_size bigint not null,
+    block_uncomp_size bigint not null
 );
 CREATE TABLE mdx_index (
-    keyword text primary key not null,
+    keyword text not null,
+    entry integer not null,
     block_index integer not null,
     record_offset integer not null,
     record_size integer not null,
     foreign key (block_index) references mdx_block(block_index)
 );
+CREATE TABLE mdx_keyword (
+    id integer primary key not null,
+    keyword text not null,
+    length integer not null
+);
+CREATE TABLE mdx_normalized (
+    normalized text not null,
+    keyword text not null
+);
+CREATE TABLE mdx_pinyin (
+    pinyin text not null,
+    tones text not null,
+    keyword text not null
+);
+CREATE TABLE mdx_phonetic (
+    code text not null,
+    keyword text not null
+);
 CREATE TABLE mdd_block (
     file_index integer,
     block_index integer,
     block_offset bigint not null,
     block_size bigint not null,
+    block_uncomp_size bigint not null,
     primary key (file_index, block_index)
 );
 CREATE TABLE mdd_index (
-    keyword text primary key not null,
+    keyword text not null,
     file_index integer not null,
     block_index integer not null,
     record_offset integer not null,
@@ -0,0 +1,10 @@
+-- Keywords containing a piece of the key in fuzzy search are found by trigrams,
+-- the trigram tokenizer needs sqlite 3.34 or later.
+CREATE VIRTUAL TABLE mdx_trigram USING fts5 (
+    keyword,
+    tokenize = 'trigram case_sensitive 1',
+    detail = 'none',
+    content = 'mdx_keyword',
+    content_rowid = 'id'
+);
+INSERT INTO mdx_trigram (rowid, keyword) SELECT id, keyword FROM mdx_keyword;
@@ -0,0 +1,192 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+const ST_CHARACTERS: &str = include_str!("../data/STCharacters.txt");
+const ST_PHRASES: &str = include_str!("../data/STPhrases.txt");
+
+/// Script of Chinese characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MDictChineseScript {
+    Simplified,
+    Traditional,
+}
+
+// A conversion table from one script to another
+#[derive(Default)]
+struct Table {
+    chars: HashMap<char, char>,
+    phrases: HashMap<String, String>,
+    // the max number of chars of phrases
+    max_phrase: usize,
+}
+
+impl Table {
+    fn insert_phrase(&mut self, from: &str, to: &str) {
+        self.max_phrase = self.max_phrase.max(from.chars().count());
+        self.phrases
+            .entry(from.to_owned())
+            .or_insert_with(|| to.to_owned());
+    }
+
+    // Convert by the longest phrase at each position, or by chars.
+    fn convert(&self, text: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut converted = String::with_capacity(text.len());
+        let mut i = 0;
+        'outer: while i < chars.len() {
+            for len in (2..=self.max_phrase.min(chars.len() - i)).rev() {
+                let phrase: String = chars[i..i + len].iter().collect();
+                if let Some(to) = self.phrases.get(&phrase) {
+                    converted.push_str(to);
+                    i += len;
+                    continue 'outer;
+                }
+            }
+            converted.push(*self.chars.get(&chars[i]).unwrap_or(&chars[i]));
+            i += 1;
+        }
+        converted
+    }
+}
+
+/// Converter between Simplified and Traditional Chinese by tables in the format of OpenCC.
+///
+/// The bundled tables only contain common characters and phrases of OpenCC, which are under the
+/// Apache License 2.0, the complete tables can be loaded by
+/// [`from_opencc`](MDictChineseConverter::from_opencc).
+pub struct MDictChineseConverter {
+    to_traditional: Table,
+    to_simplified: Table,
+}
+
+impl MDictChineseConverter {
+    /// The converter with the bundled tables.
+    pub fn new() -> MDictChineseConverter {
+        MDictChineseConverter::from_tables(ST_CHARACTERS, ST_PHRASES)
+    }
+
+    /// Load `STCharacters.txt` and `STPhrases.txt` of OpenCC, the conversion from
+    /// Traditional to Simplified is the reverse of them.
+    pub fn from_opencc<P: AsRef<Path>>(
+        characters: P,
+        phrases: P,
+    ) -> io::Result<MDictChineseConverter> {
+        let characters = fs::read_to_string(characters)?;
+        let phrases = fs::read_to_string(phrases)?;
+        Ok(MDictChineseConverter::from_tables(&characters, &phrases))
+    }
+
+    // Each line is a Simplified char or phrase, a tab, and Traditional ones separated by space,
+    // the first one is used when converting to Traditional.
+    fn from_tables(characters: &str, phrases: &str) -> MDictChineseConverter {
+        let mut to_traditional = Table::default();
+        let mut to_simplified = Table::default();
+        for (from, to) in table_entries(characters) {
+            let mut from_chars = from.chars();
+            let s = match (from_chars.next(), from_chars.next()) {
+                (Some(s), None) => s,
+                _ => continue,
+            };
+            for (i, t) in to.iter().enumerate() {
+                let mut to_chars = t.chars();
+                if let (Some(t), None) = (to_chars.next(), to_chars.next()) {
+                    if i == 0 {
+                        to_traditional.chars.insert(s, t);
+                    }
+                    to_simplified.chars.entry(t).or_insert(s);
+                }
+            }
+        }
+        for (from, to) in table_entries(phrases) {
+            if let Some(first) = to.first() {
+                to_traditional.insert_phrase(from, first);
+            }
+            for t in to {
+                to_simplified.insert_phrase(t, from);
+            }
+        }
+        MDictChineseConverter {
+            to_traditional,
+            to_simplified,
+        }
+    }
+
+    pub fn convert(&self, text: &str, script: MDictChineseScript) -> String {
+        match script {
+            MDictChineseScript::Simplified => self.to_simplified.convert(text),
+            MDictChineseScript::Traditional => self.to_traditional.convert(text),
+        }
+    }
+}
+
+impl Default for MDictChineseConverter {
+    fn default() -> MDictChineseConverter {
+        MDictChineseConverter::new()
+    }
+}
+
+// Entries of a table, lines starting with `#` like the license of the bundled tables are skipped.
+fn table_entries(table: &str) -> impl Iterator<Item = (&str, Vec<&str>)> {
+    table.lines().filter_map(|line| {
+        if line.starts_with('#') {
+            return None;
+        }
+        let mut parts = line.split('\t');
+        let from = parts.next()?.trim();
+        let to: Vec<&str> = parts.next()?.split_whitespace().collect();
+        if from.is_empty() || to.is_empty() {
+            None
+        } else {
+            Some((from, to))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use MDictChineseScript::*;
+
+    fn converter() -> MDictChineseConverter {
+        MDictChineseConverter::from_tables(
+            "# comment\n发\t發 髮\n头\t頭\n干\t幹 乾 干\n净\t淨\n后\t後 后\n",
+            "头发\t頭髮\n干净\t乾淨\n头发干\t頭髮乾\n",
+        )
+    }
+
+    #[test]
+    fn convert_longest_phrases() {
+        let cc = converter();
+        // "头发干" is longer than "头发"
+        assert_eq!(cc.convert("头发干净", Traditional), "頭髮乾淨");
+        assert_eq!(cc.convert("头发", Traditional), "頭髮");
+        assert_eq!(cc.convert("干净", Traditional), "乾淨");
+    }
+
+    #[test]
+    fn convert_single_chars() {
+        let cc = converter();
+        // the first of several forms is used out of phrases
+        assert_eq!(cc.convert("发干", Traditional), "發幹");
+        assert_eq!(cc.convert("头后", Traditional), "頭後");
+        // chars not in the table are kept
+        assert_eq!(cc.convert("a头b", Traditional), "a頭b");
+        assert_eq!(cc.convert("", Traditional), "");
+    }
+
+    #[test]
+    fn convert_to_simplified() {
+        let cc = converter();
+        // all forms of a char are converted back
+        assert_eq!(cc.convert("發髮", Simplified), "发发");
+        assert_eq!(cc.convert("幹乾干", Simplified), "干干干");
+        assert_eq!(cc.convert("頭髮乾淨", Simplified), "头发干净");
+        assert_eq!(cc.convert("后後", Simplified), "后后");
+    }
+
+    #[test]
+    fn bundled_tables() {
+        let cc = MDictChineseConverter::new();
+        assert_eq!(cc.convert("头发", Traditional), "頭髮");
+        assert_eq!(cc.convert("臺灣", Simplified), "台湾");
+    }
+}
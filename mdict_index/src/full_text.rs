@@ -0,0 +1,89 @@
+use crate::mdict_link_target;
+use regex::Regex;
+
+/// A result of full-text search, see [`MDictSqliteIndex::full_text_search`](crate::MDictSqliteIndex::full_text_search).
+#[derive(Clone, Debug)]
+pub struct MDictFullTextMatch {
+    pub keyword: String,
+    /// HTML of a short part of the record, the matched words are in `<b>`
+    pub snippet: String,
+    /// The bm25 score, a better match has a lower score
+    pub rank: f64,
+}
+
+// Marks of matched words in the snippet returned by sqlite, replaced by `<b>` after escaping
+pub(crate) const SNIPPET_START: &str = "\u{2}";
+pub(crate) const SNIPPET_END: &str = "\u{3}";
+
+// Extract the text of records for full-text index
+pub(crate) struct TextExtractor {
+    invisible: Regex,
+    tag: Regex,
+    space: Regex,
+}
+
+impl TextExtractor {
+    pub(crate) fn new() -> TextExtractor {
+        TextExtractor {
+            invisible: Regex::new(r"(?is)<(script|style)\b.*?</(script|style)\s*>").unwrap(),
+            tag: Regex::new(r"(?s)<[^>]*>").unwrap(),
+            space: Regex::new(r"\s+").unwrap(),
+        }
+    }
+
+    // The text of a record with HTML tags stripped, `None` if it's a link to another keyword.
+    pub(crate) fn text(&self, record: &str) -> Option<String> {
+        if mdict_link_target(record).is_some() {
+            return None;
+        }
+        let text = self.invisible.replace_all(record, " ");
+        let text = self.tag.replace_all(&text, " ");
+        let text = html_escape::decode_html_entities(&text);
+        // records usually end with "\r\n\0"
+        let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        Some(self.space.replace_all(text, " ").into_owned())
+    }
+}
+
+// Escape the snippet returned by sqlite and mark the matched words by `<b>`.
+pub(crate) fn snippet_html(snippet: &str) -> String {
+    html_escape::encode_text(snippet)
+        .replace(SNIPPET_START, "<b>")
+        .replace(SNIPPET_END, "</b>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_text() {
+        let extractor = TextExtractor::new();
+        let text = |record: &str| extractor.text(record);
+        assert_eq!(
+            text("<b>apple</b>&nbsp;<i>a\r\nfruit</i>\r\n\0").as_deref(),
+            Some("apple a fruit")
+        );
+        // tags may span lines, scripts and styles are invisible
+        assert_eq!(
+            text("<style>b { color: red }</style><a\nhref='x'>link</a><SCRIPT>f('<b>')</script >")
+                .as_deref(),
+            Some("link")
+        );
+        // entities are decoded after tags are stripped
+        assert_eq!(
+            text("fish &amp; chips &lt;b&gt; &#x4e2d;&#25991;").as_deref(),
+            Some("fish & chips <b> 中文")
+        );
+        assert_eq!(text("@@@LINK=apple\r\n\0"), None);
+    }
+
+    #[test]
+    fn escape_snippet() {
+        let snippet = format!("a <b> & {}match{} end", SNIPPET_START, SNIPPET_END);
+        assert_eq!(snippet_html(&snippet), "a &lt;b&gt; &amp; <b>match</b> end");
+        // matched words are escaped too
+        let snippet = format!("{}<i>&{}", SNIPPET_START, SNIPPET_END);
+        assert_eq!(snippet_html(&snippet), "<b>&lt;i&gt;&amp;</b>");
+    }
+}
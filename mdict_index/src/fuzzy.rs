@@ -0,0 +1,257 @@
+/// Edit distance used by fuzzy lookup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MDictDistance {
+    /// Insertion, deletion and substitution of a char
+    Levenshtein,
+    /// Levenshtein distance and transposition of two adjacent chars,
+    /// a substring can't be edited more than once (optimal string alignment)
+    Damerau,
+}
+
+// Rows of the edit distance matrix between `key` and a candidate which is extended char by char,
+// so the rows of a common prefix of candidates are shared.
+pub(crate) struct EditDistance {
+    key: Vec<char>,
+    max: usize,
+    metric: MDictDistance,
+    // chars of the candidate
+    chars: Vec<char>,
+    // `rows[i]` is the distances between the first `i` chars of the candidate and prefixes of `key`
+    rows: Vec<Vec<usize>>,
+}
+
+impl EditDistance {
+    pub(crate) fn new(key: &str, max: usize, metric: MDictDistance) -> EditDistance {
+        let key: Vec<char> = key.chars().collect();
+        let first = (0..=key.len()).collect();
+        EditDistance {
+            key,
+            max,
+            metric,
+            chars: Vec::new(),
+            rows: vec![first],
+        }
+    }
+
+    // Keep the first `len` chars of the candidate.
+    fn truncate(&mut self, len: usize) {
+        self.chars.truncate(len);
+        self.rows.truncate(len + 1);
+    }
+
+    fn push(&mut self, c: char) {
+        let prev = &self.rows[self.rows.len() - 1];
+        let mut row = Vec::with_capacity(prev.len());
+        row.push(prev[0] + 1);
+        for j in 1..prev.len() {
+            let cost = if self.key[j - 1] == c { 0 } else { 1 };
+            let mut d = (prev[j] + 1).min(row[j - 1] + 1).min(prev[j - 1] + cost);
+            if self.metric == MDictDistance::Damerau && j > 1 {
+                if let Some(&last) = self.chars.last() {
+                    if self.key[j - 1] == last && self.key[j - 2] == c {
+                        let prev2 = &self.rows[self.rows.len() - 2];
+                        d = d.min(prev2[j - 2] + 1);
+                    }
+                }
+            }
+            row.push(d);
+        }
+        self.chars.push(c);
+        self.rows.push(row);
+    }
+
+    // Whether the candidate can be extended to a match.
+    fn can_match(&self) -> bool {
+        let min = |row: &Vec<usize>| row.iter().copied().min().unwrap_or(0);
+        let rows = &self.rows;
+        if min(&rows[rows.len() - 1]) <= self.max {
+            return true;
+        }
+        // a transposition may use the row before the last one
+        self.metric == MDictDistance::Damerau
+            && rows.len() > 1
+            && min(&rows[rows.len() - 2]) < self.max
+    }
+
+    fn distance(&self) -> usize {
+        self.rows[self.rows.len() - 1][self.key.len()]
+    }
+
+    /// The distance between `key` and `word`, `None` if it's greater than `max`.
+    pub(crate) fn matches(&mut self, word: &str) -> Option<usize> {
+        self.truncate(0);
+        for c in word.chars() {
+            self.push(c);
+            if !self.can_match() {
+                return None;
+            }
+        }
+        Some(self.distance()).filter(|d| *d <= self.max)
+    }
+
+    /// Whether a keyword starting with the bytes `prefix` can be within the distance, the last
+    /// char of `prefix` may be incomplete.
+    pub(crate) fn can_match_prefix(&mut self, prefix: &[u8]) -> bool {
+        let (chars, incomplete) = match std::str::from_utf8(prefix) {
+            Ok(chars) => (chars, &[][..]),
+            Err(e) => {
+                let (chars, incomplete) = prefix.split_at(e.valid_up_to());
+                (std::str::from_utf8(chars).unwrap(), incomplete)
+            }
+        };
+        let common = self
+            .chars
+            .iter()
+            .zip(chars.chars())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        self.truncate(common);
+        for c in chars.chars().skip(common) {
+            self.push(c);
+            if !self.can_match() {
+                return false;
+            }
+        }
+        if incomplete.is_empty() || !self.can_match() {
+            return self.can_match();
+        }
+        // The incomplete char may be a char of `key`, or any other char which has the same
+        // distance as a char not in `key`.
+        let mut buf = [0; 4];
+        if self
+            .key
+            .iter()
+            .any(|c| c.encode_utf8(&mut buf).as_bytes().starts_with(incomplete))
+        {
+            return true;
+        }
+        let other = (0xe000..)
+            .filter_map(char::from_u32)
+            .find(|c| !self.key.contains(c))
+            .unwrap();
+        self.push(other);
+        self.can_match()
+    }
+
+    /// Find keywords within the distance in sorted `keywords`.
+    ///
+    /// This works like a Levenshtein automaton walking a trie: the rows of the common prefix
+    /// of adjacent keywords are reused, and all keywords with a prefix which can't match are skipped.
+    pub(crate) fn search<I>(&mut self, keywords: I) -> Vec<(String, usize)>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut result = Vec::new();
+        // the prefix which can't match
+        let mut pruned: Option<String> = None;
+        for word in keywords {
+            if pruned
+                .as_ref()
+                .map_or(false, |p| word.starts_with(p.as_str()))
+            {
+                continue;
+            }
+            pruned = None;
+            let common = self
+                .chars
+                .iter()
+                .zip(word.chars())
+                .take_while(|(a, b)| **a == *b)
+                .count();
+            self.truncate(common);
+            for c in word.chars().skip(common) {
+                self.push(c);
+                if !self.can_match() {
+                    pruned = Some(self.chars.iter().collect());
+                    break;
+                }
+            }
+            if pruned.is_none() && self.distance() <= self.max {
+                let distance = self.distance();
+                result.push((word, distance));
+            }
+        }
+        result
+    }
+}
+
+// Sort by the distance then the keyword, and keep the first `limit` keywords.
+pub(crate) fn rank(mut result: Vec<(String, usize)>, limit: usize) -> Vec<(String, usize)> {
+    result.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    result.truncate(limit);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: &str, b: &str, metric: MDictDistance) -> Option<usize> {
+        EditDistance::new(a, 3, metric).matches(b)
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(
+            distance("kitten", "sitting", MDictDistance::Levenshtein),
+            Some(3)
+        );
+        assert_eq!(
+            distance("kitten", "kitten", MDictDistance::Levenshtein),
+            Some(0)
+        );
+        assert_eq!(distance("", "abc", MDictDistance::Levenshtein), Some(3));
+        assert_eq!(distance("abcd", "", MDictDistance::Levenshtein), None);
+        assert_eq!(distance("ab", "ba", MDictDistance::Levenshtein), Some(2));
+        assert_eq!(distance("ab", "ba", MDictDistance::Damerau), Some(1));
+        assert_eq!(
+            distance("bakotion", "abkotoin", MDictDistance::Damerau),
+            Some(2)
+        );
+        // a transposed substring isn't edited again
+        assert_eq!(distance("ca", "abc", MDictDistance::Damerau), Some(3));
+    }
+
+    #[test]
+    fn match_prefixes() {
+        let mut distance = EditDistance::new("bac", 1, MDictDistance::Levenshtein);
+        assert!(distance.can_match_prefix(b""));
+        assert!(distance.can_match_prefix(b"xa"));
+        assert!(!distance.can_match_prefix(b"xx"));
+        // the rows of the common prefix are kept
+        assert!(distance.can_match_prefix(b"xac"));
+        assert_eq!(distance.distance(), 1);
+        assert!(!distance.can_match_prefix(b"xacx"));
+        // an incomplete char is matched by chars starting with it
+        let mut distance = EditDistance::new("b中c", 1, MDictDistance::Levenshtein);
+        assert!(distance.can_match_prefix(&"x中".as_bytes()[..2]));
+        assert!(!distance.can_match_prefix(&"x文".as_bytes()[..2]));
+        assert!(distance.can_match_prefix(&"b文".as_bytes()[..2]));
+        assert_eq!(
+            distance.search(vec!["x中c".to_owned(), "x文c".to_owned()]),
+            vec![("x中c".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn search_sorted_keywords() {
+        let keywords = ["ab", "abc", "abcd", "b", "ba", "bac", "bad", "cab"];
+        let keywords = keywords.iter().map(|s| s.to_string());
+        let found = EditDistance::new("bac", 1, MDictDistance::Damerau).search(keywords.clone());
+        let expected: Vec<(String, usize)> = keywords
+            .filter_map(|word| {
+                let d = EditDistance::new("bac", 1, MDictDistance::Damerau).matches(&word)?;
+                Some((word, d))
+            })
+            .collect();
+        assert_eq!(found, expected);
+        assert_eq!(
+            rank(found, 3),
+            vec![
+                ("bac".to_owned(), 0),
+                ("abc".to_owned(), 1),
+                ("ba".to_owned(), 1)
+            ]
+        );
+    }
+}
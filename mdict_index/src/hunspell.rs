@@ -0,0 +1,533 @@
+use crate::file_stem;
+use encoding_rs::Encoding;
+use log::*;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// A flag of an affix class, long and numeric flags are also stored as a number
+type Flag = u64;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FlagMode {
+    // one char, the default
+    Char,
+    // two chars, `FLAG long`
+    Long,
+    // decimal numbers separated by comma, `FLAG num`
+    Num,
+}
+
+// A rule of `PFX` or `SFX`: `strip` is removed from the stem and `add` is added
+struct Affix {
+    flag: Flag,
+    cross_product: bool,
+    strip: String,
+    add: String,
+    // matches the stem in the dictionary
+    condition: Option<Regex>,
+}
+
+impl Affix {
+    fn matches(&self, stem: &str) -> bool {
+        self.condition.as_ref().map_or(true, |c| c.is_match(stem))
+    }
+}
+
+/// A Hunspell dictionary, which maps inflected words to their stems by the affix rules.
+///
+/// Only the stems of one prefix, one suffix or both are found, compounds and
+/// twofold suffixes are not supported.
+pub struct MDictHunspell {
+    name: String,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    // words of the `.dic` file with their flags
+    words: HashMap<String, Vec<Flag>>,
+}
+
+impl MDictHunspell {
+    /// Load a dictionary from a `.aff` file and a `.dic` file.
+    pub fn new<P: AsRef<Path>>(aff: P, dic: P) -> io::Result<MDictHunspell> {
+        let aff = aff.as_ref();
+        let aff_bytes = fs::read(aff)?;
+        let encoding = aff_encoding(&aff_bytes)?;
+        let (aff_text, _, _) = encoding.decode(&aff_bytes);
+        let dic_bytes = fs::read(dic.as_ref())?;
+        let (dic_text, _, _) = encoding.decode(&dic_bytes);
+        MDictHunspell::parse(file_stem(aff), &aff_text, &dic_text)
+    }
+
+    fn parse(name: String, aff_text: &str, dic_text: &str) -> io::Result<MDictHunspell> {
+        let mut parser = AffParser::default();
+        for line in aff_text.lines() {
+            parser.parse_line(line)?;
+        }
+        let mut words: HashMap<String, Vec<Flag>> = HashMap::new();
+        for (i, line) in dic_text.lines().enumerate() {
+            // the first line is the number of words
+            if i == 0 && line.trim().parse::<usize>().is_ok() {
+                continue;
+            }
+            // morphological fields are after a tab
+            let entry = match line
+                .split('\t')
+                .next()
+                .and_then(|e| e.split_whitespace().next())
+            {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let (word, flags) = split_flags(entry);
+            if word.is_empty() {
+                continue;
+            }
+            let flags = parser.parse_flags(flags);
+            // homonyms are merged
+            words.entry(word).or_default().extend(flags);
+        }
+        info!(
+            "Load hunspell {}: {} words, {} prefixes, {} suffixes",
+            name,
+            words.len(),
+            parser.prefixes.len(),
+            parser.suffixes.len()
+        );
+        Ok(MDictHunspell {
+            name,
+            prefixes: parser.prefixes,
+            suffixes: parser.suffixes,
+            words,
+        })
+    }
+
+    /// Load all dictionaries in `dir`, each `{name}.aff` with `{name}.dic` is a dictionary.
+    pub fn load_dir<P: AsRef<Path>>(dir: P) -> io::Result<Vec<MDictHunspell>> {
+        let mut affs: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "aff"))
+            .filter(|path| path.with_extension("dic").is_file())
+            .collect();
+        affs.sort();
+        affs.iter()
+            .map(|aff| MDictHunspell::new(aff.clone(), aff.with_extension("dic")))
+            .collect()
+    }
+
+    /// The name of the dictionary, which is the file name like `de_DE`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Stems of `word` in the dictionary, the word itself is included if it's a stem.
+    ///
+    /// If nothing is found, the word in lowercase is tried, since a word may be capitalized
+    /// at the beginning of a sentence.
+    pub fn stems(&self, word: &str) -> Vec<String> {
+        let mut stems = self.find_stems(word);
+        let lower = word.to_lowercase();
+        if stems.is_empty() && lower != word {
+            stems = self.find_stems(&lower);
+        }
+        stems
+    }
+
+    fn find_stems(&self, word: &str) -> Vec<String> {
+        let mut stems = Vec::new();
+        let mut push = |stem: String| {
+            if !stems.contains(&stem) {
+                stems.push(stem);
+            }
+        };
+        if self.words.contains_key(word) {
+            push(word.to_owned());
+        }
+        for sfx in self.suffixes.iter() {
+            let stem = match strip_suffix(word, sfx) {
+                Some(stem) => stem,
+                None => continue,
+            };
+            if self.has_flags(&stem, &[sfx.flag]) {
+                push(stem.clone());
+            }
+            if !sfx.cross_product {
+                continue;
+            }
+            // the word may also have a prefix
+            for pfx in self.prefixes.iter().filter(|p| p.cross_product) {
+                if let Some(root) = strip_prefix(&stem, pfx) {
+                    if self.has_flags(&root, &[pfx.flag, sfx.flag]) {
+                        push(root);
+                    }
+                }
+            }
+        }
+        for pfx in self.prefixes.iter() {
+            if let Some(stem) = strip_prefix(word, pfx) {
+                if self.has_flags(&stem, &[pfx.flag]) {
+                    push(stem);
+                }
+            }
+        }
+        stems
+    }
+
+    fn has_flags(&self, stem: &str, flags: &[Flag]) -> bool {
+        self.words
+            .get(stem)
+            .map_or(false, |f| flags.iter().all(|flag| f.contains(flag)))
+    }
+}
+
+// The stem of `word` without the suffix, if the suffix and the condition match.
+fn strip_suffix(word: &str, sfx: &Affix) -> Option<String> {
+    let base = word.strip_suffix(sfx.add.as_str())?;
+    if base.is_empty() && sfx.strip.is_empty() {
+        return None;
+    }
+    let stem = format!("{}{}", base, sfx.strip);
+    Some(stem).filter(|stem| sfx.matches(stem))
+}
+
+// The stem of `word` without the prefix, if the prefix and the condition match.
+fn strip_prefix(word: &str, pfx: &Affix) -> Option<String> {
+    let base = word.strip_prefix(pfx.add.as_str())?;
+    if base.is_empty() && pfx.strip.is_empty() {
+        return None;
+    }
+    let stem = format!("{}{}", pfx.strip, base);
+    Some(stem).filter(|stem| pfx.matches(stem))
+}
+
+// The encoding declared by `SET`, the default is ISO8859-1.
+fn aff_encoding(aff: &[u8]) -> io::Result<&'static Encoding> {
+    // `SET` is ASCII, so the lines can be split before decoding
+    let set = aff
+        .split(|b| *b == b'\n')
+        .map(|line| String::from_utf8_lossy(line))
+        .find_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("SET"), Some(label)) => Some(label.to_owned()),
+                _ => None,
+            }
+        });
+    let label = set.unwrap_or_else(|| String::from("ISO8859-1"));
+    // `encoding_rs` doesn't know the names of hunspell without dash
+    let label = match label.to_ascii_uppercase().as_str() {
+        "MICROSOFT-CP1251" => String::from("windows-1251"),
+        l if l.starts_with("ISO8859") => l.replacen("ISO8859", "ISO-8859", 1),
+        _ => label,
+    };
+    Encoding::for_label(label.as_bytes()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown encoding of hunspell dictionary: {}", label),
+        )
+    })
+}
+
+// Split `word/flags` of the `.dic` file, `\/` is a slash in the word.
+fn split_flags(entry: &str) -> (String, &str) {
+    let mut word = String::new();
+    let mut chars = entry.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if entry[i + 1..].starts_with('/') => {
+                word.push('/');
+                chars.next();
+            }
+            '/' => return (word, &entry[i + 1..]),
+            c => word.push(c),
+        }
+    }
+    (word, "")
+}
+
+// Convert the condition of an affix, like `[^aeiou]y`, to a regex.
+fn condition_regex(condition: &str, suffix: bool) -> Result<Option<Regex>, regex::Error> {
+    if condition == "." {
+        return Ok(None);
+    }
+    let mut regex = String::new();
+    let mut in_set = false;
+    for c in condition.chars() {
+        match c {
+            '[' if !in_set => {
+                in_set = true;
+                regex.push('[');
+            }
+            ']' if in_set => {
+                in_set = false;
+                regex.push(']');
+            }
+            '^' if in_set && regex.ends_with('[') => regex.push('^'),
+            '.' if !in_set => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    let regex = if suffix {
+        format!("(?:{})$", regex)
+    } else {
+        format!("^(?:{})", regex)
+    };
+    Regex::new(&regex).map(Some)
+}
+
+#[derive(Default)]
+struct AffParser {
+    mode: Option<FlagMode>,
+    // `AF` aliases of flags, referred by the number from 1
+    aliases: Vec<Vec<Flag>>,
+    aliases_count: Option<usize>,
+    // the number of remaining rules of `PFX` or `SFX` headers, and whether they are cross product
+    headers: HashMap<(bool, Flag), (usize, bool)>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+}
+
+impl AffParser {
+    fn mode(&self) -> FlagMode {
+        self.mode.unwrap_or(FlagMode::Char)
+    }
+
+    fn parse_line(&mut self, line: &str) -> io::Result<()> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["FLAG", mode, ..] => {
+                self.mode = match *mode {
+                    "long" => Some(FlagMode::Long),
+                    "num" => Some(FlagMode::Num),
+                    // UTF-8 flags are chars since the file is decoded
+                    _ => Some(FlagMode::Char),
+                };
+            }
+            ["AF", value, ..] => match self.aliases_count {
+                None => self.aliases_count = value.parse().ok(),
+                Some(_) => {
+                    let flags = self.parse_raw_flags(value);
+                    self.aliases.push(flags);
+                }
+            },
+            [kind @ "PFX", flag, rest @ ..] | [kind @ "SFX", flag, rest @ ..] => {
+                let suffix = *kind == "SFX";
+                let flag = match self.parse_raw_flags(flag).first() {
+                    Some(flag) => *flag,
+                    None => return Ok(()),
+                };
+                match self.headers.get_mut(&(suffix, flag)) {
+                    Some((remaining, cross_product)) if *remaining > 0 => {
+                        *remaining -= 1;
+                        let cross_product = *cross_product;
+                        self.parse_affix(suffix, flag, cross_product, rest)?;
+                    }
+                    _ => {
+                        // a header like `SFX A Y 3`
+                        if let [cross_product, count, ..] = rest {
+                            let count = count.parse().unwrap_or(0);
+                            self.headers
+                                .insert((suffix, flag), (count, *cross_product == "Y"));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // A rule like `SFX A y ies [^aeiou]y`
+    fn parse_affix(
+        &mut self,
+        suffix: bool,
+        flag: Flag,
+        cross_product: bool,
+        rest: &[&str],
+    ) -> io::Result<()> {
+        let (strip, add) = match rest {
+            [strip, add, ..] => (*strip, *add),
+            _ => return Ok(()),
+        };
+        let condition = rest.get(2).copied().unwrap_or(".");
+        let zero = |s: &str| {
+            if s == "0" {
+                String::new()
+            } else {
+                s.to_owned()
+            }
+        };
+        // continuation flags after `/` are ignored
+        let add = add.split('/').next().unwrap_or_default();
+        let condition = condition_regex(condition, suffix)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let affix = Affix {
+            flag,
+            cross_product,
+            strip: zero(strip),
+            add: zero(add),
+            condition,
+        };
+        if suffix {
+            self.suffixes.push(affix);
+        } else {
+            self.prefixes.push(affix);
+        }
+        Ok(())
+    }
+
+    // Flags of a word in the `.dic` file, which may be an alias.
+    fn parse_flags(&self, flags: &str) -> Vec<Flag> {
+        if !self.aliases.is_empty() {
+            if let Ok(i) = flags.parse::<usize>() {
+                return self
+                    .aliases
+                    .get(i.wrapping_sub(1))
+                    .cloned()
+                    .unwrap_or_default();
+            }
+        }
+        self.parse_raw_flags(flags)
+    }
+
+    fn parse_raw_flags(&self, flags: &str) -> Vec<Flag> {
+        match self.mode() {
+            FlagMode::Char => flags.chars().map(|c| c as Flag).collect(),
+            FlagMode::Long => {
+                let chars: Vec<char> = flags.chars().collect();
+                chars
+                    .chunks(2)
+                    .map(|pair| pair.iter().fold(0, |flag, c| (flag << 21) | *c as Flag))
+                    .collect()
+            }
+            FlagMode::Num => flags
+                .split(',')
+                .filter_map(|n| n.trim().parse().ok())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hunspell(aff: &str, dic: &str) -> MDictHunspell {
+        MDictHunspell::parse(String::from("test"), aff, dic).unwrap()
+    }
+
+    fn parser(lines: &[&str]) -> AffParser {
+        let mut parser = AffParser::default();
+        for line in lines {
+            parser.parse_line(line).unwrap();
+        }
+        parser
+    }
+
+    #[test]
+    fn parse_rules() {
+        let parser = parser(&[
+            "SET UTF-8",
+            "PFX A Y 1",
+            "PFX A 0 re .",
+            "SFX B N 2",
+            "SFX B y ies [^aeiou]y",
+            "SFX B 0 s/C [aeiou]y",
+            // more rules than the header counts are read as a new header
+            "SFX B 0 ed .",
+        ]);
+        assert_eq!(parser.prefixes.len(), 1);
+        assert_eq!(parser.suffixes.len(), 2);
+        let pfx = &parser.prefixes[0];
+        assert_eq!((pfx.flag, pfx.cross_product), ('A' as Flag, true));
+        assert_eq!((pfx.strip.as_str(), pfx.add.as_str()), ("", "re"));
+        assert!(pfx.condition.is_none());
+        let sfx = &parser.suffixes[0];
+        assert_eq!((sfx.flag, sfx.cross_product), ('B' as Flag, false));
+        assert_eq!((sfx.strip.as_str(), sfx.add.as_str()), ("y", "ies"));
+        // continuation flags are dropped
+        assert_eq!(parser.suffixes[1].add, "s");
+    }
+
+    #[test]
+    fn parse_long_flags() {
+        let parser = parser(&["FLAG long"]);
+        let ab = ('A' as Flag) << 21 | 'b' as Flag;
+        let cd = ('C' as Flag) << 21 | 'd' as Flag;
+        assert_eq!(parser.parse_flags("AbCd"), vec![ab, cd]);
+        assert_ne!(parser.parse_flags("bA"), vec![ab]);
+    }
+
+    #[test]
+    fn parse_num_flags() {
+        let parser = parser(&["FLAG num"]);
+        assert_eq!(parser.parse_flags("1,23, 456"), vec![1, 23, 456]);
+        assert_eq!(parser.parse_flags("7,x"), vec![7]);
+    }
+
+    #[test]
+    fn parse_alias_flags() {
+        let aliased = parser(&["AF 2", "AF AB # 1", "AF C # 2"]);
+        assert_eq!(aliased.parse_flags("1"), vec!['A' as Flag, 'B' as Flag]);
+        assert_eq!(aliased.parse_flags("2"), vec!['C' as Flag]);
+        assert!(aliased.parse_flags("0").is_empty());
+        assert!(aliased.parse_flags("3").is_empty());
+        // without aliases a digit is a flag
+        assert_eq!(parser(&[]).parse_flags("1"), vec!['1' as Flag]);
+    }
+
+    #[test]
+    fn convert_conditions() {
+        assert!(condition_regex(".", true).unwrap().is_none());
+        let sfx = condition_regex("[^aeiou]y", true).unwrap().unwrap();
+        assert!(sfx.is_match("city"));
+        assert!(!sfx.is_match("day"));
+        assert!(!sfx.is_match("cityx"));
+        let pfx = condition_regex("un.", false).unwrap().unwrap();
+        assert!(pfx.is_match("undo"));
+        assert!(!pfx.is_match("run"));
+        // other regex chars are literal
+        let literal = condition_regex("a+", true).unwrap().unwrap();
+        assert!(literal.is_match("ba+"));
+        assert!(!literal.is_match("baa"));
+    }
+
+    #[test]
+    fn strip_affixes() {
+        let dic = hunspell(
+            "PFX U N 1\nPFX U 0 un .\nSFX S N 2\nSFX S y ies [^aeiou]y\nSFX S 0 s [aeiou]y\n",
+            "4\ncity/S\ndo/U\nday/S\ndog\n",
+        );
+        assert_eq!(dic.stems("cities"), vec!["city"]);
+        assert_eq!(dic.stems("undo"), vec!["do"]);
+        assert_eq!(dic.stems("city"), vec!["city"]);
+        // the condition doesn't match
+        assert!(dic.stems("daies").is_empty());
+        assert_eq!(dic.stems("days"), vec!["day"]);
+        // `dog` doesn't have the flag
+        assert!(dic.stems("dogs").is_empty());
+        assert_eq!(dic.stems("Cities"), vec!["city"]);
+    }
+
+    #[test]
+    fn cross_product_affixes() {
+        let aff = "PFX U Y 1\nPFX U 0 un .\nSFX D Y 1\nSFX D 0 ed .\n\
+                   PFX R N 1\nPFX R 0 re .\n";
+        let dic = hunspell(aff, "lock/UDR\nwind/D\n");
+        assert_eq!(dic.stems("unlocked"), vec!["lock"]);
+        // `R` is not cross product
+        assert!(dic.stems("relocked").is_empty());
+        // both flags are needed
+        assert!(dic.stems("unwinded").is_empty());
+        assert_eq!(dic.stems("winded"), vec!["wind"]);
+    }
+
+    #[test]
+    fn split_dic_entries() {
+        assert_eq!(split_flags("city/S"), (String::from("city"), "S"));
+        assert_eq!(split_flags("a\\/b/XY"), (String::from("a/b"), "XY"));
+        assert_eq!(split_flags("word"), (String::from("word"), ""));
+    }
+}
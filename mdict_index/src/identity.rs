@@ -0,0 +1,82 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    time::UNIX_EPOCH,
+};
+
+/// Identity of a mdx or mdd file, which is changed when the file is modified or replaced.
+///
+/// An index built from the file can't be used if the identity is changed, because the offsets
+/// of records are different.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MDictFileIdentity {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime: u128,
+    /// The checksum of the header stored in the file, so a new edition of a dictionary copied
+    /// with the same size and modification time is also found
+    pub checksum: u32,
+}
+
+impl MDictFileIdentity {
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<MDictFileIdentity> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos());
+        // the size of the header, the header, then the checksum of the header
+        let mut buf = [0; 4];
+        file.read_exact(&mut buf)?;
+        let header_size = u32::from_be_bytes(buf);
+        file.seek(SeekFrom::Start(4 + header_size as u64))?;
+        file.read_exact(&mut buf)?;
+        Ok(MDictFileIdentity {
+            size: metadata.len(),
+            mtime,
+            checksum: u32::from_le_bytes(buf),
+        })
+    }
+}
+
+/// `{size} {mtime} {checksum}`, the checksum is in hex.
+impl fmt::Display for MDictFileIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {:08x}", self.size, self.mtime, self.checksum)
+    }
+}
+
+// FNV-1a hash of the identities of a mdx file and its mdd files, which is the same in any build
+// unlike `DefaultHasher`, so it can be used in names of cache files.
+pub(crate) fn identity_hash(identities: &[MDictFileIdentity]) -> u64 {
+    identities
+        .iter()
+        .flat_map(|identity| format!("{}\n", identity).into_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_identities() {
+        let identity = |size, mtime, checksum| MDictFileIdentity {
+            size,
+            mtime,
+            checksum,
+        };
+        let mdx = identity(100, 1, 0xabcd);
+        let hash = identity_hash(std::slice::from_ref(&mdx));
+        assert_eq!(hash, identity_hash(&[identity(100, 1, 0xabcd)]));
+        assert_ne!(hash, identity_hash(&[identity(100, 2, 0xabcd)]));
+        assert_ne!(hash, identity_hash(&[identity(100, 1, 0xabce)]));
+        // a new mdd file changes the identity of the dictionary
+        assert_ne!(hash, identity_hash(&[mdx, identity(5, 1, 1)]));
+    }
+}
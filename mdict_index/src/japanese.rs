@@ -0,0 +1,492 @@
+use once_cell::sync::Lazy;
+
+// Word classes of deinflection rules, like the rules of Yomichan
+const V1: u32 = 1; // ichidan verb
+const V5: u32 = 1 << 1; // godan verb
+const VS: u32 = 1 << 2; // suru verb
+const VK: u32 = 1 << 3; // kuru verb
+const ADJ_I: u32 = 1 << 4; // i-adjective
+const TE: u32 = 1 << 5; // te-form, which is not a dictionary form
+const DICTIONARY: u32 = V1 | V5 | VS | VK | ADJ_I;
+
+/// A term from a Japanese query, see [`mdict_japanese_terms`].
+#[derive(Clone, Debug)]
+pub struct MDictJapaneseTerm {
+    pub term: String,
+    /// Transformations from the query to the term in order, like `["romaji to hiragana", "past"]`,
+    /// inflections are undone from the outermost one.
+    pub transforms: Vec<&'static str>,
+}
+
+// Replace the suffix `kana_in` of a word in `rules_in` to `kana_out`, which is in `rules_out`.
+struct Rule {
+    kana_in: String,
+    kana_out: String,
+    rules_in: u32,
+    rules_out: u32,
+    reason: &'static str,
+}
+
+// Endings of godan verbs: dictionary form, i, a, e and o stems
+const GODAN: &[[&str; 5]] = &[
+    ["う", "い", "わ", "え", "お"],
+    ["く", "き", "か", "け", "こ"],
+    ["ぐ", "ぎ", "が", "げ", "ご"],
+    ["す", "し", "さ", "せ", "そ"],
+    ["つ", "ち", "た", "て", "と"],
+    ["ぬ", "に", "な", "ね", "の"],
+    ["ぶ", "び", "ば", "べ", "ぼ"],
+    ["む", "み", "ま", "め", "も"],
+    ["る", "り", "ら", "れ", "ろ"],
+];
+
+// Past endings of godan verbs, "た" is replaced by other endings of the same sound change
+const GODAN_PAST: &[(&str, &str)] = &[
+    ("った", "う"),
+    ("った", "つ"),
+    ("った", "る"),
+    ("いた", "く"),
+    ("いだ", "ぐ"),
+    ("した", "す"),
+    ("んだ", "ぬ"),
+    ("んだ", "ぶ"),
+    ("んだ", "む"),
+    // the only exception
+    ("行った", "行く"),
+    ("いった", "いく"),
+];
+
+struct RuleSet(Vec<Rule>);
+
+impl RuleSet {
+    fn push(
+        &mut self,
+        reason: &'static str,
+        kana_in: &str,
+        kana_out: &str,
+        rules_in: u32,
+        rules_out: u32,
+    ) {
+        self.0.push(Rule {
+            kana_in: kana_in.to_owned(),
+            kana_out: kana_out.to_owned(),
+            rules_in,
+            rules_out,
+            reason,
+        });
+    }
+
+    // A kuru verb is also written as "来る".
+    fn push_kuru(&mut self, reason: &'static str, kana_in: &str, rules_in: u32) {
+        self.push(reason, kana_in, "くる", rules_in, VK);
+        let kanji_in: String = std::iter::once('来')
+            .chain(kana_in.chars().skip(1))
+            .collect();
+        self.push(reason, &kanji_in, "来る", rules_in, VK);
+    }
+
+    // Forms made from a stem of verbs, `stem` is the index of the stem in `GODAN`.
+    fn push_verb(
+        &mut self,
+        reason: &'static str,
+        stem: usize,
+        suffix: &str,
+        forms: [&str; 3],
+        rules_in: u32,
+    ) {
+        let [ichidan, suru, kuru] = forms;
+        self.push(reason, ichidan, "る", rules_in, V1);
+        for endings in GODAN {
+            self.push(
+                reason,
+                &format!("{}{}", endings[stem], suffix),
+                endings[0],
+                rules_in,
+                V5,
+            );
+        }
+        self.push(reason, suru, "する", rules_in, VS);
+        self.push_kuru(reason, kuru, rules_in);
+    }
+
+    // Forms made from the past form, with the ending "た" replaced by `ta` and "だ" by `da`.
+    fn push_past(&mut self, reason: &'static str, ta: &str, da: &str, rules_in: u32) {
+        let replace = |s: &str| s.replace('た', ta).replace('だ', da);
+        self.push(reason, ta, "る", rules_in, V1);
+        for (kana_in, kana_out) in GODAN_PAST {
+            self.push(reason, &replace(kana_in), kana_out, rules_in, V5);
+        }
+        self.push(reason, &format!("し{}", ta), "する", rules_in, VS);
+        self.push_kuru(reason, &format!("き{}", ta), rules_in);
+    }
+}
+
+fn rules() -> Vec<Rule> {
+    let mut rules = RuleSet(Vec::new());
+    // forms from the i stem
+    for (reason, suffix, rules_in) in &[
+        ("polite", "ます", 0),
+        ("polite past", "ました", 0),
+        ("polite negative", "ません", 0),
+        ("polite past negative", "ませんでした", 0),
+        ("polite volitional", "ましょう", 0),
+        ("-tai", "たい", ADJ_I),
+        ("-nagara", "ながら", 0),
+        ("imperative", "なさい", 0),
+    ] {
+        let suru = format!("し{}", suffix);
+        let kuru = format!("き{}", suffix);
+        rules.push_verb(reason, 1, suffix, [suffix, &suru, &kuru], *rules_in);
+    }
+    // forms from the a stem
+    rules.push_verb("negative", 2, "ない", ["ない", "しない", "こない"], ADJ_I);
+    rules.push_verb("-zu", 2, "ず", ["ず", "せず", "こず"], 0);
+    rules.push_verb("passive", 2, "れる", ["られる", "される", "こられる"], V1);
+    rules.push_verb("causative", 2, "せる", ["させる", "させる", "こさせる"], V1);
+    // forms from the e stem
+    rules.push_verb("potential", 3, "る", ["られる", "できる", "こられる"], V1);
+    rules.push_verb("-ba", 3, "ば", ["れば", "すれば", "くれば"], 0);
+    rules.push_verb("imperative", 3, "", ["ろ", "しろ", "こい"], 0);
+    // forms from the o stem
+    rules.push_verb("volitional", 4, "う", ["よう", "しよう", "こよう"], 0);
+    // forms from the past form
+    rules.push_past("past", "た", "だ", 0);
+    rules.push_past("-te", "て", "で", TE);
+    rules.push_past("-tara", "たら", "だら", 0);
+    rules.push_past("-tari", "たり", "だり", 0);
+    // auxiliary verbs after the te-form
+    for (reason, kana_in, rules_in) in &[
+        ("progressive or perfect", "いる", V1),
+        ("-te shimau", "しまう", V5),
+        ("-te oku", "おく", V5),
+    ] {
+        rules.push(reason, &format!("て{}", kana_in), "て", *rules_in, TE);
+        rules.push(reason, &format!("で{}", kana_in), "で", *rules_in, TE);
+    }
+    // i-adjectives
+    for (reason, kana_in, rules_in) in &[
+        ("past", "かった", 0),
+        ("-te", "くて", 0),
+        ("adverbial", "く", 0),
+        ("negative", "くない", ADJ_I),
+        ("-ba", "ければ", 0),
+        ("noun", "さ", 0),
+        ("-sou", "そう", 0),
+    ] {
+        rules.push(reason, kana_in, "い", *rules_in, ADJ_I);
+    }
+    rules.0
+}
+
+// Rules are built once, they are used by every lookup of Japanese words
+static RULES: Lazy<Vec<Rule>> = Lazy::new(rules);
+
+// Deinflect a Japanese word like Yomichan, the results may not be words.
+//
+// Rules are applied repeatedly, so "食べたくなかった" is "食べる" by past, negative and -tai.
+fn deinflect(word: &str) -> Vec<(String, Vec<&'static str>)> {
+    // the word itself can be any class
+    let mut results = vec![(word.to_owned(), 0, Vec::new())];
+    let mut i = 0;
+    while i < results.len() {
+        let (term, class, reasons) = results[i].clone();
+        for rule in RULES.iter() {
+            if class != 0 && class & rule.rules_in == 0 {
+                continue;
+            }
+            let stem = match term.strip_suffix(rule.kana_in.as_str()) {
+                Some(stem) => stem,
+                None => continue,
+            };
+            let mut reasons = reasons.clone();
+            reasons.push(rule.reason);
+            results.push((
+                format!("{}{}", stem, rule.kana_out),
+                rule.rules_out,
+                reasons,
+            ));
+        }
+        i += 1;
+    }
+    results
+        .into_iter()
+        .filter(|(_, class, _)| *class == 0 || class & DICTIONARY != 0)
+        .map(|(term, _, reasons)| (term, reasons))
+        .collect()
+}
+
+// Romaji of kana, longer ones are matched first
+const ROMAJI: &[(&str, &str)] = &[
+    ("kya", "きゃ"),
+    ("kyu", "きゅ"),
+    ("kyo", "きょ"),
+    ("gya", "ぎゃ"),
+    ("gyu", "ぎゅ"),
+    ("gyo", "ぎょ"),
+    ("sha", "しゃ"),
+    ("shu", "しゅ"),
+    ("sho", "しょ"),
+    ("she", "しぇ"),
+    ("shi", "し"),
+    ("sya", "しゃ"),
+    ("syu", "しゅ"),
+    ("syo", "しょ"),
+    ("jya", "じゃ"),
+    ("jyu", "じゅ"),
+    ("jyo", "じょ"),
+    ("zya", "じゃ"),
+    ("zyu", "じゅ"),
+    ("zyo", "じょ"),
+    ("cha", "ちゃ"),
+    ("chu", "ちゅ"),
+    ("cho", "ちょ"),
+    ("che", "ちぇ"),
+    ("chi", "ち"),
+    ("tya", "ちゃ"),
+    ("tyu", "ちゅ"),
+    ("tyo", "ちょ"),
+    ("tsu", "つ"),
+    ("nya", "にゃ"),
+    ("nyu", "にゅ"),
+    ("nyo", "にょ"),
+    ("hya", "ひゃ"),
+    ("hyu", "ひゅ"),
+    ("hyo", "ひょ"),
+    ("bya", "びゃ"),
+    ("byu", "びゅ"),
+    ("byo", "びょ"),
+    ("pya", "ぴゃ"),
+    ("pyu", "ぴゅ"),
+    ("pyo", "ぴょ"),
+    ("mya", "みゃ"),
+    ("myu", "みゅ"),
+    ("myo", "みょ"),
+    ("rya", "りゃ"),
+    ("ryu", "りゅ"),
+    ("ryo", "りょ"),
+    ("ja", "じゃ"),
+    ("ju", "じゅ"),
+    ("jo", "じょ"),
+    ("je", "じぇ"),
+    ("ji", "じ"),
+    ("fa", "ふぁ"),
+    ("fi", "ふぃ"),
+    ("fe", "ふぇ"),
+    ("fo", "ふぉ"),
+    ("fu", "ふ"),
+    ("ka", "か"),
+    ("ki", "き"),
+    ("ku", "く"),
+    ("ke", "け"),
+    ("ko", "こ"),
+    ("ga", "が"),
+    ("gi", "ぎ"),
+    ("gu", "ぐ"),
+    ("ge", "げ"),
+    ("go", "ご"),
+    ("sa", "さ"),
+    ("si", "し"),
+    ("su", "す"),
+    ("se", "せ"),
+    ("so", "そ"),
+    ("za", "ざ"),
+    ("zi", "じ"),
+    ("zu", "ず"),
+    ("ze", "ぜ"),
+    ("zo", "ぞ"),
+    ("ta", "た"),
+    ("ti", "ち"),
+    ("tu", "つ"),
+    ("te", "て"),
+    ("to", "と"),
+    ("da", "だ"),
+    ("di", "ぢ"),
+    ("du", "づ"),
+    ("de", "で"),
+    ("do", "ど"),
+    ("na", "な"),
+    ("ni", "に"),
+    ("nu", "ぬ"),
+    ("ne", "ね"),
+    ("no", "の"),
+    ("ha", "は"),
+    ("hi", "ひ"),
+    ("hu", "ふ"),
+    ("he", "へ"),
+    ("ho", "ほ"),
+    ("ba", "ば"),
+    ("bi", "び"),
+    ("bu", "ぶ"),
+    ("be", "べ"),
+    ("bo", "ぼ"),
+    ("pa", "ぱ"),
+    ("pi", "ぴ"),
+    ("pu", "ぷ"),
+    ("pe", "ぺ"),
+    ("po", "ぽ"),
+    ("ma", "ま"),
+    ("mi", "み"),
+    ("mu", "む"),
+    ("me", "め"),
+    ("mo", "も"),
+    ("ya", "や"),
+    ("yu", "ゆ"),
+    ("yo", "よ"),
+    ("ra", "ら"),
+    ("ri", "り"),
+    ("ru", "る"),
+    ("re", "れ"),
+    ("ro", "ろ"),
+    ("wa", "わ"),
+    ("wo", "を"),
+    ("vu", "ゔ"),
+    ("n'", "ん"),
+    ("a", "あ"),
+    ("i", "い"),
+    ("u", "う"),
+    ("e", "え"),
+    ("o", "お"),
+    ("-", "ー"),
+];
+
+/// Convert romaji to hiragana by Hepburn or Kunrei-shiki, chars which are not romaji are kept.
+///
+/// A doubled consonant is "っ", and "n" or "nn" which isn't followed by a vowel or "y" is "ん".
+pub fn mdict_romaji_to_hiragana(romaji: &str) -> String {
+    let romaji = romaji.to_lowercase();
+    let mut kana = String::with_capacity(romaji.len() * 3);
+    let mut rest = romaji.as_str();
+    'outer: while let Some(c) = rest.chars().next() {
+        for (latin, hiragana) in ROMAJI {
+            if rest.starts_with(latin) {
+                kana.push_str(hiragana);
+                rest = &rest[latin.len()..];
+                continue 'outer;
+            }
+        }
+        let mut next = rest[c.len_utf8()..].chars();
+        let (next, after) = (next.next(), next.next());
+        let mut len = c.len_utf8();
+        match c {
+            'n' => {
+                // "nn" is also "ん" unless it's followed by a vowel, like "konnichiwa"
+                if next == Some('n') && !after.map_or(false, |a| "aeiouy".contains(a)) {
+                    len += 1;
+                }
+                kana.push('ん');
+            }
+            // "matcha" is "まっちゃ"
+            c if (Some(c) == next || (c, next) == ('t', Some('c')))
+                && c.is_ascii_alphabetic()
+                && !"aeiou".contains(c) =>
+            {
+                kana.push('っ')
+            }
+            c => kana.push(c),
+        }
+        rest = &rest[len..];
+    }
+    kana
+}
+
+/// Convert hiragana to katakana, other chars are kept.
+pub fn mdict_hiragana_to_katakana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{3041}'..='\u{3096}' | '\u{309D}'..='\u{309E}' => {
+                std::char::from_u32(c as u32 + 0x60).unwrap_or(c)
+            }
+            c => c,
+        })
+        .collect()
+}
+
+/// Convert katakana to hiragana, other chars are kept.
+pub fn mdict_katakana_to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{30A1}'..='\u{30F6}' | '\u{30FD}'..='\u{30FE}' => {
+                std::char::from_u32(c as u32 - 0x60).unwrap_or(c)
+            }
+            c => c,
+        })
+        .collect()
+}
+
+/// Terms to look up for a Japanese query, the query itself is the first.
+///
+/// Romaji is converted to hiragana and katakana, hiragana and katakana are converted to each other,
+/// then each of them is deinflected like Yomichan. The terms may not be words.
+pub fn mdict_japanese_terms(query: &str) -> Vec<MDictJapaneseTerm> {
+    let mut forms = vec![(query.to_owned(), Vec::new())];
+    if query.chars().any(|c| c.is_ascii_alphabetic()) {
+        let hiragana = mdict_romaji_to_hiragana(query);
+        let katakana = mdict_hiragana_to_katakana(&hiragana);
+        forms.push((hiragana, vec!["romaji to hiragana"]));
+        forms.push((katakana, vec!["romaji to katakana"]));
+    } else {
+        let katakana = mdict_hiragana_to_katakana(query);
+        if katakana != query {
+            forms.push((katakana, vec!["hiragana to katakana"]));
+        }
+        let hiragana = mdict_katakana_to_hiragana(query);
+        if hiragana != query {
+            forms.push((hiragana, vec!["katakana to hiragana"]));
+        }
+    }
+    let mut terms: Vec<MDictJapaneseTerm> = Vec::new();
+    for (form, transforms) in forms {
+        for (term, reasons) in deinflect(&form) {
+            if terms.iter().any(|t| t.term == term) {
+                continue;
+            }
+            let mut transforms = transforms.clone();
+            transforms.extend(reasons);
+            terms.push(MDictJapaneseTerm { term, transforms });
+        }
+    }
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reasons_of(word: &str, base: &str) -> Option<Vec<&'static str>> {
+        deinflect(word)
+            .into_iter()
+            .find(|(term, _)| term == base)
+            .map(|(_, reasons)| reasons)
+    }
+
+    #[test]
+    fn deinflect_chained_rules() {
+        // inflections are undone from the outermost one
+        assert_eq!(
+            reasons_of("食べたくなかった", "食べる").unwrap(),
+            vec!["past", "negative", "-tai"]
+        );
+        assert_eq!(reasons_of("行った", "行く").unwrap(), vec!["past"]);
+        assert!(reasons_of("読まない", "読む").is_some());
+        assert!(reasons_of("食べる", "食べる").unwrap().is_empty());
+    }
+
+    #[test]
+    fn romaji() {
+        assert_eq!(mdict_romaji_to_hiragana("konnichiwa"), "こんにちわ");
+        assert_eq!(mdict_romaji_to_hiragana("matcha"), "まっちゃ");
+        assert_eq!(mdict_romaji_to_hiragana("kitte"), "きって");
+        assert_eq!(mdict_romaji_to_hiragana("hon"), "ほん");
+        assert_eq!(mdict_romaji_to_hiragana("kanna"), "かんな");
+        assert_eq!(mdict_hiragana_to_katakana("まっちゃ"), "マッチャ");
+        assert_eq!(mdict_katakana_to_hiragana("マッチャ"), "まっちゃ");
+    }
+
+    #[test]
+    fn japanese_terms() {
+        let terms = mdict_japanese_terms("tabetakunakatta");
+        let term = terms.iter().find(|t| t.term == "たべる").unwrap();
+        assert_eq!(term.transforms[0], "romaji to hiragana");
+        assert_eq!(terms[0].term, "tabetakunakatta");
+    }
+}
@@ -1,36 +1,650 @@
 use bytes::Bytes;
 use log::*;
 use mdict::*;
+use mem_cache::MemKeywords;
+use normalize::normalized_key;
 use patricia_tree::PatriciaMap;
+use pinyin::PinyinQuery;
+use redirect::{link_to, Redirects};
 use std::{
-    fs::OpenOptions,
+    collections::{BinaryHeap, HashMap, HashSet},
+    fs::{File, OpenOptions},
     io,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
+mod chinese;
+#[cfg(feature = "sqlite")]
+mod full_text;
+mod fuzzy;
+mod hunspell;
+mod identity;
+mod japanese;
+mod library;
+mod mem_cache;
+mod morphology;
+mod normalize;
+mod pattern;
+mod phonetic;
+mod pinyin;
+mod redirect;
+mod resource;
+mod scan;
+mod segment;
 #[cfg(feature = "sqlite")]
 mod sqlite;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+mod watch;
 
+pub use chinese::{MDictChineseConverter, MDictChineseScript};
+#[cfg(feature = "sqlite")]
+pub use full_text::MDictFullTextMatch;
+pub use fuzzy::MDictDistance;
+pub use hunspell::MDictHunspell;
+use identity::identity_hash;
+pub use identity::MDictFileIdentity;
+pub use japanese::{
+    mdict_hiragana_to_katakana, mdict_japanese_terms, mdict_katakana_to_hiragana,
+    mdict_romaji_to_hiragana, MDictJapaneseTerm,
+};
+pub use library::{MDictLibrary, MDictLibraryEntry, MDictLibraryResult, MDictSharedLibrary};
+pub use mdict::{
+    MDictBuildPhase, MDictCancelToken, MDictCancelled, MDictProgress, MDictProgressObserver,
+};
+pub use morphology::mdict_deinflect;
+pub use normalize::mdict_normalize;
+pub use pattern::MDictPattern;
+pub use phonetic::{MDictDoubleMetaphone, MDictPhoneticEncoder, MDictSoundex};
+pub use pinyin::MDictPinyin;
+pub use redirect::{mdict_link_target, MDictRedirected};
+pub use resource::*;
+pub use scan::{mdict_scan, mdict_scan_file, MDictScanError, MDictScanReport, MDictScanned};
+pub use segment::MDictSegment;
 #[cfg(feature = "sqlite")]
 pub use sqlite::*;
+#[cfg(all(feature = "watch", target_os = "linux"))]
+pub use watch::{mdict_watch, MDictChange, MDictWatcher};
+
+// A subtree of the memory index with more keywords is split for fuzzy search, splitting costs
+// 256 lookups, which is about as fast as walking 256 keywords.
+const FUZZY_SUBTREE_SIZE: usize = 256;
+
+/// Keywords around a keyword in alphabetical order, see `neighbours` of
+/// [`MDictLookup`] or [`MDictAsyncLookup`].
+#[derive(Clone, Debug, Default)]
+pub struct MDictNeighbours {
+    /// Keywords before the keyword, in alphabetical order
+    pub before: Vec<String>,
+    /// Whether the keyword exists, otherwise it's the position where the keyword would be
+    pub exists: bool,
+    /// Keywords after the keyword, in alphabetical order
+    pub after: Vec<String>,
+}
+
+/// Fallbacks of `lookup_word_with` of [`MDictLookup`] or [`MDictAsyncLookup`], which are used to
+/// show a keyword to users. The records are looked up exactly by default.
+#[derive(Clone, Debug, Default)]
+pub struct MDictLookupOptions {
+    redirects: Option<usize>,
+    candidates: bool,
+}
+
+impl MDictLookupOptions {
+    pub fn new() -> MDictLookupOptions {
+        MDictLookupOptions::default()
+    }
+
+    /// Follow `@@@LINK=` redirects like `lookup_word_redirected`, at most `max_depth` in a row.
+    /// If a redirect is broken, the records are returned as they are, so it's shown as a link.
+    pub fn redirects(mut self, max_depth: usize) -> MDictLookupOptions {
+        self.redirects = Some(max_depth);
+        self
+    }
+
+    /// If `key` is not found, return the keywords found by `normalized_candidates` as
+    /// `@@@LINK=` redirects, so "cafe" finds a link to "café" and "ＡＢＣ" finds "ABC".
+    pub fn candidates(mut self, enable: bool) -> MDictLookupOptions {
+        self.candidates = enable;
+        self
+    }
+}
+
+// Keywords are sorted by bytes of UTF-8, which is the same as `BINARY` collation of sqlite.
 
 #[cfg(not(feature = "async"))]
 pub trait MDictLookup {
     fn word_exists(&self, key: &str) -> io::Result<bool>;
-    fn lookup_word(&self, key: &str) -> io::Result<String>;
-    fn lookup_resource(&self, key: &str) -> io::Result<Bytes>;
+    /// Records of `key` in the order of the file, a keyword may have multiple records.
+    fn lookup_word(&self, key: &str) -> io::Result<Vec<String>>;
+    /// Keywords which are the same as `key` after [`mdict_normalize`], in alphabetical order.
+    ///
+    /// Keywords in both scripts of Chinese are also found if [`MDictBuildOptions::chinese`] is used.
+    fn normalized_candidates(&self, key: &str) -> io::Result<Vec<String>>;
+    /// Records of `key` with the fallbacks of `options` to show it to users, see
+    /// [`MDictLookupOptions`].
+    fn lookup_word_with(&self, key: &str, options: &MDictLookupOptions) -> io::Result<Vec<String>> {
+        let records = match options.redirects {
+            Some(max_depth) => match self.lookup_word_redirected(key, max_depth) {
+                Ok(redirected) => {
+                    debug!("lookup {} : {}", key, redirected.chain.join(" -> "));
+                    Ok(redirected.records)
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    warn!("lookup {} : {}", key, e);
+                    self.lookup_word(key)
+                }
+                Err(e) => Err(e),
+            },
+            None => self.lookup_word(key),
+        };
+        match records {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && options.candidates => {
+                let candidates = self.normalized_candidates(key)?;
+                if candidates.is_empty() {
+                    Err(e)
+                } else {
+                    Ok(candidates.iter().map(|c| link_to(c)).collect())
+                }
+            }
+            records => records,
+        }
+    }
+    /// Records of `key`, or of `key` converted to the other script of Chinese if it's not found,
+    /// with the keyword which is found.
+    fn lookup_word_chinese(
+        &self,
+        key: &str,
+        converter: &MDictChineseConverter,
+    ) -> io::Result<(String, Vec<String>)> {
+        let not_found = match self.lookup_word(key) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => e,
+            records => return Ok((key.to_owned(), records?)),
+        };
+        for script in &[
+            MDictChineseScript::Traditional,
+            MDictChineseScript::Simplified,
+        ] {
+            let converted = converter.convert(key, *script);
+            if converted == key {
+                continue;
+            }
+            match self.lookup_word(&converted) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                records => return Ok((converted, records?)),
+            }
+        }
+        Err(not_found)
+    }
+    /// Base forms of an inflected English `word` which exist, such as "run" for "running",
+    /// "mouse" for "mice" and "good" for "better", see [`mdict_deinflect`].
+    fn find_lemmas(&self, word: &str) -> io::Result<Vec<String>> {
+        let mut lemmas = Vec::new();
+        for candidate in mdict_deinflect(word) {
+            if self.word_exists(&candidate)? {
+                lemmas.push(candidate);
+            }
+        }
+        Ok(lemmas)
+    }
+    /// Terms of a Japanese query which exist, with the transformations from the query,
+    /// such as "食べる" by "polite past" for "食べました", see [`mdict_japanese_terms`].
+    fn lookup_japanese(&self, query: &str) -> io::Result<Vec<MDictJapaneseTerm>> {
+        let mut found = Vec::new();
+        for term in mdict_japanese_terms(query) {
+            if self.word_exists(&term.term)? {
+                found.push(term);
+            }
+        }
+        Ok(found)
+    }
+    /// Stems of `word` by Hunspell `dictionaries` which exist, such as "Kind" for "Kindern",
+    /// so inflected words of other languages can be found, see [`MDictHunspell`].
+    fn find_stems(&self, word: &str, dictionaries: &[MDictHunspell]) -> io::Result<Vec<String>> {
+        let mut stems = Vec::new();
+        let mut seen = HashSet::new();
+        for stem in dictionaries.iter().flat_map(|d| d.stems(word)) {
+            if stem != word && seen.insert(stem.clone()) && self.word_exists(&stem)? {
+                stems.push(stem);
+            }
+        }
+        Ok(stems)
+    }
+    /// At most `limit` keywords after `key` in alphabetical order, `key` is not required to exist.
+    ///
+    /// Pass the last keyword of a page to get the next page.
+    fn keywords_after(&self, key: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords before `key` in alphabetical order, `key` is not required to exist.
+    ///
+    /// Pass the first keyword of a page to get the previous page.
+    fn keywords_before(&self, key: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords starting with `prefix`, the exact match is the first,
+    /// then shorter keywords are ranked before longer keywords.
+    fn suggest(&self, prefix: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords within `max_distance` of `key`, with their distance.
+    ///
+    /// The closest keywords are the first, this can be used as "did you mean" if `key` is not found.
+    fn fuzzy_search(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> io::Result<Vec<(String, usize)>>;
+    /// At most `limit` keywords matching `pattern` in alphabetical order.
+    ///
+    /// Only keywords after `after` are returned, pass the last keyword of a page to get the next page.
+    fn search_pattern(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords pronounced as `query` in pinyin, like "zhongguo", "zhong1guo2"
+    /// or "zhōngguó" for "中国".
+    ///
+    /// Tones are optional for each syllable. Nothing is found unless the index is built with
+    /// [`pinyin`](MDictBuildOptions::pinyin).
+    fn pinyin_search(&self, query: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords which sound like `key`, with their edit distance to `key`.
+    ///
+    /// Keywords with the same phonetic code as `key` are found, like "phonetic" for "fonetik",
+    /// the closest keywords are the first. Nothing is found unless the index is built with
+    /// [`phonetic`](MDictBuildOptions::phonetic).
+    fn phonetic_search(&self, key: &str, limit: usize) -> io::Result<Vec<(String, usize)>>;
+    /// `before` keywords before `key` and `after` keywords after `key`.
+    fn neighbours(&self, key: &str, before: usize, after: usize) -> io::Result<MDictNeighbours> {
+        Ok(MDictNeighbours {
+            before: self.keywords_before(key, before)?,
+            exists: self.word_exists(key)?,
+            after: self.keywords_after(key, after)?,
+        })
+    }
+    /// Records of `key` with `@@@LINK=` redirects replaced by the records of their targets.
+    ///
+    /// At most `max_depth` redirects are followed in a row, `io::ErrorKind::InvalidData` is returned
+    /// if there are more, or there is a cycle, or the target doesn't exist.
+    fn lookup_word_redirected(&self, key: &str, max_depth: usize) -> io::Result<MDictRedirected> {
+        let mut redirects = Redirects::new(key, self.lookup_word(key)?, max_depth);
+        while let Some(target) = redirects.next_target()? {
+            let records = self.lookup_word(&target);
+            redirects.follow(target, records)?;
+        }
+        Ok(redirects.finish())
+    }
+    fn lookup_resource(&self, key: &str) -> io::Result<Bytes> {
+        self.open_resource(key)?.read()
+    }
+    /// Find a resource without reading it, so it can be read as a stream or by range.
+    fn open_resource(&self, key: &str) -> io::Result<MDictResource>;
 }
 
 #[cfg(feature = "async")]
 #[async_trait]
 pub trait MDictAsyncLookup {
     async fn word_exists(&self, key: &str) -> io::Result<bool>;
-    async fn lookup_word(&self, key: &str) -> io::Result<String>;
-    async fn lookup_resource(&self, key: &str) -> io::Result<Bytes>;
+    /// Records of `key` in the order of the file, a keyword may have multiple records.
+    async fn lookup_word(&self, key: &str) -> io::Result<Vec<String>>;
+    /// Keywords which are the same as `key` after [`mdict_normalize`], in alphabetical order.
+    ///
+    /// Keywords in both scripts of Chinese are also found if [`MDictBuildOptions::chinese`] is used.
+    async fn normalized_candidates(&self, key: &str) -> io::Result<Vec<String>>;
+    /// Records of `key` with the fallbacks of `options` to show it to users, see
+    /// [`MDictLookupOptions`].
+    async fn lookup_word_with(
+        &self,
+        key: &str,
+        options: &MDictLookupOptions,
+    ) -> io::Result<Vec<String>> {
+        let records = match options.redirects {
+            Some(max_depth) => match self.lookup_word_redirected(key, max_depth).await {
+                Ok(redirected) => {
+                    debug!("lookup {} : {}", key, redirected.chain.join(" -> "));
+                    Ok(redirected.records)
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    warn!("lookup {} : {}", key, e);
+                    self.lookup_word(key).await
+                }
+                Err(e) => Err(e),
+            },
+            None => self.lookup_word(key).await,
+        };
+        match records {
+            Err(e) if e.kind() == io::ErrorKind::NotFound && options.candidates => {
+                let candidates = self.normalized_candidates(key).await?;
+                if candidates.is_empty() {
+                    Err(e)
+                } else {
+                    Ok(candidates.iter().map(|c| link_to(c)).collect())
+                }
+            }
+            records => records,
+        }
+    }
+    /// Records of `key`, or of `key` converted to the other script of Chinese if it's not found,
+    /// with the keyword which is found.
+    async fn lookup_word_chinese(
+        &self,
+        key: &str,
+        converter: &MDictChineseConverter,
+    ) -> io::Result<(String, Vec<String>)> {
+        let not_found = match self.lookup_word(key).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => e,
+            records => return Ok((key.to_owned(), records?)),
+        };
+        for script in &[
+            MDictChineseScript::Traditional,
+            MDictChineseScript::Simplified,
+        ] {
+            let converted = converter.convert(key, *script);
+            if converted == key {
+                continue;
+            }
+            match self.lookup_word(&converted).await {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                records => return Ok((converted, records?)),
+            }
+        }
+        Err(not_found)
+    }
+    /// Base forms of an inflected English `word` which exist, such as "run" for "running",
+    /// "mouse" for "mice" and "good" for "better", see [`mdict_deinflect`].
+    async fn find_lemmas(&self, word: &str) -> io::Result<Vec<String>> {
+        let mut lemmas = Vec::new();
+        for candidate in mdict_deinflect(word) {
+            if self.word_exists(&candidate).await? {
+                lemmas.push(candidate);
+            }
+        }
+        Ok(lemmas)
+    }
+    /// Terms of a Japanese query which exist, with the transformations from the query,
+    /// such as "食べる" by "polite past" for "食べました", see [`mdict_japanese_terms`].
+    async fn lookup_japanese(&self, query: &str) -> io::Result<Vec<MDictJapaneseTerm>> {
+        let mut found = Vec::new();
+        for term in mdict_japanese_terms(query) {
+            if self.word_exists(&term.term).await? {
+                found.push(term);
+            }
+        }
+        Ok(found)
+    }
+    /// Stems of `word` by Hunspell `dictionaries` which exist, such as "Kind" for "Kindern",
+    /// so inflected words of other languages can be found, see [`MDictHunspell`].
+    async fn find_stems(
+        &self,
+        word: &str,
+        dictionaries: &[MDictHunspell],
+    ) -> io::Result<Vec<String>> {
+        let mut stems = Vec::new();
+        let mut seen = HashSet::new();
+        for stem in dictionaries.iter().flat_map(|d| d.stems(word)) {
+            if stem != word && seen.insert(stem.clone()) && self.word_exists(&stem).await? {
+                stems.push(stem);
+            }
+        }
+        Ok(stems)
+    }
+    /// At most `limit` keywords after `key` in alphabetical order, `key` is not required to exist.
+    ///
+    /// Pass the last keyword of a page to get the next page.
+    async fn keywords_after(&self, key: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords before `key` in alphabetical order, `key` is not required to exist.
+    ///
+    /// Pass the first keyword of a page to get the previous page.
+    async fn keywords_before(&self, key: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords starting with `prefix`, the exact match is the first,
+    /// then shorter keywords are ranked before longer keywords.
+    async fn suggest(&self, prefix: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords within `max_distance` of `key`, with their distance.
+    ///
+    /// The closest keywords are the first, this can be used as "did you mean" if `key` is not found.
+    async fn fuzzy_search(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> io::Result<Vec<(String, usize)>>;
+    /// At most `limit` keywords matching `pattern` in alphabetical order.
+    ///
+    /// Only keywords after `after` are returned, pass the last keyword of a page to get the next page.
+    async fn search_pattern(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords pronounced as `query` in pinyin, like "zhongguo", "zhong1guo2"
+    /// or "zhōngguó" for "中国".
+    ///
+    /// Tones are optional for each syllable. Nothing is found unless the index is built with
+    /// [`pinyin`](MDictBuildOptions::pinyin).
+    async fn pinyin_search(&self, query: &str, limit: usize) -> io::Result<Vec<String>>;
+    /// At most `limit` keywords which sound like `key`, with their edit distance to `key`.
+    ///
+    /// Keywords with the same phonetic code as `key` are found, like "phonetic" for "fonetik",
+    /// the closest keywords are the first. Nothing is found unless the index is built with
+    /// [`phonetic`](MDictBuildOptions::phonetic).
+    async fn phonetic_search(&self, key: &str, limit: usize) -> io::Result<Vec<(String, usize)>>;
+    /// `before` keywords before `key` and `after` keywords after `key`.
+    async fn neighbours(
+        &self,
+        key: &str,
+        before: usize,
+        after: usize,
+    ) -> io::Result<MDictNeighbours> {
+        Ok(MDictNeighbours {
+            before: self.keywords_before(key, before).await?,
+            exists: self.word_exists(key).await?,
+            after: self.keywords_after(key, after).await?,
+        })
+    }
+    /// Records of `key` with `@@@LINK=` redirects replaced by the records of their targets.
+    ///
+    /// At most `max_depth` redirects are followed in a row, `io::ErrorKind::InvalidData` is returned
+    /// if there are more, or there is a cycle, or the target doesn't exist.
+    async fn lookup_word_redirected(
+        &self,
+        key: &str,
+        max_depth: usize,
+    ) -> io::Result<MDictRedirected> {
+        let mut redirects = Redirects::new(key, self.lookup_word(key).await?, max_depth);
+        while let Some(target) = redirects.next_target()? {
+            let records = self.lookup_word(&target).await;
+            redirects.follow(target, records)?;
+        }
+        Ok(redirects.finish())
+    }
+    async fn lookup_resource(&self, key: &str) -> io::Result<Bytes> {
+        self.open_resource(key).await?.read().await
+    }
+    /// Find a resource without reading it, so it can be read as a stream or by range.
+    async fn open_resource(&self, key: &str) -> io::Result<MDictResource>;
+}
+
+/// Options of building an index
+///
+/// The progress of each file is reported in turn, the mdx file first, then the mdd files.
+#[derive(Clone, Default)]
+pub struct MDictBuildOptions {
+    progress: Option<Arc<dyn MDictProgressObserver>>,
+    cancel: Option<MDictCancelToken>,
+    full_text: bool,
+    chinese: Option<Arc<MDictChineseConverter>>,
+    pinyin: Option<Arc<MDictPinyin>>,
+    phonetic: Option<Arc<dyn MDictPhoneticEncoder>>,
+    rebuild: bool,
+    cache_dir: Option<PathBuf>,
+    mem_cache: bool,
+}
+
+impl MDictBuildOptions {
+    pub fn new() -> MDictBuildOptions {
+        MDictBuildOptions::default()
+    }
+
+    /// Report the progress of building index to `observer`.
+    pub fn progress(mut self, observer: Arc<dyn MDictProgressObserver>) -> MDictBuildOptions {
+        self.progress = Some(observer);
+        self
+    }
+
+    /// Stop building index with [`MDictCancelled`] when `cancel` is cancelled.
+    pub fn cancel(mut self, cancel: MDictCancelToken) -> MDictBuildOptions {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Build the full-text index of records, this is only used by the sqlite index.
+    ///
+    /// All records are decoded and stored as text without HTML tags, so building is slower and
+    /// the index is larger. An existing index without full-text index will be rebuilt.
+    pub fn full_text(mut self, enable: bool) -> MDictBuildOptions {
+        self.full_text = enable;
+        self
+    }
+
+    /// Also index keywords in Simplified Chinese by `converter`, so `normalized_candidates` finds
+    /// keywords in both scripts. An existing sqlite index without it will be rebuilt.
+    pub fn chinese(mut self, converter: Arc<MDictChineseConverter>) -> MDictBuildOptions {
+        self.chinese = Some(converter);
+        self
+    }
+
+    /// Index Chinese keywords by pinyin of `table` for `pinyin_search`.
+    /// An existing sqlite index without it will be rebuilt.
+    pub fn pinyin(mut self, table: Arc<MDictPinyin>) -> MDictBuildOptions {
+        self.pinyin = Some(table);
+        self
+    }
+
+    /// Index keywords by phonetic codes of `encoder` for `phonetic_search`.
+    /// An existing sqlite index without it or with another encoder will be rebuilt.
+    pub fn phonetic(mut self, encoder: Arc<dyn MDictPhoneticEncoder>) -> MDictBuildOptions {
+        self.phonetic = Some(encoder);
+        self
+    }
+
+    /// Build the sqlite index, or the cache of a memory index, even if an up-to-date one is built.
+    ///
+    /// The new index is built to a temporary file which replaces the old one at last, so an
+    /// opened index of the old file can still be used.
+    pub fn rebuild(mut self, enable: bool) -> MDictBuildOptions {
+        self.rebuild = enable;
+        self
+    }
+
+    /// Directory of the sqlite index and the cache of memory indexes, the default is
+    /// `$XDG_CACHE_HOME/mdict_rs` or `~/.cache/mdict_rs`, so dictionaries on read-only media can
+    /// be indexed.
+    ///
+    /// Files are named by the mdx file and a hash of the identities of the mdx and mdd files,
+    /// like `oxford-0123456789abcdef.db`, see [`MDictFileIdentity`]. The old file of a changed
+    /// dictionary is removed after the new one is built.
+    pub fn cache_dir<P: AsRef<Path>>(mut self, dir: P) -> MDictBuildOptions {
+        self.cache_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Save the keywords of [`MDictMemIndex`] to the cache directory, and load them instead of
+    /// decoding the files next time if the files are not changed.
+    pub fn mem_cache(mut self, enable: bool) -> MDictBuildOptions {
+        self.mem_cache = enable;
+        self
+    }
+
+    fn cache_dir_or_default(&self) -> io::Result<PathBuf> {
+        match &self.cache_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => default_cache_dir(),
+        }
+    }
+
+    // The cache file of `mdx_file` with extension `ext`, named by the identity of the files.
+    pub(crate) fn cache_file(&self, mdx_file: &Path, ext: &str) -> io::Result<PathBuf> {
+        let identities = dictionary_identities(mdx_file)?;
+        Ok(self.cache_dir_or_default()?.join(format!(
+            "{}-{:016x}.{}",
+            file_stem(mdx_file),
+            identity_hash(&identities),
+            ext
+        )))
+    }
+
+    // Other cache files with extension `ext` which may be built from `mdx_file` before it's
+    // changed. They may also belong to another dictionary with the same name, so the caller
+    // checks the path recorded in them.
+    pub(crate) fn stale_cache_files(
+        &self,
+        mdx_file: &Path,
+        ext: &str,
+        keep: &Path,
+    ) -> Vec<PathBuf> {
+        let dir = match self.cache_dir_or_default() {
+            Ok(dir) => dir,
+            Err(_) => return Vec::new(),
+        };
+        let prefix = format!("{}-", file_stem(mdx_file));
+        let suffix = format!(".{}", ext);
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path != keep)
+            .filter(|path| {
+                let name = match path.file_name().and_then(|n| n.to_str()) {
+                    Some(name) => name,
+                    None => return false,
+                };
+                // the hash is 16 hex digits
+                name.strip_prefix(prefix.as_str())
+                    .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                    .map_or(false, |hash| {
+                        hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+                    })
+            })
+            .collect()
+    }
+
+    pub(crate) fn open(&self, file: &Path, mode: MDictMode) -> io::Result<MDictIndex<File>> {
+        let mut mdict = MDictIndex::new(OpenOptions::new().read(true).open(file)?, mode)?;
+        if let Some(observer) = &self.progress {
+            mdict = mdict.with_progress(observer.clone());
+        }
+        if let Some(cancel) = &self.cancel {
+            mdict = mdict.with_cancel(cancel.clone());
+        }
+        Ok(mdict)
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn report(&self, progress: &MDictProgress) -> io::Result<()> {
+        if let Some(cancel) = &self.cancel {
+            cancel.check()?;
+        }
+        if let Some(observer) = &self.progress {
+            observer.on_progress(progress);
+        }
+        Ok(())
+    }
 }
 
 pub struct MDictMemIndex {
     mdx_index: PatriciaMap<MDictRecordIndex>,
+    // the second and later records of duplicate keywords, which are rare
+    mdx_duplicates: HashMap<String, Vec<MDictRecordIndex>>,
+    // normalized keywords to original keywords, only if they are different
+    normalized: HashMap<String, Vec<String>>,
+    chinese: Option<Arc<MDictChineseConverter>>,
+    // pinyin without tones to the pinyin with tones and keywords
+    pinyin: HashMap<String, Vec<(String, String)>>,
+    // phonetic codes to keywords
+    phonetic: HashMap<String, Vec<String>>,
+    phonetic_encoder: Option<Arc<dyn MDictPhoneticEncoder>>,
     mdx_block: Vec<MDictRecordBlockIndex>,
     mdx_file: PathBuf,
     mdd_index: PatriciaMap<(u8, MDictRecordIndex)>,
@@ -41,6 +655,14 @@ pub struct MDictMemIndex {
 
 impl MDictMemIndex {
     pub fn new<P: AsRef<Path>>(path: P) -> io::Result<MDictMemIndex> {
+        MDictMemIndex::with_options(path, &MDictBuildOptions::default())
+    }
+
+    /// Build the index with progress reporting or cancellation, see [`MDictBuildOptions`].
+    pub fn with_options<P: AsRef<Path>>(
+        path: P,
+        options: &MDictBuildOptions,
+    ) -> io::Result<MDictMemIndex> {
         let mdx_file = path.as_ref().canonicalize()?;
         if !mdx_file.is_file()
             || mdx_file
@@ -56,95 +678,341 @@ impl MDictMemIndex {
             ));
         }
         info!("mdx: {}", mdx_file.to_string_lossy());
-        let mut mdd_files = Vec::new();
-        let mdd0 = mdx_file.with_extension("mdd");
-        if mdd0.is_file() {
-            mdd_files.push(mdd0);
-            for i in 1.. {
-                let ext = format!("{}.mdd", i);
-                let mddi = mdx_file.with_extension(ext);
-                if mddi.is_file() {
-                    info!("mdd: {}", mddi.to_string_lossy());
-                    mdd_files.push(mddi);
-                } else {
-                    break;
+        let mdd_files = find_mdd_files(&mdx_file);
+        let keywords = load_mem_cache(&mdx_file, options);
+        let MemKeywords {
+            mdx_index,
+            mdx_duplicates,
+            mdx_block,
+            mdd_index,
+            mdd_blocks,
+        } = match keywords {
+            Some(keywords) => keywords,
+            None => {
+                let keywords = build_mem_keywords(&mdx_file, &mdd_files, options)?;
+                if options.mem_cache {
+                    save_mem_cache(&keywords, &mdx_file, options);
                 }
+                keywords
             }
-        }
-        let mut mdx = MDictIndex::new(
+        };
+        let header = MDictHeader::new(
             OpenOptions::new().read(true).open(&mdx_file)?,
             MDictMode::Mdx,
         )?;
-        let (mdx_block, mdx_keys) = mdx.make_index()?;
         let now = std::time::Instant::now();
-        let mdx_index = mdx_keys.into_iter().collect();
-        info!("Build Patricia Map for mdx in {:?}", now.elapsed());
-        let mut mdd_index = PatriciaMap::new();
-        let mut mdd_blocks = Vec::new();
-        for (i, file) in mdd_files.iter().enumerate() {
-            let mut mdd = MDictIndex::new(
-                OpenOptions::new().read(true).clone().open(file)?,
-                MDictMode::Mdd,
-            )?;
-            let (mdd_block, mdd_keys) = mdd.make_index()?;
-            let now = std::time::Instant::now();
-            mdd_index.extend(mdd_keys.into_iter().map(|(k, idx)| {
-                // process keys when building map rather than lookup
-                let (prefix, key) = k.split_at(1);
-                assert_eq!(prefix, "\\");
-                let key = key.replace('\\', "/");
-                (key, (i as u8, idx))
-            }));
-            mdd_blocks.push(mdd_block);
-            info!("Build Patricia Map for mdd {} in {:?}", i, now.elapsed());
-        }
+        let normalized = normalize_keywords(map_keywords(&mdx_index), options.chinese.as_deref());
+        info!("Build normalized index for mdx in {:?}", now.elapsed());
+        let pinyin = match &options.pinyin {
+            Some(table) => {
+                let now = std::time::Instant::now();
+                let pinyin = pinyin_index(map_keywords(&mdx_index), table);
+                info!("Build pinyin index for mdx in {:?}", now.elapsed());
+                pinyin
+            }
+            None => HashMap::new(),
+        };
+        let phonetic = match &options.phonetic {
+            Some(encoder) => {
+                let now = std::time::Instant::now();
+                let phonetic = phonetic_index(map_keywords(&mdx_index), encoder.as_ref());
+                info!("Build phonetic index for mdx in {:?}", now.elapsed());
+                phonetic
+            }
+            None => HashMap::new(),
+        };
         Ok(MDictMemIndex {
             mdx_index,
+            mdx_duplicates,
+            normalized,
+            chinese: options.chinese.clone(),
+            pinyin,
+            phonetic,
+            phonetic_encoder: options.phonetic.clone(),
             mdx_block,
             mdx_file,
             mdd_index,
             mdd_blocks,
             mdd_files,
-            header: mdx.into_header(),
+            header,
         })
     }
     pub fn keyword_iter(&self) -> impl Iterator<Item = String> + '_ {
-        self.mdx_index.keys().map(|k| String::from_utf8(k).unwrap())
+        map_keywords(&self.mdx_index)
     }
-}
-
-#[cfg(not(feature = "async"))]
-impl MDictLookup for MDictMemIndex {
-    fn word_exists(&self, key: &str) -> io::Result<bool> {
-        Ok(self.mdx_index.get(&key).is_some())
+    /// Keywords starting at the byte offset `pos` of `text`, longest first.
+    ///
+    /// This finds the words under the cursor in text without spaces, like Chinese and Japanese.
+    /// Nothing is found if `pos` isn't at a char boundary.
+    pub fn keywords_at(&self, text: &str, pos: usize) -> Vec<String> {
+        segment::keywords_at(&self.mdx_index, text, pos)
+    }
+    /// Split `text` into the longest keywords from left to right, chars which don't start
+    /// a keyword are segments of one char.
+    pub fn segment(&self, text: &str) -> Vec<MDictSegment> {
+        segment::segment(&self.mdx_index, text)
     }
-    fn lookup_word(&self, key: &str) -> io::Result<String> {
-        match self.mdx_index.get(&key) {
-            Some(idx) => {
-                let file = OpenOptions::new().read(true).open(&self.mdx_file)?;
-                let bytes = lookup(file, idx, &self.mdx_block[idx.block as usize])?;
-                let decoded = self.header.decode_string(bytes)?;
-                Ok(decoded)
+    // Records of a keyword in the order of the file
+    fn find_records(&self, key: &str) -> io::Result<Vec<MDictResource>> {
+        let first = self
+            .mdx_index
+            .get(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not found in index"))?;
+        let rest = self.mdx_duplicates.get(key).into_iter().flatten();
+        Ok(std::iter::once(first)
+            .chain(rest)
+            .map(|idx| {
+                let blocks = &self.mdx_block[idx.block as usize..];
+                MDictResource::new(self.mdx_file.clone(), *idx, blocks.iter().copied())
+            })
+            .collect())
+    }
+    // PatriciaMap can't seek to a key, so the keywords around `key` are found by the prefixes of
+    // `key`: the keywords after `key` start with a prefix of `key` and then a greater byte, or
+    // start with `key` itself.
+    fn keywords_after_key(&self, key: &str, limit: usize) -> Vec<String> {
+        let mut keywords = Vec::new();
+        if limit == 0 {
+            return keywords;
+        }
+        self.walk_keywords_after(key.as_bytes(), |keyword| {
+            keywords.push(String::from_utf8(keyword).unwrap());
+            keywords.len() < limit
+        });
+        keywords
+    }
+    // Call `f` with the keywords after `key` in order until it returns false.
+    fn walk_keywords_after<F: FnMut(Vec<u8>) -> bool>(&self, key: &[u8], mut f: F) {
+        for (keyword, _) in self.mdx_index.iter_prefix(key) {
+            if keyword.len() > key.len() && !f(keyword) {
+                return;
+            }
+        }
+        for len in (0..key.len()).rev() {
+            let mut prefix = key[..len].to_vec();
+            for b in key[len] as u16 + 1..=u8::MAX as u16 {
+                prefix.push(b as u8);
+                for (keyword, _) in self.mdx_index.iter_prefix(&prefix) {
+                    if !f(keyword) {
+                        return;
+                    }
+                }
+                prefix.pop();
             }
-            None => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Not found in index",
-            )),
         }
     }
-
-    fn lookup_resource(&self, key: &str) -> io::Result<Bytes> {
+    // The keywords before `key` are the prefixes of `key`, or start with a prefix of `key` and
+    // then a smaller byte, they are found from the nearest one, see `keywords_after_key`.
+    fn keywords_before_key(&self, key: &str, limit: usize) -> Vec<String> {
+        let key = key.as_bytes();
+        let mut keywords = Vec::new();
+        'prefixes: for len in (0..key.len()).rev() {
+            let mut prefix = key[..len].to_vec();
+            for b in (0..key[len]).rev() {
+                if keywords.len() == limit {
+                    break 'prefixes;
+                }
+                prefix.push(b);
+                self.last_keywords(&mut prefix, limit, &mut keywords);
+                prefix.pop();
+            }
+            if keywords.len() < limit && self.mdx_index.contains_key(&prefix) {
+                keywords.push(prefix);
+            }
+        }
+        keywords
+            .into_iter()
+            .rev()
+            .map(|keyword| String::from_utf8(keyword).unwrap())
+            .collect()
+    }
+    // Push the last keywords starting with `prefix` in reverse order until there are `limit`
+    // keywords. PatriciaMap can't iterate backwards, so a subtree with much more keywords than
+    // needed is split by the next byte from the greatest one, which costs about 256 lookups.
+    fn last_keywords(&self, prefix: &mut Vec<u8>, limit: usize, keywords: &mut Vec<Vec<u8>>) {
+        let wanted = limit - keywords.len();
+        let mut subtree: Vec<Vec<u8>> = self
+            .mdx_index
+            .iter_prefix(prefix.as_slice())
+            .map(|(keyword, _)| keyword)
+            .take(wanted + u8::MAX as usize + 1)
+            .collect();
+        if subtree.len() <= wanted + u8::MAX as usize {
+            let skipped = subtree.len().saturating_sub(wanted);
+            keywords.extend(subtree.drain(skipped..).rev());
+            return;
+        }
+        for b in (0..=u8::MAX).rev() {
+            if keywords.len() == limit {
+                return;
+            }
+            prefix.push(b);
+            self.last_keywords(prefix, limit, keywords);
+            prefix.pop();
+        }
+        if keywords.len() < limit && self.mdx_index.contains_key(prefix.as_slice()) {
+            keywords.push(prefix.clone());
+        }
+    }
+    fn suggest_keywords(&self, prefix: &str, limit: usize) -> Vec<String> {
+        if limit == 0 {
+            return Vec::new();
+        }
+        // Rank by the number of chars then the keyword, the exact match is the shortest one.
+        // Only the best `limit` keywords are kept in the heap.
+        let mut heap = BinaryHeap::with_capacity(limit + 1);
+        for (key, _) in self.mdx_index.iter_prefix(prefix.as_bytes()) {
+            let key = String::from_utf8(key).unwrap();
+            heap.push((key.chars().count(), key));
+            if heap.len() > limit {
+                heap.pop();
+            }
+        }
+        heap.into_sorted_vec().into_iter().map(|(_, k)| k).collect()
+    }
+    fn fuzzy_keywords(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> Vec<(String, usize)> {
+        let mut distance = fuzzy::EditDistance::new(key, max_distance, metric);
+        let mut result = Vec::new();
+        self.fuzzy_subtree(&mut Vec::new(), &mut distance, &mut result);
+        fuzzy::rank(result, limit)
+    }
+    // Find keywords starting with `prefix` within the distance. PatriciaMap doesn't expose its
+    // nodes, so a small subtree is walked like a trie by `EditDistance::search`, and a large one
+    // is split by the next byte, a subtree is skipped if its prefix can't match.
+    fn fuzzy_subtree(
+        &self,
+        prefix: &mut Vec<u8>,
+        distance: &mut fuzzy::EditDistance,
+        result: &mut Vec<(String, usize)>,
+    ) {
+        if !distance.can_match_prefix(prefix) {
+            return;
+        }
+        let mut subtree: Vec<String> = self
+            .mdx_index
+            .iter_prefix(prefix.as_slice())
+            .map(|(keyword, _)| String::from_utf8(keyword).unwrap())
+            .take(FUZZY_SUBTREE_SIZE + 1)
+            .collect();
+        let len = prefix.len();
+        let last = match subtree.last() {
+            Some(last) if subtree.len() > FUZZY_SUBTREE_SIZE => last.as_bytes()[len],
+            _ => {
+                result.extend(distance.search(subtree));
+                return;
+            }
+        };
+        // The keywords before the last next byte are complete subtrees, the last one may have
+        // more keywords, and the following bytes which can follow the prefix in UTF-8 are tried.
+        let end = subtree.partition_point(|k| k.len() == len || k.as_bytes()[len] < last);
+        result.extend(distance.search(subtree.drain(..end)));
+        let complete = std::str::from_utf8(prefix).is_ok();
+        let bytes = last..=if complete { 0xf4 } else { 0xbf };
+        for b in bytes.filter(|b| !complete || !(0x80..0xc2).contains(b)) {
+            prefix.push(b);
+            self.fuzzy_subtree(prefix, distance, result);
+            prefix.pop();
+        }
+    }
+    fn pattern_keywords(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>> {
+        let matcher = pattern.compile()?;
+        let prefix = matcher.prefix();
+        let mut keywords = Vec::new();
+        if limit == 0 {
+            return Ok(keywords);
+        }
+        match after {
+            // The next page starts after the last keyword instead of the first keyword with the
+            // prefix, and stops at the last keyword with the prefix.
+            Some(after) if after >= prefix => {
+                self.walk_keywords_after(after.as_bytes(), |keyword| {
+                    let keyword = String::from_utf8(keyword).unwrap();
+                    if !keyword.starts_with(prefix) {
+                        return false;
+                    }
+                    if matcher.is_match(&keyword) {
+                        keywords.push(keyword);
+                    }
+                    keywords.len() < limit
+                });
+            }
+            // Only search in the keywords starting with the prefix, which are after `after`.
+            _ => keywords.extend(
+                self.mdx_index
+                    .iter_prefix(prefix.as_bytes())
+                    .map(|(key, _)| String::from_utf8(key).unwrap())
+                    .filter(|k| matcher.is_match(k))
+                    .take(limit),
+            ),
+        }
+        Ok(keywords)
+    }
+    fn normalized_keywords(&self, key: &str) -> Vec<String> {
+        let key = normalized_key(key, self.chinese.as_deref());
+        let mut candidates = Vec::new();
+        if self.mdx_index.get(&key).is_some() {
+            candidates.push(key.clone());
+        }
+        if let Some(keywords) = self.normalized.get(&key) {
+            candidates.extend(keywords.iter().cloned());
+        }
+        candidates.sort();
+        candidates
+    }
+    fn pinyin_keywords(&self, query: &str, limit: usize) -> Vec<String> {
+        let query = match PinyinQuery::parse(query) {
+            Some(query) => query,
+            None => return Vec::new(),
+        };
+        let mut keywords: Vec<String> = self
+            .pinyin
+            .get(&query.toneless())
+            .into_iter()
+            .flatten()
+            .filter(|(pinyin, _)| query.matches(pinyin))
+            .map(|(_, keyword)| keyword.clone())
+            .collect();
+        keywords.sort();
+        keywords.dedup();
+        keywords.truncate(limit);
+        keywords
+    }
+    fn phonetic_keywords(&self, key: &str, limit: usize) -> Vec<(String, usize)> {
+        let encoder = match &self.phonetic_encoder {
+            Some(encoder) => encoder,
+            None => return Vec::new(),
+        };
+        let mut keywords = Vec::new();
+        let mut seen = HashSet::new();
+        for code in encoder.encode(key) {
+            for keyword in self.phonetic.get(&code).into_iter().flatten() {
+                if seen.insert(keyword) {
+                    keywords.push(keyword.clone());
+                }
+            }
+        }
+        phonetic::rank(key, keywords, limit)
+    }
+    fn find_resource(&self, key: &str) -> io::Result<MDictResource> {
         match self.mdd_index.get(key) {
             Some((num, idx)) => {
-                let file = OpenOptions::new()
-                    .read(true)
-                    .open(&self.mdd_files[*num as usize])?;
-                let data = lookup(
-                    file,
-                    idx,
-                    &self.mdd_blocks[*num as usize][idx.block as usize],
-                )?;
-                Ok(data)
+                let blocks = &self.mdd_blocks[*num as usize][idx.block as usize..];
+                Ok(MDictResource::new(
+                    self.mdd_files[*num as usize].clone(),
+                    *idx,
+                    blocks.iter().copied(),
+                ))
             }
             None => Err(io::Error::new(
                 io::ErrorKind::NotFound,
@@ -154,52 +1022,343 @@ impl MDictLookup for MDictMemIndex {
     }
 }
 
+// Keywords of a PatriciaMap, which are sorted by bytes
+fn map_keywords<V>(map: &PatriciaMap<V>) -> impl Iterator<Item = String> + '_ {
+    map.keys().map(|k| String::from_utf8(k).unwrap())
+}
+
+// Map normalized keywords to the original ones, a keyword which is already normalized is skipped.
+fn normalize_keywords(
+    keywords: impl Iterator<Item = String>,
+    chinese: Option<&MDictChineseConverter>,
+) -> HashMap<String, Vec<String>> {
+    let mut normalized: HashMap<String, Vec<String>> = HashMap::new();
+    for keyword in keywords {
+        let key = normalized_key(&keyword, chinese);
+        if key != keyword {
+            normalized.entry(key).or_default().push(keyword);
+        }
+    }
+    normalized
+}
+
+// Map pinyin without tones to the pinyin with tones and keywords.
+fn pinyin_index(
+    keywords: impl Iterator<Item = String>,
+    table: &MDictPinyin,
+) -> HashMap<String, Vec<(String, String)>> {
+    let mut index: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    for keyword in keywords {
+        for pinyin in table.keyword_pinyin(&keyword) {
+            index
+                .entry(pinyin::toneless(&pinyin))
+                .or_default()
+                .push((pinyin, keyword.clone()));
+        }
+    }
+    index
+}
+
+// Map phonetic codes to keywords.
+fn phonetic_index(
+    keywords: impl Iterator<Item = String>,
+    encoder: &dyn MDictPhoneticEncoder,
+) -> HashMap<String, Vec<String>> {
+    let mut index: HashMap<String, Vec<String>> = HashMap::new();
+    for keyword in keywords {
+        for code in encoder.encode(&keyword) {
+            index.entry(code).or_default().push(keyword.clone());
+        }
+    }
+    index
+}
+
+// Decode keywords of the mdx file and mdd files for `MDictMemIndex`.
+fn build_mem_keywords(
+    mdx_file: &Path,
+    mdd_files: &[PathBuf],
+    options: &MDictBuildOptions,
+) -> io::Result<MemKeywords> {
+    let mut mdx = options.open(mdx_file, MDictMode::Mdx)?;
+    let now = std::time::Instant::now();
+    let mut mdx_index = PatriciaMap::new();
+    let mut mdx_duplicates = HashMap::new();
+    let mdx_block = mdx.make_index_with(|key, index| {
+        if mdx_index.get(&key).is_some() {
+            mdx_duplicates
+                .entry(key)
+                .or_insert_with(Vec::new)
+                .push(index);
+        } else {
+            mdx_index.insert(key, index);
+        }
+        Ok(())
+    })?;
+    info!("Build Patricia Map for mdx in {:?}", now.elapsed());
+    let mut mdd_index = PatriciaMap::new();
+    let mut mdd_blocks = Vec::new();
+    for (i, file) in mdd_files.iter().enumerate() {
+        let mut mdd = options.open(file, MDictMode::Mdd)?;
+        let now = std::time::Instant::now();
+        let mdd_block = mdd.make_index_with(|key, index| {
+            mdd_index.insert(resource_name(&key)?, (i as u8, index));
+            Ok(())
+        })?;
+        mdd_blocks.push(mdd_block);
+        info!("Build Patricia Map for mdd {} in {:?}", i, now.elapsed());
+    }
+    Ok(MemKeywords {
+        mdx_index,
+        mdx_duplicates,
+        mdx_block,
+        mdd_index,
+        mdd_blocks,
+    })
+}
+
+fn dictionary_identities(mdx_file: &Path) -> io::Result<Vec<MDictFileIdentity>> {
+    let mut identities = vec![MDictFileIdentity::new(mdx_file)?];
+    for mdd_file in find_mdd_files(mdx_file) {
+        identities.push(MDictFileIdentity::new(mdd_file)?);
+    }
+    Ok(identities)
+}
+
+// Keywords of `MDictMemIndex` in the cache if it's enabled and up to date.
+fn load_mem_cache(mdx_file: &Path, options: &MDictBuildOptions) -> Option<MemKeywords> {
+    if !options.mem_cache || options.rebuild {
+        return None;
+    }
+    let result = options.cache_file(mdx_file, "mem").and_then(|cache_file| {
+        let identities = dictionary_identities(mdx_file)?;
+        MemKeywords::load(&cache_file, &identities)
+    });
+    match result {
+        Ok(keywords) => {
+            info!("Load keywords from cache");
+            Some(keywords)
+        }
+        Err(e) => {
+            info!("Cache of keywords is not used: {}", e);
+            None
+        }
+    }
+}
+
+// Errors are only logged, since the index can be used without the cache.
+fn save_mem_cache(keywords: &MemKeywords, mdx_file: &Path, options: &MDictBuildOptions) {
+    let result = options.cache_file(mdx_file, "mem").and_then(|cache_file| {
+        let identities = dictionary_identities(mdx_file)?;
+        keywords.save(&cache_file, mdx_file, &identities)?;
+        Ok(cache_file)
+    });
+    let cache_file = match result {
+        Ok(cache_file) => cache_file,
+        Err(e) => {
+            warn!("Failed to save keywords to cache: {}", e);
+            return;
+        }
+    };
+    // the cache of the dictionary before it's changed
+    for stale in options.stale_cache_files(mdx_file, "mem", &cache_file) {
+        if mem_cache::cached_mdx_file(&stale).ok().as_deref() == Some(mdx_file)
+            && std::fs::remove_file(&stale).is_ok()
+        {
+            info!("Remove old cache {}", stale.to_string_lossy());
+        }
+    }
+}
+
+fn default_cache_dir() -> io::Result<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")));
+    match cache_home {
+        Some(dir) => Ok(dir.join("mdict_rs")),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No cache directory, set $XDG_CACHE_HOME or the cache directory of build options",
+        )),
+    }
+}
+
+pub(crate) fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Find resource files of a mdx file: `{name}.mdd`, `{name}.1.mdd`, `{name}.2.mdd` ...
+pub(crate) fn find_mdd_files(mdx_file: &Path) -> Vec<PathBuf> {
+    let mut mdd_files = Vec::new();
+    let mdd0 = mdx_file.with_extension("mdd");
+    if mdd0.is_file() {
+        mdd_files.push(mdd0);
+        for i in 1.. {
+            let ext = format!("{}.mdd", i);
+            let mddi = mdx_file.with_extension(ext);
+            if mddi.is_file() {
+                mdd_files.push(mddi);
+            } else {
+                break;
+            }
+        }
+    }
+    for i in mdd_files.iter() {
+        info!("mdd: {}", i.to_string_lossy());
+    }
+    mdd_files
+}
+
+// Resource names in mdd files are like `\\image\\a.png`, map them to `image/a.png`.
+// This is done when building index rather than lookup.
+pub(crate) fn resource_name(key: &str) -> io::Result<String> {
+    if !key.starts_with('\\') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid resource name: {}", key),
+        ));
+    }
+    Ok(key[1..].replace('\\', "/"))
+}
+
+#[cfg(not(feature = "async"))]
+impl MDictLookup for MDictMemIndex {
+    fn word_exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.mdx_index.get(&key).is_some())
+    }
+    fn lookup_word(&self, key: &str) -> io::Result<Vec<String>> {
+        self.find_records(key)?
+            .iter()
+            .map(|record| self.header.decode_string(record.read()?))
+            .collect()
+    }
+    fn normalized_candidates(&self, key: &str) -> io::Result<Vec<String>> {
+        Ok(self.normalized_keywords(key))
+    }
+
+    fn keywords_after(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.keywords_after_key(key, limit))
+    }
+
+    fn keywords_before(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.keywords_before_key(key, limit))
+    }
+
+    fn suggest(&self, prefix: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.suggest_keywords(prefix, limit))
+    }
+
+    fn fuzzy_search(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> io::Result<Vec<(String, usize)>> {
+        Ok(self.fuzzy_keywords(key, max_distance, metric, limit))
+    }
+
+    fn search_pattern(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>> {
+        self.pattern_keywords(pattern, after, limit)
+    }
+
+    fn pinyin_search(&self, query: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.pinyin_keywords(query, limit))
+    }
+
+    fn phonetic_search(&self, key: &str, limit: usize) -> io::Result<Vec<(String, usize)>> {
+        Ok(self.phonetic_keywords(key, limit))
+    }
+
+    fn open_resource(&self, key: &str) -> io::Result<MDictResource> {
+        self.find_resource(key)
+    }
+}
+
 #[cfg(feature = "async")]
 use async_trait::async_trait;
 
+// Open a file for the asynchronous lookup.
+//
+// Without the "tokio-compat" feature, the file is opened with `async-fs`, which runs blocking
+// file operations on its own thread pool and works with any runtime.
+#[cfg(all(feature = "async", not(feature = "tokio-compat")))]
+async fn open_async(path: &Path) -> io::Result<async_fs::File> {
+    async_fs::File::open(path).await
+}
+
+// Open a file for the asynchronous lookup using tokio, must be called in a tokio runtime.
+#[cfg(feature = "tokio-compat")]
+async fn open_async(path: &Path) -> io::Result<mdict::compat::Compat<tokio::fs::File>> {
+    use mdict::compat::TokioAsyncReadCompatExt;
+    let file = tokio::fs::File::open(path).await?;
+    Ok(file.compat())
+}
+
 #[cfg(feature = "async")]
 #[async_trait]
 impl MDictAsyncLookup for MDictMemIndex {
     async fn word_exists(&self, key: &str) -> io::Result<bool> {
         Ok(self.mdx_index.get(&key).is_some())
     }
-    async fn lookup_word(&self, key: &str) -> io::Result<String> {
-        match self.mdx_index.get(&key) {
-            Some(idx) => {
-                let file: tokio::fs::File = tokio::fs::OpenOptions::new()
-                    .read(true)
-                    .open(&self.mdx_file)
-                    .await?;
-                let bytes = lookup(file, idx, &self.mdx_block[idx.block as usize]).await?;
-                let decoded = self.header.decode_string(bytes)?;
-                Ok(decoded)
-            }
-            None => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Not found in index",
-            )),
+    async fn lookup_word(&self, key: &str) -> io::Result<Vec<String>> {
+        let mut decoded = Vec::new();
+        for record in self.find_records(key)? {
+            decoded.push(self.header.decode_string(record.read().await?)?);
         }
+        Ok(decoded)
+    }
+    async fn normalized_candidates(&self, key: &str) -> io::Result<Vec<String>> {
+        Ok(self.normalized_keywords(key))
     }
 
-    async fn lookup_resource(&self, key: &str) -> io::Result<Bytes> {
-        match self.mdd_index.get(key) {
-            Some((num, idx)) => {
-                let file = tokio::fs::OpenOptions::new()
-                    .read(true)
-                    .open(&self.mdd_files[*num as usize])
-                    .await?;
-                let data = lookup(
-                    file,
-                    idx,
-                    &self.mdd_blocks[*num as usize][idx.block as usize],
-                )
-                .await?;
-                Ok(data)
-            }
-            None => Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                "Not found in index",
-            )),
-        }
+    async fn keywords_after(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.keywords_after_key(key, limit))
+    }
+
+    async fn keywords_before(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.keywords_before_key(key, limit))
+    }
+
+    async fn suggest(&self, prefix: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.suggest_keywords(prefix, limit))
+    }
+
+    async fn fuzzy_search(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> io::Result<Vec<(String, usize)>> {
+        Ok(self.fuzzy_keywords(key, max_distance, metric, limit))
+    }
+
+    async fn search_pattern(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>> {
+        self.pattern_keywords(pattern, after, limit)
+    }
+
+    async fn pinyin_search(&self, query: &str, limit: usize) -> io::Result<Vec<String>> {
+        Ok(self.pinyin_keywords(query, limit))
+    }
+
+    async fn phonetic_search(&self, key: &str, limit: usize) -> io::Result<Vec<(String, usize)>> {
+        Ok(self.phonetic_keywords(key, limit))
+    }
+
+    async fn open_resource(&self, key: &str) -> io::Result<MDictResource> {
+        self.find_resource(key)
     }
 }
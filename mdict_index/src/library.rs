@@ -0,0 +1,612 @@
+#[cfg(feature = "async")]
+use crate::MDictAsyncLookup;
+#[cfg(not(feature = "async"))]
+use crate::MDictLookup;
+#[cfg(feature = "async")]
+use futures_util::future::join_all;
+use log::{info, warn};
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+/// A dictionary in [`MDictLibrary`]
+pub struct MDictLibraryEntry<D> {
+    id: String,
+    name: String,
+    priority: i32,
+    groups: Vec<String>,
+    path: Option<PathBuf>,
+    // the files are changing, so the index can't be used until it's reloaded
+    disabled: bool,
+    // shared with snapshots of the library, so lookups can finish after it's replaced
+    index: Arc<D>,
+}
+
+impl<D> Clone for MDictLibraryEntry<D> {
+    fn clone(&self) -> MDictLibraryEntry<D> {
+        MDictLibraryEntry {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            priority: self.priority,
+            groups: self.groups.clone(),
+            path: self.path.clone(),
+            disabled: self.disabled,
+            index: self.index.clone(),
+        }
+    }
+}
+
+impl<D> MDictLibraryEntry<D> {
+    /// `id` identifies the dictionary in the library, it should be the same after restarting,
+    /// like the file name. `name` is shown to users.
+    pub fn new(id: &str, name: &str, index: D) -> MDictLibraryEntry<D> {
+        MDictLibraryEntry {
+            id: id.to_owned(),
+            name: name.to_owned(),
+            priority: 0,
+            groups: Vec::new(),
+            path: None,
+            disabled: false,
+            index: Arc::new(index),
+        }
+    }
+
+    /// Dictionaries with higher priority are the first in results, the default is 0.
+    pub fn with_priority(mut self, priority: i32) -> MDictLibraryEntry<D> {
+        self.priority = priority;
+        self
+    }
+
+    /// Add the dictionary to `group`, like "English" or "Medical".
+    pub fn with_group(mut self, group: &str) -> MDictLibraryEntry<D> {
+        if !self.in_group(group) {
+            self.groups.push(group.to_owned());
+        }
+        self
+    }
+
+    /// The mdx file of the dictionary, which is used to find it when the file is changed,
+    /// see [`MDictLibrary::reload`].
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> MDictLibraryEntry<D> {
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    pub fn in_group(&self, group: &str) -> bool {
+        self.groups.iter().any(|g| g == group)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether the dictionary is disabled by [`MDictLibrary::disable_path`].
+    pub fn is_disabled(&self) -> bool {
+        self.disabled
+    }
+
+    pub fn index(&self) -> &D {
+        &self.index
+    }
+}
+
+/// Result of a dictionary in [`MDictLibrary`]
+#[derive(Clone, Debug)]
+pub struct MDictLibraryResult<T> {
+    /// ID of the dictionary
+    pub id: String,
+    pub result: T,
+}
+
+/// Dictionaries looked up together, in the order of priority.
+///
+/// Lookups are done in all dictionaries of a group, or all dictionaries if the group is `None`.
+/// Errors other than [`io::ErrorKind::NotFound`] are logged, and the dictionary is skipped.
+/// Disabled dictionaries are skipped in lookups, [`get`](MDictLibrary::get) and
+/// [`dictionaries`](MDictLibrary::dictionaries).
+pub struct MDictLibrary<D> {
+    // sorted by priority, dictionaries with the same priority are in the order of adding
+    dictionaries: Vec<MDictLibraryEntry<D>>,
+}
+
+// Indexes are shared, so a copy is cheap
+impl<D> Clone for MDictLibrary<D> {
+    fn clone(&self) -> MDictLibrary<D> {
+        MDictLibrary {
+            dictionaries: self.dictionaries.clone(),
+        }
+    }
+}
+
+impl<D> MDictLibrary<D> {
+    pub fn new() -> MDictLibrary<D> {
+        MDictLibrary {
+            dictionaries: Vec::new(),
+        }
+    }
+
+    /// Add a dictionary, fails with [`io::ErrorKind::AlreadyExists`] if the ID is used.
+    pub fn add(&mut self, entry: MDictLibraryEntry<D>) -> io::Result<()> {
+        if self.contains(&entry.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Dictionary already exists: {}", entry.id),
+            ));
+        }
+        let i = self
+            .dictionaries
+            .partition_point(|d| d.priority >= entry.priority);
+        self.dictionaries.insert(i, entry);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<MDictLibraryEntry<D>> {
+        let i = self.dictionaries.iter().position(|d| d.id == id)?;
+        Some(self.dictionaries.remove(i))
+    }
+
+    /// Replace the index and name of the dictionary with the same path as `entry`, its ID,
+    /// priority and groups are kept, and it's enabled. `entry` is added if no dictionary has
+    /// the path.
+    pub fn reload(&mut self, entry: MDictLibraryEntry<D>) -> io::Result<()> {
+        let old = match &entry.path {
+            Some(path) => self
+                .dictionaries
+                .iter_mut()
+                .find(|d| d.path.as_ref() == Some(path)),
+            None => None,
+        };
+        match old {
+            Some(old) => {
+                info!("Reload dictionary {}", old.id);
+                old.name = entry.name;
+                old.index = entry.index;
+                old.disabled = false;
+                Ok(())
+            }
+            None => self.add(entry),
+        }
+    }
+
+    /// Remove dictionaries of the mdx file `path`, or all mdx files in the directory `path`.
+    pub fn remove_path<P: AsRef<Path>>(&mut self, path: P) -> Vec<MDictLibraryEntry<D>> {
+        let path = path.as_ref();
+        let (removed, kept) = self
+            .dictionaries
+            .drain(..)
+            .partition(|d| d.path.as_ref().map_or(false, |p| p.starts_with(path)));
+        self.dictionaries = kept;
+        removed
+    }
+
+    /// Disable dictionaries of the mdx file `path`, or all mdx files in the directory `path`,
+    /// until they are reloaded, returns their IDs. This is used when their files are changing,
+    /// so the old indexes are not used with the new files.
+    pub fn disable_path<P: AsRef<Path>>(&mut self, path: P) -> Vec<String> {
+        let path = path.as_ref();
+        self.dictionaries
+            .iter_mut()
+            .filter(|d| !d.disabled && d.path.as_ref().map_or(false, |p| p.starts_with(path)))
+            .map(|d| {
+                d.disabled = true;
+                d.id.clone()
+            })
+            .collect()
+    }
+
+    /// Enable dictionaries disabled by [`disable_path`](MDictLibrary::disable_path) without
+    /// reloading, returns their IDs. This is used when they can't be reloaded, so the old
+    /// indexes are used instead of nothing.
+    pub fn enable_path<P: AsRef<Path>>(&mut self, path: P) -> Vec<String> {
+        let path = path.as_ref();
+        self.dictionaries
+            .iter_mut()
+            .filter(|d| d.disabled && d.path.as_ref().map_or(false, |p| p.starts_with(path)))
+            .map(|d| {
+                d.disabled = false;
+                d.id.clone()
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: &str) -> Option<&MDictLibraryEntry<D>> {
+        self.dictionaries().find(|d| d.id == id)
+    }
+
+    /// Whether the ID is used, including disabled dictionaries.
+    pub fn contains(&self, id: &str) -> bool {
+        self.dictionaries.iter().any(|d| d.id == id)
+    }
+
+    /// The number of dictionaries, including disabled ones.
+    pub fn len(&self) -> usize {
+        self.dictionaries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dictionaries.is_empty()
+    }
+
+    /// All enabled dictionaries in the order of priority.
+    pub fn dictionaries(&self) -> impl Iterator<Item = &MDictLibraryEntry<D>> {
+        self.dictionaries.iter().filter(|d| !d.disabled)
+    }
+
+    /// Dictionaries in `group` in the order of priority, or all dictionaries if it's `None`.
+    pub fn select<'a>(
+        &'a self,
+        group: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a MDictLibraryEntry<D>> + 'a {
+        self.dictionaries()
+            .filter(move |d| group.map_or(true, |group| d.in_group(group)))
+    }
+
+    /// Names of all groups in alphabetical order.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups: Vec<&str> = self
+            .dictionaries
+            .iter()
+            .flat_map(|d| d.groups.iter().map(String::as_str))
+            .collect();
+        groups.sort_unstable();
+        groups.dedup();
+        groups
+    }
+}
+
+impl<D> Default for MDictLibrary<D> {
+    fn default() -> MDictLibrary<D> {
+        MDictLibrary::new()
+    }
+}
+
+/// A library shared by lookups and [`mdict_watch`](crate::mdict_watch), which replaces
+/// dictionaries while it's used.
+///
+/// A lookup uses a snapshot from [`load`](MDictSharedLibrary::load), which is not changed by
+/// updates, so indexes of replaced dictionaries are dropped after lookups using them finish.
+pub struct MDictSharedLibrary<D> {
+    library: RwLock<Arc<MDictLibrary<D>>>,
+}
+
+impl<D> MDictSharedLibrary<D> {
+    pub fn new(library: MDictLibrary<D>) -> MDictSharedLibrary<D> {
+        MDictSharedLibrary {
+            library: RwLock::new(Arc::new(library)),
+        }
+    }
+
+    /// The current library.
+    pub fn load(&self) -> Arc<MDictLibrary<D>> {
+        self.library.read().unwrap().clone()
+    }
+
+    /// Change a copy of the library by `f`, then replace the current library with it.
+    pub fn update<T, F: FnOnce(&mut MDictLibrary<D>) -> T>(&self, f: F) -> T {
+        let mut current = self.library.write().unwrap();
+        let mut library = MDictLibrary::clone(&current);
+        let result = f(&mut library);
+        *current = Arc::new(library);
+        result
+    }
+}
+
+// Keep the found results and log errors
+fn collect<T>(
+    what: &str,
+    key: &str,
+    results: Vec<(&str, io::Result<T>)>,
+) -> Vec<MDictLibraryResult<T>> {
+    results
+        .into_iter()
+        .filter_map(|(id, result)| match result {
+            Ok(result) => Some(MDictLibraryResult {
+                id: id.to_owned(),
+                result,
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("{} {} in {} failed: {}", what, key, id, e);
+                }
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "async"))]
+impl<D> MDictLibrary<D> {
+    /// Look up `key` in each dictionary by `f`, like a combination of lookups with fallbacks.
+    pub fn lookup_with<T, F>(
+        &self,
+        key: &str,
+        group: Option<&str>,
+        f: F,
+    ) -> Vec<MDictLibraryResult<T>>
+    where
+        F: Fn(&D) -> io::Result<T>,
+    {
+        let results = self.select(group).map(|d| (d.id(), f(&d.index))).collect();
+        collect("lookup", key, results)
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl<D: MDictLookup> MDictLibrary<D> {
+    /// Records of `key` in each dictionary which has it.
+    pub fn lookup_word(
+        &self,
+        key: &str,
+        group: Option<&str>,
+    ) -> Vec<MDictLibraryResult<Vec<String>>> {
+        let results = self
+            .select(group)
+            .map(|d| (d.id(), d.index.lookup_word(key)))
+            .collect();
+        collect("lookup", key, results)
+    }
+
+    /// Whether `key` exists in each dictionary.
+    pub fn word_exists(&self, key: &str, group: Option<&str>) -> Vec<MDictLibraryResult<bool>> {
+        let results = self
+            .select(group)
+            .map(|d| (d.id(), d.index.word_exists(key)))
+            .collect();
+        collect("word_exists", key, results)
+    }
+
+    /// At most `limit` keywords starting with `prefix` in each dictionary which has any.
+    pub fn suggest(
+        &self,
+        prefix: &str,
+        limit: usize,
+        group: Option<&str>,
+    ) -> Vec<MDictLibraryResult<Vec<String>>> {
+        let results = self
+            .select(group)
+            .map(|d| (d.id(), d.index.suggest(prefix, limit)))
+            .collect();
+        let mut results = collect("suggest", prefix, results);
+        results.retain(|r| !r.result.is_empty());
+        results
+    }
+}
+
+#[cfg(feature = "async")]
+impl<D> MDictLibrary<D> {
+    /// Look up `key` in each dictionary by `f`, like a combination of lookups with fallbacks,
+    /// dictionaries are looked up concurrently.
+    pub async fn lookup_with<'a, T, F, Fut>(
+        &'a self,
+        key: &str,
+        group: Option<&'a str>,
+        f: F,
+    ) -> Vec<MDictLibraryResult<T>>
+    where
+        F: Fn(&'a D) -> Fut,
+        Fut: Future<Output = io::Result<T>>,
+    {
+        let results = join_all(self.select(group).map(|d| {
+            let result = f(&d.index);
+            async move { (d.id(), result.await) }
+        }))
+        .await;
+        collect("lookup", key, results)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<D: MDictAsyncLookup + Sync> MDictLibrary<D> {
+    /// Records of `key` in each dictionary which has it, dictionaries are looked up concurrently.
+    pub async fn lookup_word(
+        &self,
+        key: &str,
+        group: Option<&str>,
+    ) -> Vec<MDictLibraryResult<Vec<String>>> {
+        let results = join_all(
+            self.select(group)
+                .map(|d| async move { (d.id(), d.index.lookup_word(key).await) }),
+        )
+        .await;
+        collect("lookup", key, results)
+    }
+
+    /// Whether `key` exists in each dictionary.
+    pub async fn word_exists(
+        &self,
+        key: &str,
+        group: Option<&str>,
+    ) -> Vec<MDictLibraryResult<bool>> {
+        let results = join_all(
+            self.select(group)
+                .map(|d| async move { (d.id(), d.index.word_exists(key).await) }),
+        )
+        .await;
+        collect("word_exists", key, results)
+    }
+
+    /// At most `limit` keywords starting with `prefix` in each dictionary which has any.
+    pub async fn suggest(
+        &self,
+        prefix: &str,
+        limit: usize,
+        group: Option<&str>,
+    ) -> Vec<MDictLibraryResult<Vec<String>>> {
+        let results = join_all(
+            self.select(group)
+                .map(|d| async move { (d.id(), d.index.suggest(prefix, limit).await) }),
+        )
+        .await;
+        let mut results = collect("suggest", prefix, results);
+        results.retain(|r| !r.result.is_empty());
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The record of a dictionary, or not found
+    struct Dummy(Option<&'static str>);
+
+    impl Dummy {
+        fn lookup(&self) -> io::Result<String> {
+            self.0
+                .map(str::to_owned)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Not found"))
+        }
+    }
+
+    fn entry(id: &str, record: Option<&'static str>) -> MDictLibraryEntry<Dummy> {
+        MDictLibraryEntry::new(id, id, Dummy(record))
+    }
+
+    fn ids(library: &MDictLibrary<Dummy>, group: Option<&str>) -> Vec<String> {
+        library.select(group).map(|d| d.id().to_owned()).collect()
+    }
+
+    #[cfg(not(feature = "async"))]
+    fn lookup(library: &MDictLibrary<Dummy>, group: Option<&str>) -> Vec<(String, String)> {
+        library
+            .lookup_with("key", group, Dummy::lookup)
+            .into_iter()
+            .map(|r| (r.id, r.result))
+            .collect()
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn lookup(library: &MDictLibrary<Dummy>, group: Option<&str>) -> Vec<(String, String)> {
+        futures_executor::block_on(library.lookup_with("key", group, |d| async move { d.lookup() }))
+            .into_iter()
+            .map(|r| (r.id, r.result))
+            .collect()
+    }
+
+    #[test]
+    fn sort_by_priority() {
+        let mut library = MDictLibrary::new();
+        library.add(entry("a", None)).unwrap();
+        library.add(entry("b", None).with_priority(5)).unwrap();
+        library.add(entry("c", None)).unwrap();
+        library.add(entry("d", None).with_priority(-1)).unwrap();
+        library.add(entry("e", None).with_priority(5)).unwrap();
+        // the same priority is in the order of adding
+        assert_eq!(ids(&library, None), ["b", "e", "a", "c", "d"]);
+    }
+
+    #[test]
+    fn reject_duplicate_ids() {
+        let mut library = MDictLibrary::new();
+        library.add(entry("a", None)).unwrap();
+        let e = library.add(entry("a", None).with_priority(1)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(library.len(), 1);
+    }
+
+    #[cfg(any(not(feature = "async"), feature = "sqlite"))]
+    #[test]
+    fn lookup_in_groups() {
+        let mut library = MDictLibrary::new();
+        library
+            .add(entry("en", Some("en")).with_group("English"))
+            .unwrap();
+        library
+            .add(
+                entry("zh", Some("zh"))
+                    .with_group("Chinese")
+                    .with_priority(1),
+            )
+            .unwrap();
+        library
+            .add(
+                entry("none", None)
+                    .with_group("English")
+                    .with_group("Chinese"),
+            )
+            .unwrap();
+        assert_eq!(ids(&library, Some("English")), ["en", "none"]);
+        assert_eq!(ids(&library, Some("Chinese")), ["zh", "none"]);
+        assert!(ids(&library, Some("Medical")).is_empty());
+        assert_eq!(library.groups(), ["Chinese", "English"]);
+        // not found is skipped
+        let found = |id: &str| (id.to_owned(), id.to_owned());
+        assert_eq!(lookup(&library, None), [found("zh"), found("en")]);
+        assert_eq!(lookup(&library, Some("English")), [found("en")]);
+    }
+
+    #[test]
+    fn reload_by_path() {
+        let mut library = MDictLibrary::new();
+        let path = Path::new("/dict/en/oxford.mdx");
+        library
+            .add(
+                entry("oxford", Some("old"))
+                    .with_path(path)
+                    .with_priority(3),
+            )
+            .unwrap();
+        assert_eq!(library.disable_path(path), ["oxford"]);
+        assert!(library.get("oxford").is_none());
+        assert!(library.contains("oxford"));
+        // the ID and the priority are kept
+        let new = MDictLibraryEntry::new("other", "Oxford 2", Dummy(Some("new"))).with_path(path);
+        library.reload(new).unwrap();
+        let reloaded = library.get("oxford").unwrap();
+        assert_eq!(reloaded.name(), "Oxford 2");
+        assert_eq!(reloaded.priority(), 3);
+        assert!(!reloaded.is_disabled());
+        assert_eq!(reloaded.index().lookup().unwrap(), "new");
+        assert!(!library.contains("other"));
+        // another path is added
+        library
+            .reload(entry("other", None).with_path("/dict/other.mdx"))
+            .unwrap();
+        assert_eq!(library.len(), 2);
+    }
+
+    #[test]
+    fn match_directory_prefixes() {
+        let mut library = MDictLibrary::new();
+        library
+            .add(entry("a", None).with_path("/dict/en/a.mdx"))
+            .unwrap();
+        library
+            .add(entry("b", None).with_path("/dict/en/b.mdx"))
+            .unwrap();
+        library
+            .add(entry("c", None).with_path("/dict/english/c.mdx"))
+            .unwrap();
+        library.add(entry("d", None)).unwrap();
+        // paths are matched by components, "/dict/en" doesn't contain "/dict/english"
+        assert_eq!(library.disable_path("/dict/en"), ["a", "b"]);
+        assert!(library.disable_path("/dict/en").is_empty());
+        assert_eq!(ids(&library, None), ["c", "d"]);
+        let removed = library.remove_path("/dict/en/a.mdx");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].id(), "a");
+        let removed = library.remove_path("/dict");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(ids(&library, None), ["d"]);
+    }
+}
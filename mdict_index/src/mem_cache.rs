@@ -0,0 +1,331 @@
+use crate::MDictFileIdentity;
+use mdict::{MDictRecordBlockIndex, MDictRecordIndex};
+use patricia_tree::PatriciaMap;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+// The cache of a `MDictMemIndex` starts with the magic, the version of this crate, the path of
+// the mdx file and the identities of files, then the records blocks and keywords of the mdx file
+// and mdd files. Numbers are little endian, strings are prefixed by the length in u32.
+//
+// Indexes derived from keywords, like normalized keywords, are not saved since they depend on
+// build options, they are built again after loading.
+const MAGIC: &[u8; 8] = b"MDICTMEM";
+
+// Keywords and records blocks of a mdx file and its mdd files
+pub(crate) struct MemKeywords {
+    pub(crate) mdx_index: PatriciaMap<MDictRecordIndex>,
+    pub(crate) mdx_duplicates: HashMap<String, Vec<MDictRecordIndex>>,
+    pub(crate) mdx_block: Vec<MDictRecordBlockIndex>,
+    pub(crate) mdd_index: PatriciaMap<(u8, MDictRecordIndex)>,
+    pub(crate) mdd_blocks: Vec<Vec<MDictRecordBlockIndex>>,
+}
+
+impl MemKeywords {
+    // Load the cache if it's built from files of `identities`.
+    pub(crate) fn load(cache_file: &Path, identities: &[MDictFileIdentity]) -> io::Result<Self> {
+        let mut reader = CacheReader(BufReader::new(File::open(cache_file)?));
+        reader.header()?;
+        let built = (0..reader.u32()?)
+            .map(|_| reader.string())
+            .collect::<io::Result<Vec<String>>>()?;
+        let identities: Vec<String> = identities.iter().map(|i| i.to_string()).collect();
+        if built != identities {
+            return Err(invalid_data("Dictionary files are changed"));
+        }
+        let mdx_block = reader.blocks()?;
+        let mut mdx_index = PatriciaMap::new();
+        for _ in 0..reader.u64()? {
+            let key = reader.string()?;
+            mdx_index.insert(key, reader.record()?);
+        }
+        let mut mdx_duplicates = HashMap::new();
+        for _ in 0..reader.u64()? {
+            let key = reader.string()?;
+            let records = (0..reader.u32()?)
+                .map(|_| reader.record())
+                .collect::<io::Result<Vec<_>>>()?;
+            mdx_duplicates.insert(key, records);
+        }
+        let mdd_blocks = (0..reader.u32()?)
+            .map(|_| reader.blocks())
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut mdd_index = PatriciaMap::new();
+        for _ in 0..reader.u64()? {
+            let key = reader.string()?;
+            let file = reader.u8()?;
+            if file as usize >= mdd_blocks.len() {
+                return Err(invalid_data("Invalid mdd file of resource"));
+            }
+            mdd_index.insert(key, (file, reader.record()?));
+        }
+        Ok(MemKeywords {
+            mdx_index,
+            mdx_duplicates,
+            mdx_block,
+            mdd_index,
+            mdd_blocks,
+        })
+    }
+
+    // Save to a temporary file which replaces `cache_file` at last.
+    pub(crate) fn save(
+        &self,
+        cache_file: &Path,
+        mdx_file: &Path,
+        identities: &[MDictFileIdentity],
+    ) -> io::Result<()> {
+        if let Some(dir) = cache_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let build_file = cache_file.with_extension("mem.build");
+        let result = self.write(&build_file, mdx_file, identities);
+        if result.is_err() {
+            let _ = fs::remove_file(&build_file);
+            return result;
+        }
+        fs::rename(&build_file, cache_file)
+    }
+
+    fn write(
+        &self,
+        build_file: &Path,
+        mdx_file: &Path,
+        identities: &[MDictFileIdentity],
+    ) -> io::Result<()> {
+        let mut writer = CacheWriter(BufWriter::new(File::create(build_file)?));
+        writer.0.write_all(MAGIC)?;
+        writer.string(env!("CARGO_PKG_VERSION").as_bytes())?;
+        writer.string(mdx_file.to_string_lossy().as_bytes())?;
+        writer.u32(identities.len() as u32)?;
+        for identity in identities {
+            writer.string(identity.to_string().as_bytes())?;
+        }
+        writer.blocks(&self.mdx_block)?;
+        writer.u64(self.mdx_index.len() as u64)?;
+        for (key, record) in self.mdx_index.iter() {
+            writer.string(&key)?;
+            writer.record(record)?;
+        }
+        writer.u64(self.mdx_duplicates.len() as u64)?;
+        for (key, records) in self.mdx_duplicates.iter() {
+            writer.string(key.as_bytes())?;
+            writer.u32(records.len() as u32)?;
+            for record in records {
+                writer.record(record)?;
+            }
+        }
+        writer.u32(self.mdd_blocks.len() as u32)?;
+        for blocks in self.mdd_blocks.iter() {
+            writer.blocks(blocks)?;
+        }
+        writer.u64(self.mdd_index.len() as u64)?;
+        for (key, (file, record)) in self.mdd_index.iter() {
+            writer.string(&key)?;
+            writer.0.write_all(&[*file])?;
+            writer.record(record)?;
+        }
+        let file = writer.0.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+    }
+}
+
+// The path of the mdx file which the cache is built from.
+pub(crate) fn cached_mdx_file(cache_file: &Path) -> io::Result<PathBuf> {
+    let mut reader = CacheReader(BufReader::new(File::open(cache_file)?));
+    reader.header()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+struct CacheWriter(BufWriter<File>);
+
+impl CacheWriter {
+    fn u32(&mut self, n: u32) -> io::Result<()> {
+        self.0.write_all(&n.to_le_bytes())
+    }
+
+    fn u64(&mut self, n: u64) -> io::Result<()> {
+        self.0.write_all(&n.to_le_bytes())
+    }
+
+    fn string(&mut self, s: &[u8]) -> io::Result<()> {
+        self.u32(s.len() as u32)?;
+        self.0.write_all(s)
+    }
+
+    fn record(&mut self, record: &MDictRecordIndex) -> io::Result<()> {
+        self.u32(record.block)?;
+        self.u32(record.offset)?;
+        self.u32(record.len)
+    }
+
+    fn blocks(&mut self, blocks: &[MDictRecordBlockIndex]) -> io::Result<()> {
+        self.u32(blocks.len() as u32)?;
+        for block in blocks {
+            self.u64(block.offset)?;
+            self.u64(block.comp_size)?;
+            self.u64(block.uncomp_size)?;
+        }
+        Ok(())
+    }
+}
+
+struct CacheReader(BufReader<File>);
+
+impl CacheReader {
+    // Check the magic and the version, and return the path of the mdx file.
+    fn header(&mut self) -> io::Result<PathBuf> {
+        let mut magic = [0; 8];
+        self.0.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a cache of mdict_index"));
+        }
+        if self.string()? != env!("CARGO_PKG_VERSION") {
+            return Err(invalid_data("Cache is built by another version"));
+        }
+        self.string().map(PathBuf::from)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.0.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.0.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        self.0.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let mut buf = Vec::new();
+        let len = self.u32()? as u64;
+        // a truncated file doesn't allocate the length
+        if (&mut self.0).take(len).read_to_end(&mut buf)? as u64 != len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn record(&mut self) -> io::Result<MDictRecordIndex> {
+        Ok(MDictRecordIndex {
+            block: self.u32()?,
+            offset: self.u32()?,
+            len: self.u32()?,
+        })
+    }
+
+    fn blocks(&mut self) -> io::Result<Vec<MDictRecordBlockIndex>> {
+        (0..self.u32()?)
+            .map(|_| {
+                Ok(MDictRecordBlockIndex {
+                    offset: self.u64()?,
+                    comp_size: self.u64()?,
+                    uncomp_size: self.u64()?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(block: u32, offset: u32, len: u32) -> MDictRecordIndex {
+        MDictRecordIndex { block, offset, len }
+    }
+
+    fn block(offset: u64) -> MDictRecordBlockIndex {
+        MDictRecordBlockIndex {
+            offset,
+            comp_size: 10,
+            uncomp_size: 20,
+        }
+    }
+
+    fn identity(mtime: u128) -> MDictFileIdentity {
+        MDictFileIdentity {
+            size: 100,
+            mtime,
+            checksum: 0x1234,
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut mdx_index = PatriciaMap::new();
+        mdx_index.insert("apple", record(0, 0, 5));
+        mdx_index.insert("banana", record(0, 5, 7));
+        let mut mdx_duplicates = HashMap::new();
+        mdx_duplicates.insert(String::from("apple"), vec![record(1, 0, 3)]);
+        let mut mdd_index = PatriciaMap::new();
+        mdd_index.insert("image/a.png", (1, record(0, 0, 100)));
+        let keywords = MemKeywords {
+            mdx_index,
+            mdx_duplicates,
+            mdx_block: vec![block(100), block(110)],
+            mdd_index,
+            mdd_blocks: vec![vec![block(50)], vec![block(60)]],
+        };
+        let dir = std::env::temp_dir().join(format!("mdict_mem_cache_{}", std::process::id()));
+        let file = dir.join("test-0123456789abcdef.mem");
+        let identities = [identity(1), identity(2)];
+        keywords
+            .save(&file, Path::new("/dict/test.mdx"), &identities)
+            .unwrap();
+        assert_eq!(cached_mdx_file(&file).unwrap(), Path::new("/dict/test.mdx"));
+
+        let loaded = MemKeywords::load(&file, &identities).unwrap();
+        let fields = |r: &MDictRecordIndex| (r.block, r.offset, r.len);
+        let block_fields = |blocks: &[MDictRecordBlockIndex]| {
+            blocks
+                .iter()
+                .map(|b| (b.offset, b.comp_size, b.uncomp_size))
+                .collect::<Vec<_>>()
+        };
+        let records = |k: &MemKeywords| {
+            k.mdx_index
+                .iter()
+                .map(|(key, r)| (key, fields(r)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(records(&loaded), records(&keywords));
+        let duplicates = loaded.mdx_duplicates.get("apple").unwrap();
+        assert_eq!(
+            duplicates.iter().map(fields).collect::<Vec<_>>(),
+            vec![(1, 0, 3)]
+        );
+        assert_eq!(
+            block_fields(&loaded.mdx_block),
+            block_fields(&keywords.mdx_block)
+        );
+        assert_eq!(loaded.mdd_blocks.len(), 2);
+        assert_eq!(block_fields(&loaded.mdd_blocks[1]), vec![(60, 10, 20)]);
+        let (file_no, resource) = loaded.mdd_index.get("image/a.png").unwrap();
+        assert_eq!((*file_no, fields(resource)), (1, (0, 0, 100)));
+        // a changed file is found by the identities
+        let err = MemKeywords::load(&file, &[identity(1), identity(3)]).err();
+        assert_eq!(err.map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+
+        // a truncated file is an error
+        let bytes = fs::read(&file).unwrap();
+        fs::write(&file, &bytes[..bytes.len() - 4]).unwrap();
+        assert!(MemKeywords::load(&file, &identities).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
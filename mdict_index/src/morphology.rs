@@ -0,0 +1,248 @@
+// Irregular inflected forms and their base forms, in alphabetical order of the inflected form
+const IRREGULAR: &[(&str, &[&str])] = &[
+    ("am", &["be"]),
+    ("analyses", &["analysis"]),
+    ("are", &["be"]),
+    ("ate", &["eat"]),
+    ("bade", &["bid"]),
+    ("been", &["be"]),
+    ("began", &["begin"]),
+    ("begun", &["begin"]),
+    ("best", &["good", "well"]),
+    ("better", &["good", "well"]),
+    ("bitten", &["bite"]),
+    ("blew", &["blow"]),
+    ("blown", &["blow"]),
+    ("bore", &["bear"]),
+    ("born", &["bear"]),
+    ("borne", &["bear"]),
+    ("bought", &["buy"]),
+    ("broke", &["break"]),
+    ("broken", &["break"]),
+    ("brought", &["bring"]),
+    ("built", &["build"]),
+    ("came", &["come"]),
+    ("caught", &["catch"]),
+    ("children", &["child"]),
+    ("chose", &["choose"]),
+    ("chosen", &["choose"]),
+    ("crises", &["crisis"]),
+    ("criteria", &["criterion"]),
+    ("did", &["do"]),
+    ("does", &["do"]),
+    ("done", &["do"]),
+    ("drank", &["drink"]),
+    ("drawn", &["draw"]),
+    ("drew", &["draw"]),
+    ("driven", &["drive"]),
+    ("drove", &["drive"]),
+    ("drunk", &["drink"]),
+    ("eaten", &["eat"]),
+    ("fallen", &["fall"]),
+    ("farther", &["far"]),
+    ("farthest", &["far"]),
+    ("feet", &["foot"]),
+    ("fell", &["fall"]),
+    ("felt", &["feel"]),
+    ("fled", &["flee"]),
+    ("flew", &["fly"]),
+    ("flown", &["fly"]),
+    ("forgot", &["forget"]),
+    ("forgotten", &["forget"]),
+    ("fought", &["fight"]),
+    ("found", &["find"]),
+    ("froze", &["freeze"]),
+    ("frozen", &["freeze"]),
+    ("further", &["far"]),
+    ("furthest", &["far"]),
+    ("gave", &["give"]),
+    ("geese", &["goose"]),
+    ("given", &["give"]),
+    ("gone", &["go"]),
+    ("got", &["get"]),
+    ("gotten", &["get"]),
+    ("grew", &["grow"]),
+    ("grown", &["grow"]),
+    ("had", &["have"]),
+    ("has", &["have"]),
+    ("heard", &["hear"]),
+    ("held", &["hold"]),
+    ("hid", &["hide"]),
+    ("hidden", &["hide"]),
+    ("is", &["be"]),
+    ("kept", &["keep"]),
+    ("knew", &["know"]),
+    ("known", &["know"]),
+    ("laid", &["lay"]),
+    ("lain", &["lie"]),
+    ("lay", &["lie"]),
+    ("least", &["little"]),
+    ("led", &["lead"]),
+    ("left", &["leave"]),
+    ("less", &["little"]),
+    ("lice", &["louse"]),
+    ("lost", &["lose"]),
+    ("made", &["make"]),
+    ("meant", &["mean"]),
+    ("men", &["man"]),
+    ("met", &["meet"]),
+    ("mice", &["mouse"]),
+    ("more", &["many", "much"]),
+    ("most", &["many", "much"]),
+    ("oxen", &["ox"]),
+    ("paid", &["pay"]),
+    ("people", &["person"]),
+    ("phenomena", &["phenomenon"]),
+    ("ran", &["run"]),
+    ("rang", &["ring"]),
+    ("risen", &["rise"]),
+    ("rode", &["ride"]),
+    ("rose", &["rise"]),
+    ("rung", &["ring"]),
+    ("said", &["say"]),
+    ("sang", &["sing"]),
+    ("sank", &["sink"]),
+    ("sat", &["sit"]),
+    ("saw", &["see"]),
+    ("seen", &["see"]),
+    ("sent", &["send"]),
+    ("shook", &["shake"]),
+    ("shot", &["shoot"]),
+    ("slept", &["sleep"]),
+    ("sold", &["sell"]),
+    ("sought", &["seek"]),
+    ("spent", &["spend"]),
+    ("spoke", &["speak"]),
+    ("spoken", &["speak"]),
+    ("stole", &["steal"]),
+    ("stolen", &["steal"]),
+    ("stood", &["stand"]),
+    ("struck", &["strike"]),
+    ("sung", &["sing"]),
+    ("sunk", &["sink"]),
+    ("swam", &["swim"]),
+    ("swum", &["swim"]),
+    ("taken", &["take"]),
+    ("taught", &["teach"]),
+    ("teeth", &["tooth"]),
+    ("thought", &["think"]),
+    ("threw", &["throw"]),
+    ("thrown", &["throw"]),
+    ("told", &["tell"]),
+    ("took", &["take"]),
+    ("understood", &["understand"]),
+    ("was", &["be"]),
+    ("went", &["go"]),
+    ("were", &["be"]),
+    ("women", &["woman"]),
+    ("won", &["win"]),
+    ("wore", &["wear"]),
+    ("worn", &["wear"]),
+    ("worse", &["bad", "badly"]),
+    ("worst", &["bad", "badly"]),
+    ("written", &["write"]),
+    ("wrote", &["write"]),
+];
+
+// Suffixes of regular inflections and their replacements, longer suffixes are tried first
+const RULES: &[(&str, &str)] = &[
+    // adjectives
+    ("iest", "y"),
+    ("est", "e"),
+    ("est", ""),
+    ("ier", "y"),
+    ("er", "e"),
+    ("er", ""),
+    // verbs
+    ("ying", "ie"),
+    ("ing", "e"),
+    ("ing", ""),
+    ("ied", "y"),
+    ("ed", "e"),
+    ("ed", ""),
+    // nouns and verbs
+    ("ves", "f"),
+    ("ves", "fe"),
+    ("ies", "y"),
+    ("ches", "ch"),
+    ("shes", "sh"),
+    ("sses", "ss"),
+    ("xes", "x"),
+    ("zes", "z"),
+    ("oes", "o"),
+    ("es", "e"),
+    ("s", ""),
+];
+
+// The last consonant is doubled before these suffixes, like "running" and "bigger"
+const DOUBLING: &[&str] = &["est", "er", "ing", "ed"];
+
+/// Possible base forms of an inflected English word, most likely ones are the first.
+///
+/// Irregular forms like "mice" and "better" are found in a table, regular forms like "running"
+/// and "cities" are found by stripping suffixes. The candidates may not be words, so they should
+/// be looked up in a dictionary. The word itself is not included.
+pub fn mdict_deinflect(word: &str) -> Vec<String> {
+    let mut candidates = Vec::new();
+    deinflect(word, &mut candidates);
+    let lower = word.to_lowercase();
+    if lower != word {
+        deinflect(&lower, &mut candidates);
+    }
+    candidates.retain(|c| c != word);
+    candidates
+}
+
+fn deinflect(word: &str, candidates: &mut Vec<String>) {
+    let mut push = |candidate: String| {
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    };
+    if let Ok(i) = IRREGULAR.binary_search_by(|(form, _)| (*form).cmp(word)) {
+        for base in IRREGULAR[i].1 {
+            push(base.to_string());
+        }
+    }
+    for (suffix, replace) in RULES {
+        let stem = match word.strip_suffix(suffix) {
+            // a stem needs at least two chars, so "is" isn't "i" + "s"
+            Some(stem) if stem.chars().count() > 1 => stem,
+            _ => continue,
+        };
+        push(format!("{}{}", stem, replace));
+        if replace.is_empty() && DOUBLING.contains(suffix) {
+            let mut chars = stem.chars().rev();
+            if let (Some(last), Some(prev)) = (chars.next(), chars.next()) {
+                if last == prev && !"aeiou".contains(last) {
+                    push(stem[..stem.len() - last.len_utf8()].to_owned());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn irregular_is_sorted() {
+        // irregular forms are found by binary search
+        for pair in IRREGULAR.windows(2) {
+            assert!(pair[0].0 < pair[1].0, "{} >= {}", pair[0].0, pair[1].0);
+        }
+    }
+
+    #[test]
+    fn deinflect_words() {
+        let has = |word: &str, base: &str| mdict_deinflect(word).iter().any(|c| c == base);
+        assert!(has("running", "run"));
+        assert!(has("cities", "city"));
+        assert!(has("mice", "mouse"));
+        assert!(has("better", "good"));
+        assert!(has("Walked", "walk"));
+        assert!(!mdict_deinflect("is").contains(&"i".to_owned()));
+        assert!(!mdict_deinflect("run").contains(&"run".to_owned()));
+    }
+}
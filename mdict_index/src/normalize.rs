@@ -0,0 +1,42 @@
+use crate::chinese::{MDictChineseConverter, MDictChineseScript};
+use caseless::Caseless;
+use unicode_normalization::UnicodeNormalization;
+
+/// Normalize a keyword for case-, accent- and width-insensitive lookup.
+///
+/// This applies Unicode NFKC, case folding and strips diacritics,
+/// so "Café", "cafe" and full-width "ＣＡＦＥ" are all "cafe".
+/// Only combining diacritical marks (U+0300 to U+036F) are stripped, the voiced marks of kana
+/// and vowel signs of Indic scripts are kept, because they change the letter.
+pub fn mdict_normalize(key: &str) -> String {
+    key.nfkc()
+        .default_case_fold()
+        .nfkd()
+        .filter(|c| !('\u{300}'..='\u{36f}').contains(c))
+        .nfc()
+        .collect()
+}
+
+// The key of the normalized index, which is also converted to Simplified Chinese if `chinese` is given.
+pub(crate) fn normalized_key(key: &str, chinese: Option<&MDictChineseConverter>) -> String {
+    match chinese {
+        Some(converter) => mdict_normalize(&converter.convert(key, MDictChineseScript::Simplified)),
+        None => mdict_normalize(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_diacritics() {
+        assert_eq!(mdict_normalize("Café"), "cafe");
+        assert_eq!(mdict_normalize("ＣＡＦＥ"), "cafe");
+        assert_eq!(mdict_normalize("Ångström"), "angstrom");
+        assert_eq!(mdict_normalize("が"), "が");
+        assert_eq!(mdict_normalize("ぱ"), "ぱ");
+        assert_eq!(mdict_normalize("ｶﾞ"), "ガ");
+        assert_eq!(mdict_normalize("हिन्दी"), "हिन्दी");
+    }
+}
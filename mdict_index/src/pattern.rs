@@ -0,0 +1,158 @@
+use regex::Regex;
+use std::io;
+
+/// Pattern to search keywords
+#[derive(Clone, Debug)]
+pub enum MDictPattern {
+    /// Glob pattern matching the whole keyword, like `GLOB` of sqlite:
+    /// `*` matches any chars, `?` matches one char, `[...]` and `[^...]` match one char in or not in the set.
+    Glob(String),
+    /// Regular expression of the `regex` crate, which matches any part of the keyword unless it's anchored.
+    Regex(String),
+}
+
+// A compiled pattern
+pub(crate) struct PatternMatcher {
+    regex: Regex,
+    // literal prefix of all matched keywords
+    prefix: String,
+}
+
+impl MDictPattern {
+    pub(crate) fn compile(&self) -> io::Result<PatternMatcher> {
+        let (regex, prefix) = match self {
+            MDictPattern::Glob(glob) => (glob_to_regex(glob), glob_prefix(glob)),
+            MDictPattern::Regex(regex) => (regex.clone(), regex_prefix(regex)),
+        };
+        let regex =
+            Regex::new(&regex).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(PatternMatcher { regex, prefix })
+    }
+}
+
+impl PatternMatcher {
+    pub(crate) fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn is_match(&self, keyword: &str) -> bool {
+        keyword.starts_with(&self.prefix) && self.regex.is_match(keyword)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("(?s)^");
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                // `]` right after `[` or `[^` is a literal
+                let mut end = i + 1;
+                if chars.get(end) == Some(&'^') {
+                    end += 1;
+                }
+                if chars.get(end) == Some(&']') {
+                    end += 1;
+                }
+                while end < chars.len() && chars[end] != ']' {
+                    end += 1;
+                }
+                if end == chars.len() {
+                    // not a set
+                    regex.push_str(r"\[");
+                } else {
+                    regex.push('[');
+                    for (j, c) in chars[i + 1..end].iter().enumerate() {
+                        match c {
+                            '^' if j == 0 => regex.push('^'),
+                            '\\' | '[' | '&' | '~' => {
+                                regex.push('\\');
+                                regex.push(*c);
+                            }
+                            c => regex.push(*c),
+                        }
+                    }
+                    regex.push(']');
+                    i = end;
+                }
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+    regex.push('$');
+    regex
+}
+
+fn glob_prefix(glob: &str) -> String {
+    glob.chars()
+        .take_while(|c| !matches!(c, '*' | '?' | '['))
+        .collect()
+}
+
+// The literal prefix of a regex anchored by `^`, empty if it's not found simply.
+fn regex_prefix(regex: &str) -> String {
+    if !regex.starts_with('^') || regex.contains('|') {
+        return String::new();
+    }
+    let mut prefix: Vec<char> = Vec::new();
+    for c in regex[1..].chars() {
+        match c {
+            // the last char is optional or repeated
+            '?' | '*' | '{' => {
+                prefix.pop();
+                break;
+            }
+            '\\' | '.' | '+' | '(' | ')' | '[' | ']' | '}' | '^' | '$' => break,
+            c => prefix.push(c),
+        }
+    }
+    prefix.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(pattern: MDictPattern) -> PatternMatcher {
+        pattern.compile().unwrap()
+    }
+
+    #[test]
+    fn glob() {
+        let m = matcher(MDictPattern::Glob("app*e?".to_owned()));
+        assert_eq!(m.prefix(), "app");
+        assert!(m.is_match("apples"));
+        assert!(m.is_match("applet"));
+        assert!(!m.is_match("apple"));
+        assert!(!m.is_match("pineapples"));
+        let m = matcher(MDictPattern::Glob("[^a-c]at.[]]".to_owned()));
+        assert_eq!(m.prefix(), "");
+        assert!(m.is_match("hat.]"));
+        assert!(!m.is_match("cat.]"));
+        assert!(!m.is_match("hat-]"));
+        // not a set
+        let m = matcher(MDictPattern::Glob("a[b".to_owned()));
+        assert_eq!(m.prefix(), "a");
+        assert!(m.is_match("a[b"));
+    }
+
+    #[test]
+    fn regex() {
+        let m = matcher(MDictPattern::Regex("^colou?r".to_owned()));
+        assert_eq!(m.prefix(), "colo");
+        assert!(m.is_match("color"));
+        assert!(m.is_match("colourful"));
+        assert!(!m.is_match("discolor"));
+        let m = matcher(MDictPattern::Regex("ing$".to_owned()));
+        assert_eq!(m.prefix(), "");
+        assert!(m.is_match("running"));
+        assert_eq!(regex_prefix("^ab+c"), "ab");
+        assert_eq!(regex_prefix(r"^ab\.c"), "ab");
+        assert_eq!(regex_prefix("^ab|cd"), "");
+        assert!(MDictPattern::Regex("(".to_owned()).compile().is_err());
+    }
+}
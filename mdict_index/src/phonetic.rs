@@ -0,0 +1,792 @@
+use crate::fuzzy::{self, EditDistance, MDictDistance};
+
+/// Encoder of the phonetic index, words which sound alike have the same code.
+///
+/// [`MDictDoubleMetaphone`] and [`MDictSoundex`] are for English, other languages can have
+/// their own encoders.
+pub trait MDictPhoneticEncoder: Send + Sync {
+    /// The name is saved in the sqlite index, so the index is rebuilt if another encoder is used.
+    fn name(&self) -> &str;
+    /// Codes of `word`, or nothing if `word` can't be encoded.
+    fn encode(&self, word: &str) -> Vec<String>;
+}
+
+/// Double Metaphone by Lawrence Philips, which has a primary code and an alternate code
+/// for words of other origins, like "Schmidt" is "XMT" or "SMT".
+#[derive(Clone, Debug)]
+pub struct MDictDoubleMetaphone {
+    max_len: usize,
+}
+
+impl MDictDoubleMetaphone {
+    /// Codes of at most 4 chars, as the original algorithm.
+    pub fn new() -> MDictDoubleMetaphone {
+        MDictDoubleMetaphone { max_len: 4 }
+    }
+
+    /// Codes of at most `max_len` chars, longer codes have less false matches.
+    pub fn with_max_len(max_len: usize) -> MDictDoubleMetaphone {
+        MDictDoubleMetaphone { max_len }
+    }
+
+    /// The primary and alternate code of `word`, they are the same for most words.
+    pub fn codes(&self, word: &str) -> (String, String) {
+        let word = Word::new(word);
+        let mut codes = Codes {
+            primary: String::new(),
+            alternate: String::new(),
+            max_len: self.max_len,
+        };
+        // the first letter isn't pronounced, like "Knight"
+        let mut i = if word.is(0, &["GN", "KN", "PN", "WR", "PS"]) {
+            1
+        } else {
+            0
+        };
+        while !codes.is_complete() && i < word.len() {
+            i = match word.at(i) {
+                'A' | 'E' | 'I' | 'O' | 'U' | 'Y' => {
+                    if i == 0 {
+                        codes.push("A");
+                    }
+                    i + 1
+                }
+                'B' => {
+                    codes.push("P");
+                    word.skip_same(i)
+                }
+                'Ç' => {
+                    codes.push("S");
+                    i + 1
+                }
+                'C' => word.c(i, &mut codes),
+                'D' => word.d(i, &mut codes),
+                'F' => {
+                    codes.push("F");
+                    word.skip_same(i)
+                }
+                'G' => word.g(i, &mut codes),
+                'H' => word.h(i, &mut codes),
+                'J' => word.j(i, &mut codes),
+                'K' => {
+                    codes.push("K");
+                    word.skip_same(i)
+                }
+                'L' => word.l(i, &mut codes),
+                'M' => {
+                    codes.push("M");
+                    if word.m_is_doubled(i) {
+                        i + 2
+                    } else {
+                        i + 1
+                    }
+                }
+                'N' => {
+                    codes.push("N");
+                    word.skip_same(i)
+                }
+                'Ñ' => {
+                    codes.push("N");
+                    i + 1
+                }
+                'P' => {
+                    if word.at(i + 1) == 'H' {
+                        codes.push("F");
+                        i + 2
+                    } else {
+                        codes.push("P");
+                        if word.is(i + 1, &["P", "B"]) {
+                            i + 2
+                        } else {
+                            i + 1
+                        }
+                    }
+                }
+                'Q' => {
+                    codes.push("K");
+                    word.skip_same(i)
+                }
+                'R' => word.r(i, &mut codes),
+                'S' => word.s(i, &mut codes),
+                'T' => word.t(i, &mut codes),
+                'V' => {
+                    codes.push("F");
+                    word.skip_same(i)
+                }
+                'W' => word.w(i, &mut codes),
+                'X' => word.x(i, &mut codes),
+                'Z' => word.z(i, &mut codes),
+                _ => i + 1,
+            };
+        }
+        (codes.primary, codes.alternate)
+    }
+}
+
+impl Default for MDictDoubleMetaphone {
+    fn default() -> MDictDoubleMetaphone {
+        MDictDoubleMetaphone::new()
+    }
+}
+
+impl MDictPhoneticEncoder for MDictDoubleMetaphone {
+    fn name(&self) -> &str {
+        "double-metaphone"
+    }
+
+    fn encode(&self, word: &str) -> Vec<String> {
+        let (primary, alternate) = self.codes(word);
+        let mut codes = Vec::with_capacity(2);
+        if !primary.is_empty() {
+            codes.push(primary.clone());
+        }
+        if !alternate.is_empty() && alternate != primary {
+            codes.push(alternate);
+        }
+        codes
+    }
+}
+
+/// American Soundex, which is simpler and matches more words than Double Metaphone.
+#[derive(Clone, Debug, Default)]
+pub struct MDictSoundex;
+
+impl MDictPhoneticEncoder for MDictSoundex {
+    fn name(&self) -> &str {
+        "soundex"
+    }
+
+    fn encode(&self, word: &str) -> Vec<String> {
+        fn digit(c: char) -> Option<char> {
+            match c {
+                'B' | 'F' | 'P' | 'V' => Some('1'),
+                'C' | 'G' | 'J' | 'K' | 'Q' | 'S' | 'X' | 'Z' => Some('2'),
+                'D' | 'T' => Some('3'),
+                'L' => Some('4'),
+                'M' | 'N' => Some('5'),
+                'R' => Some('6'),
+                _ => None,
+            }
+        }
+        let mut letters = word
+            .chars()
+            .map(|c| c.to_ascii_uppercase())
+            .filter(char::is_ascii_uppercase);
+        let first = match letters.next() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let mut code = first.to_string();
+        let mut last = digit(first);
+        for c in letters {
+            let d = digit(c);
+            if d.is_some() && d != last {
+                code.extend(d);
+                if code.len() == 4 {
+                    break;
+                }
+            }
+            // letters with the same digit separated by "H" or "W" are coded once
+            if c != 'H' && c != 'W' {
+                last = d;
+            }
+        }
+        while code.len() < 4 {
+            code.push('0');
+        }
+        vec![code]
+    }
+}
+
+// Rank keywords with the same code as `key` by the edit distance, and keep the first `limit` ones.
+pub(crate) fn rank(key: &str, keywords: Vec<String>, limit: usize) -> Vec<(String, usize)> {
+    let mut distance = EditDistance::new(&key.to_lowercase(), usize::MAX, MDictDistance::Damerau);
+    let result = keywords
+        .into_iter()
+        .filter_map(|keyword| {
+            let d = distance.matches(&keyword.to_lowercase())?;
+            Some((keyword, d))
+        })
+        .collect();
+    fuzzy::rank(result, limit)
+}
+
+struct Codes {
+    primary: String,
+    alternate: String,
+    max_len: usize,
+}
+
+impl Codes {
+    fn push(&mut self, code: &str) {
+        self.push_both(code, code);
+    }
+
+    fn push_both(&mut self, primary: &str, alternate: &str) {
+        self.push_primary(primary);
+        self.push_alternate(alternate);
+    }
+
+    fn push_primary(&mut self, code: &str) {
+        let len = code
+            .len()
+            .min(self.max_len.saturating_sub(self.primary.len()));
+        self.primary.push_str(&code[..len]);
+    }
+
+    fn push_alternate(&mut self, code: &str) {
+        let len = code
+            .len()
+            .min(self.max_len.saturating_sub(self.alternate.len()));
+        self.alternate.push_str(&code[..len]);
+    }
+
+    fn is_complete(&self) -> bool {
+        self.primary.len() >= self.max_len && self.alternate.len() >= self.max_len
+    }
+}
+
+// An uppercase word, the rules of letters are methods returning the index of the next letter.
+struct Word {
+    chars: Vec<char>,
+    // words of Slavic or Germanic origin
+    slavo_germanic: bool,
+}
+
+impl Word {
+    fn new(word: &str) -> Word {
+        let chars: Vec<char> = word.trim().chars().flat_map(char::to_uppercase).collect();
+        let upper: String = chars.iter().collect();
+        let slavo_germanic = upper.contains('W')
+            || upper.contains('K')
+            || upper.contains("CZ")
+            || upper.contains("WITZ");
+        Word {
+            chars,
+            slavo_germanic,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    // The letter at `i`, or '\0' if `i` is out of the word
+    fn at(&self, i: usize) -> char {
+        self.chars.get(i).copied().unwrap_or('\0')
+    }
+
+    fn is_vowel(c: char) -> bool {
+        matches!(c, 'A' | 'E' | 'I' | 'O' | 'U' | 'Y')
+    }
+
+    // Whether any of `parts` is at `i`, all of them have the same length
+    fn is(&self, i: usize, parts: &[&str]) -> bool {
+        parts.iter().any(|part| {
+            let len = part.chars().count();
+            i + len <= self.len() && self.chars[i..i + len].iter().copied().eq(part.chars())
+        })
+    }
+
+    // `is` with `i` before the current letter by `n`, false if it's before the start
+    fn is_before(&self, i: usize, n: usize, parts: &[&str]) -> bool {
+        i.checked_sub(n).map_or(false, |i| self.is(i, parts))
+    }
+
+    // Skip the next letter if it's the same as the letter at `i`
+    fn skip_same(&self, i: usize) -> usize {
+        if self.at(i + 1) == self.at(i) {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+
+    fn is_germanic(&self) -> bool {
+        self.is(0, &["VAN ", "VON "]) || self.is(0, &["SCH"])
+    }
+
+    fn c(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.c_is_k(i) {
+            codes.push("K");
+            i + 2
+        } else if i == 0 && self.is(i, &["CAESAR"]) {
+            codes.push("S");
+            i + 2
+        } else if self.is(i, &["CH"]) {
+            self.ch(i, codes)
+        } else if self.is(i, &["CZ"]) && !self.is_before(i, 2, &["WICZ"]) {
+            // "Czerny"
+            codes.push_both("S", "X");
+            i + 2
+        } else if self.is(i + 1, &["CIA"]) {
+            // "Focaccia"
+            codes.push("X");
+            i + 3
+        } else if self.is(i, &["CC"]) && !(i == 1 && self.at(0) == 'M') {
+            self.cc(i, codes)
+        } else if self.is(i, &["CK", "CG", "CQ"]) {
+            codes.push("K");
+            i + 2
+        } else if self.is(i, &["CI", "CE", "CY"]) {
+            if self.is(i, &["CIO", "CIE", "CIA"]) {
+                codes.push_both("S", "X");
+            } else {
+                codes.push("S");
+            }
+            i + 2
+        } else {
+            codes.push("K");
+            if self.is(i + 1, &[" C", " Q", " G"]) {
+                // "Mac Caffrey", "Mac Gregor"
+                i + 3
+            } else if self.is(i + 1, &["C", "K", "Q"]) && !self.is(i + 1, &["CE", "CI"]) {
+                i + 2
+            } else {
+                i + 1
+            }
+        }
+    }
+
+    // Germanic "CH" like "Bacher", and "Chianti"
+    fn c_is_k(&self, i: usize) -> bool {
+        if self.is(i, &["CHIA"]) {
+            true
+        } else if i <= 1 || Word::is_vowel(self.at(i - 2)) || !self.is(i - 1, &["ACH"]) {
+            false
+        } else {
+            let c = self.at(i + 2);
+            (c != 'I' && c != 'E') || self.is(i - 2, &["BACHER", "MACHER"])
+        }
+    }
+
+    fn cc(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is(i + 2, &["I", "E", "H"]) && !self.is(i + 2, &["HU"]) {
+            if (i == 1 && self.at(0) == 'A') || self.is_before(i, 1, &["UCCEE", "UCCES"]) {
+                // "Accident", "Succeed"
+                codes.push("KS");
+            } else {
+                // "Bacci", "Bertucci"
+                codes.push("X");
+            }
+            i + 3
+        } else {
+            // "Accommodate"
+            codes.push("K");
+            i + 2
+        }
+    }
+
+    fn ch(&self, i: usize, codes: &mut Codes) -> usize {
+        if i > 0 && self.is(i, &["CHAE"]) {
+            // "Michael"
+            codes.push_both("K", "X");
+        } else if self.ch_is_greek(i) || self.ch_is_k(i) {
+            codes.push("K");
+        } else if i > 0 {
+            if self.is(0, &["MC"]) {
+                codes.push("K");
+            } else {
+                codes.push_both("X", "K");
+            }
+        } else {
+            codes.push("X");
+        }
+        i + 2
+    }
+
+    // Greek roots like "Chemistry" and "Chorus"
+    fn ch_is_greek(&self, i: usize) -> bool {
+        i == 0
+            && (self.is(i + 1, &["HARAC", "HARIS"])
+                || self.is(i + 1, &["HOR", "HYM", "HIA", "HEM"]))
+            && !self.is(0, &["CHORE"])
+    }
+
+    fn ch_is_k(&self, i: usize) -> bool {
+        self.is_germanic()
+            || self.is_before(i, 2, &["ORCHES", "ARCHIT", "ORCHID"])
+            || self.is(i + 2, &["T", "S"])
+            || ((i == 0 || self.is_before(i, 1, &["A", "O", "U", "E"]))
+                && (self.is(i + 2, &["L", "R", "N", "M", "B", "H", "F", "V", "W", " "])
+                    || i + 2 == self.len()))
+    }
+
+    fn d(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is(i, &["DG"]) {
+            if self.is(i + 2, &["I", "E", "Y"]) {
+                // "Edge"
+                codes.push("J");
+                i + 3
+            } else {
+                // "Edgar"
+                codes.push("TK");
+                i + 2
+            }
+        } else if self.is(i, &["DT", "DD"]) {
+            codes.push("T");
+            i + 2
+        } else {
+            codes.push("T");
+            i + 1
+        }
+    }
+
+    fn g(&self, i: usize, codes: &mut Codes) -> usize {
+        let next = self.at(i + 1);
+        if next == 'H' {
+            self.gh(i, codes)
+        } else if next == 'N' {
+            if i == 1 && Word::is_vowel(self.at(0)) && !self.slavo_germanic {
+                codes.push_both("KN", "N");
+            } else if !self.is(i + 2, &["EY"]) && !self.slavo_germanic {
+                codes.push_both("N", "KN");
+            } else {
+                codes.push("KN");
+            }
+            i + 2
+        } else if self.is(i + 1, &["LI"]) && !self.slavo_germanic {
+            // "Tagliaro"
+            codes.push_both("KL", "L");
+            i + 2
+        } else if i == 0
+            && (next == 'Y'
+                || self.is(
+                    i + 1,
+                    &[
+                        "ES", "EP", "EB", "EL", "EY", "IB", "IL", "IN", "IE", "EI", "ER",
+                    ],
+                ))
+        {
+            codes.push_both("K", "J");
+            i + 2
+        } else if (self.is(i + 1, &["ER"]) || next == 'Y')
+            && !self.is(0, &["DANGER", "RANGER", "MANGER"])
+            && !self.is_before(i, 1, &["E", "I"])
+            && !self.is_before(i, 1, &["RGY", "OGY"])
+        {
+            codes.push_both("K", "J");
+            i + 2
+        } else if self.is(i + 1, &["E", "I", "Y"]) || self.is_before(i, 1, &["AGGI", "OGGI"]) {
+            if self.is_germanic() || self.is(i + 1, &["ET"]) {
+                codes.push("K");
+            } else if self.is(i + 1, &["IER"]) {
+                codes.push("J");
+            } else {
+                codes.push_both("J", "K");
+            }
+            i + 2
+        } else {
+            codes.push("K");
+            self.skip_same(i)
+        }
+    }
+
+    fn gh(&self, i: usize, codes: &mut Codes) -> usize {
+        if i > 0 && !Word::is_vowel(self.at(i - 1)) {
+            codes.push("K");
+        } else if i == 0 {
+            // "Ghislane", "Ghost"
+            if self.at(i + 2) == 'I' {
+                codes.push("J");
+            } else {
+                codes.push("K");
+            }
+        } else if self.is_before(i, 2, &["B", "H", "D"])
+            || self.is_before(i, 3, &["B", "H", "D"])
+            || self.is_before(i, 4, &["B", "H"])
+        {
+            // silent like "Hugh", "bough" and "broughton"
+        } else if i > 2 && self.at(i - 1) == 'U' && self.is(i - 3, &["C", "G", "L", "R", "T"]) {
+            // "laugh", "cough", "rough"
+            codes.push("F");
+        } else if i > 0 && self.at(i - 1) != 'I' {
+            codes.push("K");
+        }
+        i + 2
+    }
+
+    fn h(&self, i: usize, codes: &mut Codes) -> usize {
+        // only keep "H" between vowels or at the start before a vowel
+        if (i == 0 || Word::is_vowel(self.at(i - 1))) && Word::is_vowel(self.at(i + 1)) {
+            codes.push("H");
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+
+    fn j(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is(i, &["JOSE"]) || self.is(0, &["SAN "]) {
+            // Spanish like "Jose", "San Jacinto"
+            if (i == 0 && self.at(i + 4) == ' ') || self.len() == 4 || self.is(0, &["SAN "]) {
+                codes.push("H");
+            } else {
+                codes.push_both("J", "H");
+            }
+            return i + 1;
+        }
+        if i == 0 {
+            codes.push_both("J", "A");
+        } else if Word::is_vowel(self.at(i - 1))
+            && !self.slavo_germanic
+            && matches!(self.at(i + 1), 'A' | 'O')
+        {
+            codes.push_both("J", "H");
+        } else if i + 1 == self.len() {
+            codes.push_primary("J");
+        } else if !self.is(i + 1, &["L", "T", "K", "S", "N", "M", "B", "Z"])
+            && !self.is_before(i, 1, &["S", "K", "L"])
+        {
+            codes.push("J");
+        }
+        self.skip_same(i)
+    }
+
+    fn l(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.at(i + 1) == 'L' {
+            // Spanish like "Cabrillo", "Gallegos"
+            let len = self.len();
+            let spanish = (i + 3 == len && self.is_before(i, 1, &["ILLO", "ILLA", "ALLE"]))
+                || ((self.is_before(len, 2, &["AS", "OS"]) || self.is_before(len, 1, &["A", "O"]))
+                    && self.is_before(i, 1, &["ALLE"]));
+            if spanish {
+                codes.push_primary("L");
+            } else {
+                codes.push("L");
+            }
+            i + 2
+        } else {
+            codes.push("L");
+            i + 1
+        }
+    }
+
+    // "MM" or "MB" of "Dumb" and "Thumb"
+    fn m_is_doubled(&self, i: usize) -> bool {
+        self.at(i + 1) == 'M'
+            || (self.is_before(i, 1, &["UMB"]) && (i + 2 == self.len() || self.is(i + 2, &["ER"])))
+    }
+
+    fn r(&self, i: usize, codes: &mut Codes) -> usize {
+        // French like "Rogier"
+        if i + 1 == self.len()
+            && !self.slavo_germanic
+            && self.is_before(i, 2, &["IE"])
+            && !self.is_before(i, 4, &["ME", "MA"])
+        {
+            codes.push_alternate("R");
+        } else {
+            codes.push("R");
+        }
+        self.skip_same(i)
+    }
+
+    fn s(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is_before(i, 1, &["ISL", "YSL"]) {
+            // silent like "Island", "Carlisle"
+            i + 1
+        } else if i == 0 && self.is(i, &["SUGAR"]) {
+            codes.push_both("X", "S");
+            i + 1
+        } else if self.is(i, &["SH"]) {
+            if self.is(i + 1, &["HEIM", "HOEK", "HOLM", "HOLZ"]) {
+                codes.push("S");
+            } else {
+                codes.push("X");
+            }
+            i + 2
+        } else if self.is(i, &["SIO", "SIA"]) || self.is(i, &["SIAN"]) {
+            if self.slavo_germanic {
+                codes.push("S");
+            } else {
+                codes.push_both("S", "X");
+            }
+            i + 3
+        } else if (i == 0 && self.is(i + 1, &["M", "N", "L", "W"])) || self.is(i + 1, &["Z"]) {
+            // "Smith" is also "Schmidt", "Snider" is also "Schneider"
+            codes.push_both("S", "X");
+            if self.is(i + 1, &["Z"]) {
+                i + 2
+            } else {
+                i + 1
+            }
+        } else if self.is(i, &["SC"]) {
+            self.sc(i, codes)
+        } else {
+            // French like "Resnais", "Artois"
+            if i + 1 == self.len() && self.is_before(i, 2, &["AI", "OI"]) {
+                codes.push_alternate("S");
+            } else {
+                codes.push("S");
+            }
+            if self.is(i + 1, &["S", "Z"]) {
+                i + 2
+            } else {
+                i + 1
+            }
+        }
+    }
+
+    fn sc(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.at(i + 2) == 'H' {
+            if self.is(i + 3, &["OO", "ER", "EN", "UY", "ED", "EM"]) {
+                // Dutch like "School", "Schenker"
+                if self.is(i + 3, &["ER", "EN"]) {
+                    codes.push_both("X", "SK");
+                } else {
+                    codes.push("SK");
+                }
+            } else if i == 0 && !Word::is_vowel(self.at(3)) && self.at(3) != 'W' {
+                codes.push_both("X", "S");
+            } else {
+                codes.push("X");
+            }
+        } else if self.is(i + 2, &["I", "E", "Y"]) {
+            codes.push("S");
+        } else {
+            codes.push("SK");
+        }
+        i + 3
+    }
+
+    fn t(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is(i, &["TION"]) || self.is(i, &["TIA", "TCH"]) {
+            codes.push("X");
+            i + 3
+        } else if self.is(i, &["TH"]) || self.is(i, &["TTH"]) {
+            if self.is(i + 2, &["OM", "AM"]) || self.is_germanic() {
+                // "Thomas", "Thames"
+                codes.push("T");
+            } else {
+                codes.push_both("0", "T");
+            }
+            i + 2
+        } else {
+            codes.push("T");
+            if self.is(i + 1, &["T", "D"]) {
+                i + 2
+            } else {
+                i + 1
+            }
+        }
+    }
+
+    fn w(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.is(i, &["WR"]) {
+            codes.push("R");
+            i + 2
+        } else if i == 0 && (Word::is_vowel(self.at(i + 1)) || self.is(i, &["WH"])) {
+            // "Wasserman" is also "Vasserman"
+            if Word::is_vowel(self.at(i + 1)) {
+                codes.push_both("A", "F");
+            } else {
+                codes.push("A");
+            }
+            i + 1
+        } else if (i + 1 == self.len() && i > 0 && Word::is_vowel(self.at(i - 1)))
+            || self.is_before(i, 1, &["EWSKI", "EWSKY", "OWSKI", "OWSKY"])
+            || self.is(0, &["SCH"])
+        {
+            // Polish like "Filipowicz"
+            codes.push_alternate("F");
+            i + 1
+        } else if self.is(i, &["WICZ", "WITZ"]) {
+            codes.push_both("TS", "FX");
+            i + 4
+        } else {
+            i + 1
+        }
+    }
+
+    fn x(&self, i: usize, codes: &mut Codes) -> usize {
+        if i == 0 {
+            // "Xavier"
+            codes.push("S");
+            return i + 1;
+        }
+        // silent in French like "Breaux"
+        if !(i + 1 == self.len()
+            && (self.is_before(i, 3, &["IAU", "EAU"]) || self.is_before(i, 2, &["AU", "OU"])))
+        {
+            codes.push("KS");
+        }
+        if self.is(i + 1, &["C", "X"]) {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+
+    fn z(&self, i: usize, codes: &mut Codes) -> usize {
+        if self.at(i + 1) == 'H' {
+            // Chinese like "Zhao"
+            codes.push("J");
+            return i + 2;
+        }
+        if self.is(i + 1, &["ZO", "ZI", "ZA"])
+            || (self.slavo_germanic && i > 0 && self.at(i - 1) != 'T')
+        {
+            codes.push_both("S", "TS");
+        } else {
+            codes.push("S");
+        }
+        self.skip_same(i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(word: &str) -> (String, String) {
+        MDictDoubleMetaphone::new().codes(word)
+    }
+
+    fn pair(primary: &str, alternate: &str) -> (String, String) {
+        (primary.to_owned(), alternate.to_owned())
+    }
+
+    #[test]
+    fn double_metaphone() {
+        assert_eq!(codes("Schmidt"), pair("XMT", "SMT"));
+        assert_eq!(codes("Smith"), pair("SM0", "XMT"));
+        // "TH" is "T" before "OM"
+        assert_eq!(codes("Thompson"), pair("TMPS", "TMPS"));
+        assert_eq!(codes("Knight"), pair("NT", "NT"));
+        assert_eq!(codes("dumb"), pair("TM", "TM"));
+        assert_eq!(codes("Xavier"), pair("SF", "SFR"));
+        let encoder = MDictDoubleMetaphone::new();
+        assert_eq!(encoder.encode("Schmidt"), vec!["XMT", "SMT"]);
+        assert_eq!(encoder.encode("dumb"), vec!["TM"]);
+        assert!(encoder.encode("123").is_empty());
+        assert_eq!(
+            MDictDoubleMetaphone::with_max_len(6).codes("Thompson"),
+            pair("TMPSN", "TMPSN")
+        );
+    }
+
+    #[test]
+    fn soundex() {
+        for (word, code) in [
+            ("Robert", "R163"),
+            ("Rupert", "R163"),
+            ("Ashcraft", "A261"),
+            ("Tymczak", "T522"),
+            ("Pfister", "P236"),
+            ("Lee", "L000"),
+        ] {
+            assert_eq!(MDictSoundex.encode(word), vec![code], "{}", word);
+        }
+        assert!(MDictSoundex.encode("").is_empty());
+    }
+
+    #[test]
+    fn rank_by_distance() {
+        let keywords = vec!["Smyth".to_owned(), "smith".to_owned(), "Schmidt".to_owned()];
+        assert_eq!(
+            rank("Smith", keywords, 2),
+            vec![("smith".to_owned(), 0), ("Smyth".to_owned(), 1)]
+        );
+    }
+}
@@ -0,0 +1,279 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+const PINYIN: &str = include_str!("../data/pinyin.txt");
+// A keyword with more combinations of readings is only indexed by the first ones
+const MAX_COMBINATIONS: usize = 16;
+
+/// Readings of Chinese characters, for searching keywords by pinyin.
+///
+/// The bundled table only contains common characters of pinyin-data, which is under the MIT
+/// License, the complete table can be loaded by [`from_file`](MDictPinyin::from_file).
+pub struct MDictPinyin {
+    // readings with tone numbers like "zhong1", "lv4" for "lǜ" and "ma5" for the neutral tone
+    readings: HashMap<char, Vec<String>>,
+}
+
+impl MDictPinyin {
+    /// The table of common characters bundled in this crate.
+    pub fn new() -> MDictPinyin {
+        MDictPinyin::from_table(PINYIN)
+    }
+
+    /// Load `pinyin.txt` of pinyin-data, each line is like `U+4E2D: zhōng,zhòng  # 中`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<MDictPinyin> {
+        Ok(MDictPinyin::from_table(&fs::read_to_string(path)?))
+    }
+
+    fn from_table(table: &str) -> MDictPinyin {
+        let mut readings: HashMap<char, Vec<String>> = HashMap::new();
+        for line in table.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut parts = line.splitn(2, ':');
+            let c = parts
+                .next()
+                .and_then(|code| code.trim().strip_prefix("U+"))
+                .and_then(|code| u32::from_str_radix(code, 16).ok())
+                .and_then(char::from_u32);
+            let (c, values) = match (c, parts.next()) {
+                (Some(c), Some(values)) => (c, values),
+                _ => continue,
+            };
+            let entry = readings.entry(c).or_default();
+            for reading in values.split(',').filter_map(tone_number) {
+                if !entry.contains(&reading) {
+                    entry.push(reading);
+                }
+            }
+        }
+        readings.retain(|_, r| !r.is_empty());
+        MDictPinyin { readings }
+    }
+
+    /// Readings of `c` with tone numbers, like "zhong1" and "zhong4" for "中".
+    pub fn readings(&self, c: char) -> &[String] {
+        self.readings.get(&c).map_or(&[], |r| r.as_slice())
+    }
+
+    /// Pinyin of `keyword` with tone numbers separated by spaces, like "zhong1 guo2" for "中国".
+    ///
+    /// There is one for each combination of readings of polyphonic characters. A keyword with any
+    /// character not in the table has no pinyin, so keywords in other languages are skipped.
+    pub fn keyword_pinyin(&self, keyword: &str) -> Vec<String> {
+        let mut combinations = vec![String::new()];
+        for c in keyword.chars() {
+            let readings = self.readings(c);
+            if readings.is_empty() {
+                return Vec::new();
+            }
+            let mut next = Vec::new();
+            for prefix in &combinations {
+                for reading in readings {
+                    if next.len() < MAX_COMBINATIONS {
+                        if prefix.is_empty() {
+                            next.push(reading.clone());
+                        } else {
+                            next.push(format!("{} {}", prefix, reading));
+                        }
+                    }
+                }
+            }
+            combinations = next;
+        }
+        combinations.retain(|p| !p.is_empty());
+        combinations
+    }
+}
+
+impl Default for MDictPinyin {
+    fn default() -> MDictPinyin {
+        MDictPinyin::new()
+    }
+}
+
+// Convert a reading like "zhōng" or "lǜ" to "zhong1" or "lv4".
+fn tone_number(reading: &str) -> Option<String> {
+    let mut number = String::with_capacity(reading.len() + 1);
+    let mut tone = 5;
+    for c in reading.trim().chars() {
+        let (base, t) = pinyin_char(c)?;
+        if t != 0 {
+            tone = t;
+        }
+        number.push(base);
+    }
+    if number.is_empty() {
+        return None;
+    }
+    number.push(char::from(b'0' + tone));
+    Some(number)
+}
+
+// The letter without tone mark and the tone of a lowercase char of pinyin, "ü" is "v".
+fn pinyin_char(c: char) -> Option<(char, u8)> {
+    const MARKED: [(char, &str); 6] = [
+        ('a', "āáǎà"),
+        ('e', "ēéěè"),
+        ('i', "īíǐì"),
+        ('o', "ōóǒò"),
+        ('u', "ūúǔù"),
+        ('v', "ǖǘǚǜ"),
+    ];
+    match c {
+        'a'..='z' => return Some((c, 0)),
+        'ü' => return Some(('v', 0)),
+        _ => {}
+    }
+    for (base, marked) in MARKED.iter() {
+        if let Some(i) = marked.chars().position(|m| m == c) {
+            return Some((*base, i as u8 + 1));
+        }
+    }
+    None
+}
+
+// The pinyin without tones, which is the key of the pinyin index.
+pub(crate) fn toneless(pinyin: &str) -> String {
+    pinyin.chars().filter(|c| c.is_ascii_lowercase()).collect()
+}
+
+// A query like "zhongguo", "zhong1guo2", "Zhōngguó" or "zhong guo".
+pub(crate) struct PinyinQuery {
+    // letters and the tones of them, tone numbers are digits with tone 0
+    chars: Vec<(char, u8)>,
+}
+
+impl PinyinQuery {
+    pub(crate) fn parse(query: &str) -> Option<PinyinQuery> {
+        let mut chars: Vec<(char, u8)> = Vec::with_capacity(query.len());
+        for c in query.chars().flat_map(char::to_lowercase) {
+            match c {
+                ' ' | '\'' | '-' => {}
+                '0'..='5' => chars.push((c, 0)),
+                // "u:" is "ü"
+                ':' if chars.last() == Some(&('u', 0)) => {
+                    chars.pop();
+                    chars.push(('v', 0));
+                }
+                _ => chars.push(pinyin_char(c)?),
+            }
+        }
+        if chars.iter().any(|(c, _)| c.is_ascii_lowercase()) {
+            Some(PinyinQuery { chars })
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn toneless(&self) -> String {
+        self.chars
+            .iter()
+            .map(|(c, _)| *c)
+            .filter(char::is_ascii_lowercase)
+            .collect()
+    }
+
+    // Whether tones in this query are the same as `pinyin` like "zhong1 guo2".
+    // A syllable without tone in the query matches any tone.
+    pub(crate) fn matches(&self, pinyin: &str) -> bool {
+        let mut query = self.chars.iter().peekable();
+        for syllable in pinyin.split(' ') {
+            // the tone number is the last char, which may be missing in a custom table
+            let (letters, tone) = match syllable.strip_suffix(|c: char| c.is_ascii_digit()) {
+                Some(letters) => (letters, &syllable.as_bytes()[letters.len()..]),
+                None => (syllable, &[][..]),
+            };
+            let mut query_tone = 0;
+            for c in letters.chars() {
+                match query.next() {
+                    Some((q, t)) if *q == c => {
+                        if *t != 0 {
+                            query_tone = *t;
+                        }
+                    }
+                    _ => return false,
+                }
+            }
+            if let Some(t) = query.peek().and_then(|(c, _)| c.to_digit(10)) {
+                // 0 is also the neutral tone
+                query_tone = if t == 0 { 5 } else { t as u8 };
+                query.next();
+            }
+            if query_tone != 0 && tone != [b'0' + query_tone] {
+                return false;
+            }
+        }
+        query.next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> MDictPinyin {
+        MDictPinyin::from_table(
+            "U+4E2D: zhōng,zhòng  # 中\nU+56FD: guó  # 国\nU+7EFF: lǜ,lù  # 绿\nU+5417: ma  # 吗\n",
+        )
+    }
+
+    fn query(q: &str) -> PinyinQuery {
+        PinyinQuery::parse(q).unwrap()
+    }
+
+    #[test]
+    fn convert_tone_marks() {
+        assert_eq!(tone_number("zhōng").as_deref(), Some("zhong1"));
+        assert_eq!(tone_number("lǜ").as_deref(), Some("lv4"));
+        assert_eq!(tone_number("nǚ").as_deref(), Some("nv3"));
+        // no mark is the neutral tone
+        assert_eq!(tone_number(" ma ").as_deref(), Some("ma5"));
+        assert_eq!(tone_number(""), None);
+        assert_eq!(tone_number("x1"), None);
+    }
+
+    #[test]
+    fn combine_readings() {
+        let pinyin = table();
+        assert_eq!(pinyin.readings('绿'), ["lv4", "lu4"]);
+        assert_eq!(
+            pinyin.keyword_pinyin("中国"),
+            ["zhong1 guo2", "zhong4 guo2"]
+        );
+        assert_eq!(
+            pinyin.keyword_pinyin("中绿"),
+            ["zhong1 lv4", "zhong1 lu4", "zhong4 lv4", "zhong4 lu4"]
+        );
+        assert_eq!(pinyin.keyword_pinyin("吗"), ["ma5"]);
+        // a char not in the table
+        assert!(pinyin.keyword_pinyin("中a").is_empty());
+        assert!(pinyin.keyword_pinyin("").is_empty());
+    }
+
+    #[test]
+    fn parse_queries() {
+        assert_eq!(query("Zhōng guó").toneless(), "zhongguo");
+        assert_eq!(query("zhong1-guo2").toneless(), "zhongguo");
+        assert_eq!(query("xi'an").toneless(), "xian");
+        // "u:" is "ü"
+        assert_eq!(query("lu:4").toneless(), "lv");
+        assert_eq!(query("lǜ").toneless(), "lv");
+        assert!(PinyinQuery::parse("123").is_none());
+        assert!(PinyinQuery::parse("中国").is_none());
+    }
+
+    #[test]
+    fn match_tones() {
+        assert!(query("zhongguo").matches("zhong1 guo2"));
+        assert!(query("zhong1guo").matches("zhong1 guo2"));
+        assert!(query("zhōngguó").matches("zhong1 guo2"));
+        assert!(!query("zhong4guo").matches("zhong1 guo2"));
+        assert!(!query("zhongguo2x").matches("zhong1 guo2"));
+        assert!(!query("zhong").matches("zhong1 guo2"));
+        // 0 is the neutral tone
+        assert!(query("ma0").matches("ma5"));
+        // syllables without tone numbers
+        assert!(query("ma").matches("ma"));
+        assert!(!query("ma1").matches("ma"));
+        assert!(!query("ma").matches(""));
+    }
+}
@@ -0,0 +1,172 @@
+use std::{io, vec};
+
+const LINK: &str = "@@@LINK=";
+
+/// Records of a keyword with `@@@LINK=` redirects followed, see `lookup_word_redirected` of
+/// [`MDictLookup`](crate::MDictLookup) or [`MDictAsyncLookup`](crate::MDictAsyncLookup).
+#[derive(Clone, Debug, Default)]
+pub struct MDictRedirected {
+    /// Records in the order of the file, redirects are replaced by the records of their targets
+    pub records: Vec<String>,
+    /// Keywords in the order they are looked up, the first one is the requested keyword
+    pub chain: Vec<String>,
+}
+
+// A record redirecting to `target`
+pub(crate) fn link_to(target: &str) -> String {
+    format!("{}{}", LINK, target)
+}
+
+/// The target keyword if `record` is a redirect like `@@@LINK=target`.
+pub fn mdict_link_target(record: &str) -> Option<&str> {
+    if !record.starts_with(LINK) {
+        return None;
+    }
+    // records usually end with "\r\n\0"
+    let target = record[LINK.len()..].trim_end_matches(|c: char| c == '\0' || c.is_whitespace());
+    Some(target.trim_start())
+}
+
+// Depth-first resolution of redirects, the records of a keyword are looked up by the caller.
+pub(crate) struct Redirects {
+    max_depth: usize,
+    result: MDictRedirected,
+    // keywords being resolved with their remaining records, the path from the requested keyword
+    stack: Vec<(String, vec::IntoIter<String>)>,
+}
+
+impl Redirects {
+    pub(crate) fn new(key: &str, records: Vec<String>, max_depth: usize) -> Redirects {
+        Redirects {
+            max_depth,
+            result: MDictRedirected {
+                records: Vec::new(),
+                chain: vec![key.to_owned()],
+            },
+            stack: vec![(key.to_owned(), records.into_iter())],
+        }
+    }
+
+    /// The next keyword to look up, `None` if all redirects are resolved.
+    pub(crate) fn next_target(&mut self) -> io::Result<Option<String>> {
+        while let Some((_, records)) = self.stack.last_mut() {
+            let record = match records.next() {
+                Some(record) => record,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            let target = match mdict_link_target(&record) {
+                Some(target) => target,
+                None => {
+                    self.result.records.push(record);
+                    continue;
+                }
+            };
+            if self.stack.iter().any(|(key, _)| key == target) {
+                return Err(self.error(target, "Redirect cycle"));
+            }
+            if self.stack.len() > self.max_depth {
+                return Err(self.error(target, "Too many redirects"));
+            }
+            return Ok(Some(target.to_owned()));
+        }
+        Ok(None)
+    }
+
+    /// Continue with the records of `target` returned by `next_target`.
+    pub(crate) fn follow(
+        &mut self,
+        target: String,
+        records: io::Result<Vec<String>>,
+    ) -> io::Result<()> {
+        let records = match records {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(self.error(&target, "Redirect target not found"))
+            }
+            records => records?,
+        };
+        self.result.chain.push(target.clone());
+        self.stack.push((target, records.into_iter()));
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> MDictRedirected {
+        self.result
+    }
+
+    fn error(&self, target: &str, message: &str) -> io::Error {
+        let mut path: Vec<&str> = self.stack.iter().map(|(key, _)| key.as_str()).collect();
+        path.push(target);
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", message, path.join(" -> ")),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // Resolve redirects of `key` in `dict` like `lookup_word_redirected`.
+    fn resolve(
+        dict: &HashMap<&str, Vec<&str>>,
+        key: &str,
+        max_depth: usize,
+    ) -> io::Result<MDictRedirected> {
+        let lookup = |key: &str| match dict.get(key) {
+            Some(records) => Ok(records.iter().map(|r| r.to_string()).collect()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, key.to_owned())),
+        };
+        let mut redirects = Redirects::new(key, lookup(key)?, max_depth);
+        while let Some(target) = redirects.next_target()? {
+            let records = lookup(&target);
+            redirects.follow(target, records)?;
+        }
+        Ok(redirects.finish())
+    }
+
+    #[test]
+    fn link_target() {
+        assert_eq!(mdict_link_target("@@@LINK=apple\r\n\0"), Some("apple"));
+        assert_eq!(mdict_link_target("@@@LINK= apple pie"), Some("apple pie"));
+        assert_eq!(mdict_link_target("an apple"), None);
+    }
+
+    #[test]
+    fn follow_redirects() {
+        let dict: HashMap<&str, Vec<&str>> = vec![
+            ("colour", vec!["@@@LINK=color"]),
+            ("color", vec!["<b>color</b>", "@@@LINK=hue"]),
+            ("hue", vec!["<b>hue</b>"]),
+        ]
+        .into_iter()
+        .collect();
+        let result = resolve(&dict, "colour", 5).unwrap();
+        assert_eq!(result.records, vec!["<b>color</b>", "<b>hue</b>"]);
+        assert_eq!(result.chain, vec!["colour", "color", "hue"]);
+        // two redirects are too many
+        let e = resolve(&dict, "colour", 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "Too many redirects: colour -> color -> hue");
+    }
+
+    #[test]
+    fn redirect_errors() {
+        let dict: HashMap<&str, Vec<&str>> = vec![
+            ("a", vec!["@@@LINK=b"]),
+            ("b", vec!["@@@LINK=a"]),
+            ("c", vec!["@@@LINK=d"]),
+        ]
+        .into_iter()
+        .collect();
+        let e = resolve(&dict, "a", 5).unwrap_err();
+        assert_eq!(e.to_string(), "Redirect cycle: a -> b -> a");
+        let e = resolve(&dict, "c", 5).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "Redirect target not found: c -> d");
+    }
+}
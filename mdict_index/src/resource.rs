@@ -0,0 +1,108 @@
+use mdict::*;
+use std::{io, ops::RangeBounds, path::PathBuf};
+
+use bytes::Bytes;
+#[cfg(feature = "async")]
+use futures_util::{io::AsyncBufRead, stream::Stream};
+#[cfg(not(feature = "async"))]
+use std::fs::{File, OpenOptions};
+
+/// A resource in the mdd files, which can be read by range without loading the whole resource.
+///
+/// This is also used internally to read records in the mdx file, which may span multiple blocks.
+///
+/// This is returned by `open_resource` of [`MDictLookup`](crate::MDictLookup)
+/// or [`MDictAsyncLookup`](crate::MDictAsyncLookup).
+#[derive(Clone, Debug)]
+pub struct MDictResource {
+    file: PathBuf,
+    index: MDictRecordIndex,
+    // blocks containing this resource, start with the block of `index.block`
+    blocks: Vec<MDictRecordBlockIndex>,
+}
+
+impl MDictResource {
+    // `blocks` should start with the block of `index.block`,
+    // only the blocks containing this resource are kept.
+    pub(crate) fn new<I>(file: PathBuf, index: MDictRecordIndex, blocks: I) -> MDictResource
+    where
+        I: IntoIterator<Item = MDictRecordBlockIndex>,
+    {
+        let end = index.offset as u64 + index.len as u64;
+        let mut size = 0;
+        let blocks = blocks
+            .into_iter()
+            .take_while(|b| {
+                let take = size < end || size == 0;
+                size += b.uncomp_size;
+                take
+            })
+            .collect();
+        MDictResource {
+            file,
+            index,
+            blocks,
+        }
+    }
+
+    /// Length of this resource in bytes.
+    pub fn len(&self) -> u64 {
+        self.index.len as u64
+    }
+
+    /// Whether this resource is empty.
+    pub fn is_empty(&self) -> bool {
+        self.index.len == 0
+    }
+
+    #[cfg(not(feature = "async"))]
+    /// Read the whole resource.
+    pub fn read(&self) -> io::Result<Bytes> {
+        use std::io::Read;
+        let mut data = Vec::with_capacity(self.len() as usize);
+        self.reader(..)?.read_to_end(&mut data)?;
+        Ok(data.into())
+    }
+
+    #[cfg(feature = "async")]
+    /// Read the whole resource.
+    pub async fn read(&self) -> io::Result<Bytes> {
+        use futures_util::io::AsyncReadExt;
+        let mut data = Vec::with_capacity(self.len() as usize);
+        self.reader(..).await?.read_to_end(&mut data).await?;
+        Ok(data.into())
+    }
+
+    #[cfg(not(feature = "async"))]
+    /// Open a reader over the `range` of this resource.
+    ///
+    /// The range will be clamped to the length of this resource.
+    pub fn reader<B: RangeBounds<u64>>(&self, range: B) -> io::Result<MDictRecordReader<File>> {
+        let file = OpenOptions::new().read(true).open(&self.file)?;
+        Ok(lookup_reader(file, &self.index, &self.blocks, range))
+    }
+
+    #[cfg(feature = "async")]
+    /// Open a reader over the `range` of this resource.
+    ///
+    /// The range will be clamped to the length of this resource.
+    pub async fn reader<B: RangeBounds<u64>>(
+        &self,
+        range: B,
+    ) -> io::Result<impl AsyncBufRead + Send + Unpin> {
+        let file = crate::open_async(&self.file).await?;
+        Ok(lookup_reader(file, &self.index, &self.blocks, range))
+    }
+
+    #[cfg(feature = "async")]
+    /// Open the `range` of this resource as a stream of chunks.
+    ///
+    /// The range will be clamped to the length of this resource.
+    pub async fn stream<B: RangeBounds<u64>>(
+        &self,
+        range: B,
+    ) -> io::Result<impl Stream<Item = io::Result<Bytes>> + Send> {
+        let file = crate::open_async(&self.file).await?;
+        Ok(lookup_stream(file, &self.index, &self.blocks, range))
+    }
+}
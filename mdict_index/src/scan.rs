@@ -0,0 +1,354 @@
+use crate::find_mdd_files;
+use log::{info, warn};
+use mdict::{MDictHeader, MDictMode};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// A dictionary found by [`mdict_scan`]
+#[derive(Clone, Debug)]
+pub struct MDictScanned {
+    /// The path of the mdx file relative to the scanned directory without the extension,
+    /// like `English/oxford`, which is the same after rescanning.
+    pub id: String,
+    /// The title in the header of the mdx file, or the file name if it has no title
+    pub name: String,
+    /// The first directory in the scanned directory, like `English` of `English/oxford.mdx`
+    pub group: Option<String>,
+    /// The mdx file in the scanned directory, symbolic links in its path are not resolved
+    pub mdx_file: PathBuf,
+    /// `{name}.mdd`, `{name}.1.mdd` ...
+    pub mdd_files: Vec<PathBuf>,
+}
+
+/// A dictionary which is skipped by [`mdict_scan`]
+#[derive(Debug)]
+pub struct MDictScanError {
+    /// The broken mdx or mdd file, or a directory which can't be read
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+/// Result of [`mdict_scan`]
+#[derive(Debug, Default)]
+pub struct MDictScanReport {
+    /// Dictionaries in the order of their paths
+    pub dictionaries: Vec<MDictScanned>,
+    pub errors: Vec<MDictScanError>,
+}
+
+impl MDictScanReport {
+    /// Paths of mdx files, one per line, which is the config file of `mdict_server`.
+    pub fn listing(&self) -> String {
+        let mut listing = String::new();
+        for dict in &self.dictionaries {
+            listing.push_str(&dict.mdx_file.to_string_lossy());
+            listing.push('\n');
+        }
+        listing
+    }
+}
+
+/// Find all mdx files and their mdd files in `dir` and its subdirectories.
+///
+/// The header of each file is checked, a dictionary with a broken mdx or mdd file is skipped and
+/// reported in [`errors`](MDictScanReport::errors). Hidden directories are skipped, and symbolic
+/// links to directories out of `dir` are followed once.
+pub fn mdict_scan<P: AsRef<Path>>(dir: P) -> io::Result<MDictScanReport> {
+    let root = dir.as_ref().canonicalize()?;
+    if !root.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Expect a directory",
+        ));
+    }
+    let now = std::time::Instant::now();
+    let mut report = MDictScanReport::default();
+    let mut mdx_files = Vec::new();
+    let mut visited = HashSet::new();
+    find_mdx_files(
+        &root,
+        &root,
+        &mut visited,
+        &mut mdx_files,
+        &mut report.errors,
+    );
+    mdx_files.sort();
+    for mdx_file in mdx_files {
+        match scan_in(&root, &mdx_file) {
+            Ok(dict) => report.dictionaries.push(dict),
+            Err(e) => {
+                warn!("Skip {}: {}", e.path.to_string_lossy(), e.error);
+                report.errors.push(e);
+            }
+        }
+    }
+    info!(
+        "Find {} dictionaries in {} in {:?}, {} skipped",
+        report.dictionaries.len(),
+        root.to_string_lossy(),
+        now.elapsed(),
+        report.errors.len()
+    );
+    Ok(report)
+}
+
+/// Check a mdx file and its mdd files like [`mdict_scan`], the ID is the file name.
+pub fn mdict_scan_file<P: AsRef<Path>>(path: P) -> Result<MDictScanned, MDictScanError> {
+    let path = path.as_ref();
+    let mdx_file = path.canonicalize().map_err(|error| MDictScanError {
+        path: path.to_owned(),
+        error,
+    })?;
+    check_file(mdx_file)
+}
+
+// Check `mdx_file` in the scanned directory `root`, the ID and group are from its relative path.
+pub(crate) fn scan_in(root: &Path, mdx_file: &Path) -> Result<MDictScanned, MDictScanError> {
+    let relative = mdx_file.strip_prefix(root).unwrap_or(mdx_file);
+    let id = relative
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let group = relative
+        .parent()
+        .and_then(|parent| parent.components().next())
+        .map(|c| c.as_os_str().to_string_lossy().into_owned());
+    let mut dict = check_file(mdx_file.to_owned())?;
+    dict.id = id;
+    dict.group = group;
+    Ok(dict)
+}
+
+fn check_file(mdx_file: PathBuf) -> Result<MDictScanned, MDictScanError> {
+    let error = |path: &Path, error| MDictScanError {
+        path: path.to_owned(),
+        error,
+    };
+    let header = read_header(&mdx_file, MDictMode::Mdx).map_err(|e| error(&mdx_file, e))?;
+    let mdd_files = find_mdd_files(&mdx_file);
+    for mdd_file in &mdd_files {
+        read_header(mdd_file, MDictMode::Mdd).map_err(|e| error(mdd_file, e))?;
+    }
+    let file_name = mdx_file
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // "Title (No HTML code allowed)" is the placeholder of the editor
+    let name = match header.attrs.get("Title").map(|t| t.trim()) {
+        Some(title) if !title.is_empty() && !title.starts_with("Title (No HTML") => {
+            title.to_owned()
+        }
+        _ => file_name.clone(),
+    };
+    Ok(MDictScanned {
+        id: file_name,
+        name,
+        group: None,
+        mdx_file,
+        mdd_files,
+    })
+}
+
+fn read_header(file: &Path, mode: MDictMode) -> io::Result<MDictHeader> {
+    MDictHeader::new(File::open(file)?, mode)
+}
+
+// Find mdx files in `dir` which is in `root`, directories in `visited` are skipped.
+pub(crate) fn find_mdx_files(
+    root: &Path,
+    dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+    mdx_files: &mut Vec<PathBuf>,
+    errors: &mut Vec<MDictScanError>,
+) {
+    // a symbolic link may point to a directory which is already visited
+    if !dir.canonicalize().map_or(false, |dir| visited.insert(dir)) {
+        return;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) => {
+            warn!("Skip {}: {}", dir.to_string_lossy(), error);
+            errors.push(MDictScanError {
+                path: dir.to_owned(),
+                error,
+            });
+            return;
+        }
+    };
+    // sorted, so the same directory is found by the same path after rescanning
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .map(|entry| entry.path())
+        .collect();
+    paths.sort();
+    for path in paths {
+        // a symbolic link in `root` is found by its real path, otherwise the path in `root` is
+        // kept for the ID
+        let is_link = fs::symlink_metadata(&path).map_or(false, |m| m.file_type().is_symlink());
+        if is_link
+            && path
+                .canonicalize()
+                .map_or(false, |real| real.starts_with(root))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            find_mdx_files(root, &path, visited, mdx_files, errors);
+        } else if path.is_file()
+            && path
+                .extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("mdx"))
+        {
+            mdx_files.push(path);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // An empty directory in the temporary directory, which is removed when dropped
+    pub(crate) struct TempDir(pub(crate) PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("mdict_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir.canonicalize().unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Write a file with only the header, which is enough to be scanned.
+    pub(crate) fn write_header(path: &Path, title: &str) {
+        let xml = format!(
+            "<Dictionary GeneratedByEngineVersion=\"2.0\" Title=\"{}\" Encoding=\"UTF-8\"/>\r\n\0",
+            title
+        );
+        let header: Vec<u8> = xml.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        // adler32 checksum of the header
+        let (a, b) = header.iter().fold((1u32, 0u32), |(a, b), byte| {
+            let a = (a + *byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        let mut file = (header.len() as u32).to_be_bytes().to_vec();
+        file.extend(&header);
+        file.extend(&((b << 16) | a).to_le_bytes());
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(path, file).unwrap();
+    }
+
+    fn ids(report: &MDictScanReport) -> Vec<(&str, Option<&str>, &str)> {
+        report
+            .dictionaries
+            .iter()
+            .map(|d| (d.id.as_str(), d.group.as_deref(), d.name.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn scan_ids_and_groups() {
+        let dir = TempDir::new("scan_ids");
+        let root = &dir.0;
+        write_header(&root.join("top.mdx"), "Top");
+        write_header(&root.join("English/oxford.mdx"), "Oxford");
+        write_header(&root.join("English/oxford.mdd"), "");
+        write_header(
+            &root.join("English/old/webster.MDX"),
+            "Title (No HTML code allowed)",
+        );
+        fs::write(root.join("English/notes.txt"), "").unwrap();
+        let report = mdict_scan(root).unwrap();
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            ids(&report),
+            [
+                ("English/old/webster", Some("English"), "webster"),
+                ("English/oxford", Some("English"), "Oxford"),
+                ("top", None, "Top"),
+            ]
+        );
+        assert_eq!(
+            report.dictionaries[1].mdd_files,
+            [root.join("English/oxford.mdd")]
+        );
+    }
+
+    #[test]
+    fn skip_hidden_files() {
+        let dir = TempDir::new("scan_hidden");
+        let root = &dir.0;
+        write_header(&root.join(".cache/a.mdx"), "A");
+        write_header(&root.join("English/.b.mdx"), "B");
+        write_header(&root.join("English/c.mdx"), "C");
+        let report = mdict_scan(root).unwrap();
+        assert_eq!(ids(&report), [("English/c", Some("English"), "C")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_links_once() {
+        use std::os::unix::fs::symlink;
+
+        let dir = TempDir::new("scan_links");
+        let root = dir.0.join("root");
+        let outside = dir.0.join("outside");
+        write_header(&root.join("English/a.mdx"), "A");
+        write_header(&outside.join("b.mdx"), "B");
+        // a link in the root is found by its real path
+        symlink(root.join("English"), root.join("Links")).unwrap();
+        // links out of the root are followed once
+        symlink(&outside, root.join("More")).unwrap();
+        symlink(&outside, root.join("Other")).unwrap();
+        let mut mdx_files = Vec::new();
+        let mut errors = Vec::new();
+        find_mdx_files(
+            &root,
+            &root,
+            &mut HashSet::new(),
+            &mut mdx_files,
+            &mut errors,
+        );
+        assert_eq!(
+            mdx_files,
+            [root.join("English/a.mdx"), root.join("More/b.mdx")]
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn report_broken_files() {
+        let dir = TempDir::new("scan_broken");
+        let root = &dir.0;
+        fs::write(root.join("broken.mdx"), "not a dictionary").unwrap();
+        write_header(&root.join("good.mdx"), "Good");
+        write_header(&root.join("mdd.mdx"), "Broken mdd");
+        write_header(&root.join("mdd.mdd"), "");
+        fs::write(root.join("mdd.1.mdd"), [0, 0, 0, 4, 1, 2]).unwrap();
+        let report = mdict_scan(root).unwrap();
+        assert_eq!(ids(&report), [("good", None, "Good")]);
+        let errors: Vec<&Path> = report.errors.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(errors, [root.join("broken.mdx"), root.join("mdd.1.mdd")]);
+        assert_eq!(
+            report.listing(),
+            format!("{}\n", root.join("good.mdx").display())
+        );
+    }
+}
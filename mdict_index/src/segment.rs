@@ -0,0 +1,110 @@
+use patricia_tree::PatriciaMap;
+
+/// A segment of text, see [`MDictMemIndex::segment`](crate::MDictMemIndex::segment).
+#[derive(Clone, Debug)]
+pub struct MDictSegment {
+    /// The byte offset of the segment in the text
+    pub start: usize,
+    /// The byte offset after the segment
+    pub end: usize,
+    /// Keywords starting at `start`, longest first, the segment is the first one.
+    ///
+    /// It's empty if no keyword starts here, and the segment is a single char.
+    pub keywords: Vec<String>,
+}
+
+// Keywords of `map` starting at the byte offset `pos` of `text`, longest first.
+pub(crate) fn keywords_at<V>(map: &PatriciaMap<V>, text: &str, pos: usize) -> Vec<String> {
+    let rest = match text.get(pos..) {
+        Some(rest) => rest,
+        None => return Vec::new(),
+    };
+    // keywords which are prefixes of `rest` are found from shorter to longer
+    let mut keywords: Vec<String> = map
+        .common_prefixes(rest.as_bytes())
+        .filter(|(key, _)| !key.is_empty())
+        .map(|(key, _)| String::from_utf8_lossy(key).into_owned())
+        .collect();
+    keywords.reverse();
+    keywords
+}
+
+// Split `text` into the longest keywords of `map`.
+pub(crate) fn segment<V>(map: &PatriciaMap<V>, text: &str) -> Vec<MDictSegment> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while let Some(c) = text[start..].chars().next() {
+        let keywords = keywords_at(map, text, start);
+        let len = keywords.first().map_or(c.len_utf8(), |k| k.len());
+        segments.push(MDictSegment {
+            start,
+            end: start + len,
+            keywords,
+        });
+        start += len;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(keywords: &[&str]) -> PatriciaMap<()> {
+        let mut map = PatriciaMap::new();
+        for keyword in keywords {
+            map.insert(keyword, ());
+        }
+        map
+    }
+
+    fn segments<'a>(map: &PatriciaMap<()>, text: &'a str) -> Vec<(&'a str, Vec<String>)> {
+        segment(map, text)
+            .into_iter()
+            .map(|s| (&text[s.start..s.end], s.keywords))
+            .collect()
+    }
+
+    #[test]
+    fn keywords_at_positions() {
+        let map = map(&["中", "中文", "中文字", "文字", "字典", "b"]);
+        let text = "中文字典";
+        // longest first
+        assert_eq!(keywords_at(&map, text, 0), ["中文字", "中文", "中"]);
+        assert_eq!(keywords_at(&map, text, 3), ["文字"]);
+        assert_eq!(keywords_at(&map, text, 6), ["字典"]);
+        // not at a char boundary, or out of the text
+        assert!(keywords_at(&map, text, 1).is_empty());
+        assert!(keywords_at(&map, text, 12).is_empty());
+        assert!(keywords_at(&map, text, 13).is_empty());
+        // keywords longer than the rest aren't found
+        assert_eq!(keywords_at(&map, "中文", 0), ["中文", "中"]);
+        assert!(keywords_at(&map, "ab", 0).is_empty());
+        assert_eq!(keywords_at(&map, "ab", 1), ["b"]);
+    }
+
+    #[test]
+    fn segment_text() {
+        let map = map(&["中", "中文", "文字", "字典", "hello"]);
+        // the longest keyword is taken first, so "文字" isn't found after "中文"
+        assert_eq!(
+            segments(&map, "中文字典"),
+            vec![
+                ("中文", vec!["中文".to_owned(), "中".to_owned()]),
+                ("字典", vec!["字典".to_owned()]),
+            ]
+        );
+        // unknown text is split into chars
+        assert_eq!(
+            segments(&map, "我hello, 中"),
+            vec![
+                ("我", vec![]),
+                ("hello", vec!["hello".to_owned()]),
+                (",", vec![]),
+                (" ", vec![]),
+                ("中", vec!["中".to_owned()]),
+            ]
+        );
+        assert!(segment(&map, "").is_empty());
+    }
+}
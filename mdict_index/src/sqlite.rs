@@ -1,83 +1,296 @@
+use crate::full_text::*;
 use crate::*;
 use async_trait::async_trait;
+use futures_channel::mpsc;
+use futures_util::{
+    sink::SinkExt,
+    stream::{Stream, StreamExt},
+};
 use log::info;
 use sqlx::sqlite::{
     SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions,
 };
-use sqlx::{ConnectOptions, Connection, Executor};
+use sqlx::{ConnectOptions, Connection, Executor, Sqlite, Transaction};
 use std::path::{Path, PathBuf};
 use std::{
-    fs::{self, OpenOptions},
+    collections::HashSet,
+    fs::{self, File, OpenOptions},
     io,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use tokio::stream::StreamExt;
 
 const DB_INIT: &str = include_str!("../migration/init.sql");
+const DB_FTS: &str = include_str!("../migration/fts.sql");
+const DB_FUZZY: &str = include_str!("../migration/fuzzy.sql");
+const DB_TRIGRAM: &str = include_str!("../migration/trigram.sql");
+// Bump this when the schema in `migration` is changed, so the old index will be rebuilt.
+const DB_SCHEMA: &str = "8";
+// Prefix of keys in `meta` for identities of the mdx file and mdd files
+const IDENTITY_KEY: &str = "identity.";
 
 pub struct MDictSqliteBuilder {
     conn: SqliteConnection,
-    index: MDictMemIndex,
-}
-
-struct MdxBlock {
-    block_index: i32,
-    block_offset: i64,
-    block_size: i64,
+    mdx_file: PathBuf,
+    mdd_files: Vec<PathBuf>,
+    options: MDictBuildOptions,
 }
 
-struct MdxIndex {
-    keyword: String,
+#[derive(sqlx::FromRow, Debug)]
+struct MdxQuery {
     block_index: i32,
     record_offset: i32,
     record_size: i32,
 }
 
 #[derive(sqlx::FromRow, Debug)]
-struct MdxQuery {
+struct MddQuery {
     keyword: String,
+    file_index: i32,
     block_index: i32,
     record_offset: i32,
     record_size: i32,
-    block_offset: i64,
-    block_size: i64,
 }
 
-struct MddBlock {
-    file_index: i32,
-    block_index: i32,
+#[derive(sqlx::FromRow, Debug)]
+struct BlockQuery {
     block_offset: i64,
     block_size: i64,
+    block_uncomp_size: i64,
 }
 
-struct MddIndex {
-    keyword: String,
-    file_index: i32,
-    block_index: i32,
-    record_offset: i32,
-    record_size: i32,
+// Parameters of a statement, which are limited to 999 by old versions of sqlite.
+const MAX_PARAMS: usize = 999;
+
+// A value of a row in `BulkInsert`
+enum BulkValue {
+    Integer(i64),
+    Text(String),
 }
 
-#[derive(sqlx::FromRow, Debug)]
-struct MddQuery {
-    keyword: String,
-    file_index: i32,
-    block_index: i32,
-    record_offset: i32,
-    record_size: i32,
-    block_offset: i64,
-    block_size: i64,
+impl From<i32> for BulkValue {
+    fn from(v: i32) -> BulkValue {
+        BulkValue::Integer(v as i64)
+    }
+}
+
+impl From<i64> for BulkValue {
+    fn from(v: i64) -> BulkValue {
+        BulkValue::Integer(v)
+    }
+}
+
+impl From<String> for BulkValue {
+    fn from(v: String) -> BulkValue {
+        BulkValue::Text(v)
+    }
+}
+
+// Rows of a table which are inserted by statements of many rows, the statement is prepared once
+// and reused, so it's much faster than a statement per row.
+struct BulkInsert {
+    table: &'static str,
+    columns: &'static [&'static str],
+    rows_per_statement: usize,
+    // the statement of `rows_per_statement` rows
+    statement: String,
+    values: Vec<BulkValue>,
+    inserted: u64,
+}
+
+impl BulkInsert {
+    fn new(table: &'static str, columns: &'static [&'static str]) -> BulkInsert {
+        let rows_per_statement = MAX_PARAMS / columns.len();
+        BulkInsert {
+            table,
+            columns,
+            rows_per_statement,
+            statement: insert_statement(table, columns, rows_per_statement),
+            values: Vec::with_capacity(rows_per_statement * columns.len()),
+            inserted: 0,
+        }
+    }
+
+    // Add a row with values of all columns, rows are inserted when there are enough rows.
+    async fn push(
+        &mut self,
+        transaction: &mut Transaction<'_, Sqlite>,
+        row: Vec<BulkValue>,
+    ) -> sqlx::Result<()> {
+        debug_assert_eq!(row.len(), self.columns.len());
+        self.values.extend(row);
+        if self.values.len() >= self.rows_per_statement * self.columns.len() {
+            self.flush(transaction).await?;
+        }
+        Ok(())
+    }
+
+    // Insert all added rows.
+    async fn flush(&mut self, transaction: &mut Transaction<'_, Sqlite>) -> sqlx::Result<()> {
+        let rows = self.values.len() / self.columns.len();
+        if rows == 0 {
+            return Ok(());
+        }
+        let last;
+        let statement = if rows == self.rows_per_statement {
+            &self.statement
+        } else {
+            last = insert_statement(self.table, self.columns, rows);
+            &last
+        };
+        let mut query = sqlx::query(statement);
+        for value in self.values.drain(..) {
+            query = match value {
+                BulkValue::Integer(v) => query.bind(v),
+                BulkValue::Text(v) => query.bind(v),
+            };
+        }
+        query.execute(&mut *transaction).await?;
+        self.inserted += rows as u64;
+        Ok(())
+    }
+}
+
+// `insert into table (a, b) values (?, ?), (?, ?) ...` of `rows` rows
+fn insert_statement(table: &str, columns: &[&str], rows: usize) -> String {
+    let row = format!("({})", vec!["?"; columns.len()].join(", "));
+    format!(
+        "insert into {} ({}) values {}",
+        table,
+        columns.join(", "),
+        vec![row.as_str(); rows].join(", ")
+    )
+}
+
+// Keywords are sent in batches from the thread reading the MDict file.
+const KEY_BATCH_SIZE: usize = 4096;
+
+enum KeyBatch {
+    // keywords with the text of records for full-text index,
+    // and the progress of decoding keywords blocks when they are sent
+    Keys(
+        Vec<(String, MDictRecordIndex, Option<String>)>,
+        Option<MDictProgress>,
+    ),
+    Blocks(Vec<MDictRecordBlockIndex>),
+}
+
+// Read the index of a MDict file in another thread, the keywords are sent in batches
+// and the record blocks are sent at last.
+//
+// The channel is bounded, so only a few batches of keywords are kept in memory
+// while they are inserted into the database.
+//
+// The progress of decoding keywords blocks is sent with the keywords, and it's reported as
+// `DatabaseInsert` after they are inserted. Other phases are reported directly from this thread.
+//
+// If full-text index is enabled, the records of mdx file are also read and sent as text.
+fn read_index(
+    file: PathBuf,
+    mode: MDictMode,
+    options: &MDictBuildOptions,
+) -> mpsc::Receiver<io::Result<KeyBatch>> {
+    let (mut tx, rx) = mpsc::channel(2);
+    let latest = Arc::new(Mutex::new(None));
+    let observer = {
+        let latest = latest.clone();
+        let forward = options.progress.clone();
+        move |progress: &MDictProgress| {
+            if progress.phase == MDictBuildPhase::KeyBlocks {
+                *latest.lock().unwrap() = Some(progress.clone());
+            } else if let Some(forward) = &forward {
+                forward.on_progress(progress);
+            }
+        }
+    };
+    let options = MDictBuildOptions {
+        progress: Some(Arc::new(observer)),
+        ..options.clone()
+    };
+    std::thread::spawn(move || {
+        let mut read = || -> io::Result<()> {
+            let mut mdict = options.open(&file, mode)?;
+            let mut batch = Vec::with_capacity(KEY_BATCH_SIZE);
+            let mut push = |key, index, text| {
+                batch.push((key, index, text));
+                if batch.len() >= KEY_BATCH_SIZE {
+                    let keys = std::mem::replace(&mut batch, Vec::with_capacity(KEY_BATCH_SIZE));
+                    let progress = latest.lock().unwrap().clone();
+                    send_batch(&mut tx, Ok(KeyBatch::Keys(keys, progress)))?;
+                }
+                Ok(())
+            };
+            let blocks = match mode {
+                MDictMode::Mdx if options.full_text => {
+                    let header =
+                        MDictHeader::new(OpenOptions::new().read(true).open(&file)?, mode)?;
+                    let extractor = TextExtractor::new();
+                    mdict.make_index_with_records(|key, index, record| {
+                        let text = extractor.text(&header.decode_string(record)?);
+                        push(key, index, text)
+                    })?
+                }
+                _ => mdict.make_index_with(|key, index| push(key, index, None))?,
+            };
+            let progress = latest.lock().unwrap().clone();
+            send_batch(&mut tx, Ok(KeyBatch::Keys(batch, progress)))?;
+            send_batch(&mut tx, Ok(KeyBatch::Blocks(blocks)))
+        };
+        if let Err(e) = read() {
+            // the receiver may be dropped
+            let _ = send_batch(&mut tx, Err(e));
+        }
+    });
+    rx
+}
+
+fn send_batch(
+    tx: &mut mpsc::Sender<io::Result<KeyBatch>>,
+    batch: io::Result<KeyBatch>,
+) -> io::Result<()> {
+    futures_executor::block_on(tx.send(batch))
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Index building is aborted"))
 }
 
 impl MDictSqliteBuilder {
     async fn build(mut self) -> sqlx::Result<()> {
+        // files changed while building are found when the index is opened next time
+        let identities = file_identities(&self.mdx_file, &self.mdd_files)?;
         self.conn.execute(DB_INIT).await?;
+        if self.options.full_text {
+            self.conn.execute(DB_FTS).await?;
+        }
         self.write_mdx().await?;
         self.write_mdd().await?;
         let now = std::time::Instant::now();
         let mut transaction = self.conn.begin().await?;
         transaction
-            .execute("CREATE UNIQUE INDEX mdx_key ON mdx_index (keyword)")
+            .execute("CREATE UNIQUE INDEX mdx_key ON mdx_index (keyword, entry)")
             .await?;
+        transaction.execute(DB_FUZZY).await?;
+        // fuzzy search falls back to the index of keywords by length if sqlite is too old
+        match transaction.execute(DB_TRIGRAM).await {
+            Ok(_) => {
+                transaction
+                    .execute(
+                        sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                            .bind("fuzzy")
+                            .bind("trigram"),
+                    )
+                    .await?;
+            }
+            Err(e) => warn!("Failed to build trigram index for fuzzy search: {}", e),
+        }
+        transaction
+            .execute("CREATE INDEX mdx_normalized_key ON mdx_normalized (normalized)")
+            .await?;
+        transaction
+            .execute("CREATE INDEX mdx_pinyin_key ON mdx_pinyin (pinyin)")
+            .await?;
+        transaction
+            .execute("CREATE INDEX mdx_phonetic_key ON mdx_phonetic (code)")
+            .await?;
+        dedup_resources(&mut transaction).await?;
         transaction
             .execute("CREATE UNIQUE INDEX mdd_key ON mdd_index (keyword)")
             .await?;
@@ -88,118 +301,319 @@ impl MDictSqliteBuilder {
                     .bind(env!("CARGO_PKG_VERSION")),
             )
             .await?;
+        transaction
+            .execute(
+                sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                    .bind("schema")
+                    .bind(DB_SCHEMA),
+            )
+            .await?;
+        // the old index of the mdx file is found by the path after the file is changed
+        transaction
+            .execute(
+                sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                    .bind("path")
+                    .bind(self.mdx_file.to_string_lossy().into_owned()),
+            )
+            .await?;
+        for (key, identity) in identities {
+            transaction
+                .execute(
+                    sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                        .bind(key)
+                        .bind(identity),
+                )
+                .await?;
+        }
+        if self.options.chinese.is_some() {
+            transaction
+                .execute(
+                    sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                        .bind("chinese")
+                        .bind("simplified"),
+                )
+                .await?;
+        }
+        if self.options.pinyin.is_some() {
+            transaction
+                .execute(
+                    sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                        .bind("pinyin")
+                        .bind("tones"),
+                )
+                .await?;
+        }
+        if let Some(encoder) = &self.options.phonetic {
+            transaction
+                .execute(
+                    sqlx::query("insert into meta (key, value) values ( ?1, ?2)")
+                        .bind("phonetic")
+                        .bind(encoder.name()),
+                )
+                .await?;
+        }
         transaction.commit().await?;
         info!("Build index cache in {:?}", now.elapsed());
+        // close the connection explicitly, otherwise the database may be still locked when it's reopened
+        self.conn.close().await?;
         Ok(())
     }
     async fn write_mdx(&mut self) -> sqlx::Result<()> {
         let now = std::time::Instant::now();
+        let mut batches = read_index(self.mdx_file.clone(), MDictMode::Mdx, &self.options);
         let mut transaction = self.conn.begin().await?;
-        for (i, v) in self.index.mdx_block.iter().enumerate() {
-            let mdx_block = MdxBlock {
-                block_index: i as i32,
-                block_offset: v.offset as i64,
-                block_size: v.comp_size as i64,
-            };
-            sqlx::query!(
-                r"
-                    insert into mdx_block (block_index, block_offset, block_size)
-                    values ( ?1, ?2, ?3 )
-                ",
-                mdx_block.block_index,
-                mdx_block.block_offset,
-                mdx_block.block_size
-            )
-            .execute(&mut transaction)
-            .await?;
+        let mut fts_rows = BulkInsert::new("mdx_fts", &["keyword", "text"]);
+        let mut normalized_rows = BulkInsert::new("mdx_normalized", &["normalized", "keyword"]);
+        let mut pinyin_rows = BulkInsert::new("mdx_pinyin", &["pinyin", "tones", "keyword"]);
+        let mut phonetic_rows = BulkInsert::new("mdx_phonetic", &["code", "keyword"]);
+        let mut index_rows = BulkInsert::new(
+            "mdx_index",
+            &[
+                "keyword",
+                "entry",
+                "block_index",
+                "record_offset",
+                "record_size",
+            ],
+        );
+        let mut block_rows = BulkInsert::new(
+            "mdx_block",
+            &[
+                "block_index",
+                "block_offset",
+                "block_size",
+                "block_uncomp_size",
+            ],
+        );
+        let mut inserted = 0;
+        let mut entry = 0;
+        while let Some(batch) = batches.next().await {
+            match batch? {
+                KeyBatch::Keys(keys, progress) => {
+                    inserted += keys.len() as u64;
+                    for (keyword, v, text) in keys {
+                        if let Some(text) = text {
+                            fts_rows
+                                .push(&mut transaction, vec![keyword.clone().into(), text.into()])
+                                .await?;
+                        }
+                        let normalized = normalized_key(&keyword, self.options.chinese.as_deref());
+                        // only keywords which are different after normalized
+                        if normalized != keyword {
+                            normalized_rows
+                                .push(
+                                    &mut transaction,
+                                    vec![normalized.into(), keyword.clone().into()],
+                                )
+                                .await?;
+                        }
+                        if let Some(table) = &self.options.pinyin {
+                            // pinyin without tones like "zhongguo", and with tones like
+                            // "zhong1 guo2"
+                            for tones in table.keyword_pinyin(&keyword) {
+                                let row = vec![
+                                    pinyin::toneless(&tones).into(),
+                                    tones.into(),
+                                    keyword.clone().into(),
+                                ];
+                                pinyin_rows.push(&mut transaction, row).await?;
+                            }
+                        }
+                        if let Some(encoder) = &self.options.phonetic {
+                            for code in encoder.encode(&keyword) {
+                                phonetic_rows
+                                    .push(
+                                        &mut transaction,
+                                        vec![code.into(), keyword.clone().into()],
+                                    )
+                                    .await?;
+                            }
+                        }
+                        // `entry` is the ordinal of the record in the file, records of duplicate
+                        // keywords are ordered by it
+                        let row = vec![
+                            keyword.into(),
+                            entry.into(),
+                            (v.block as i32).into(),
+                            (v.offset as i32).into(),
+                            (v.len as i32).into(),
+                        ];
+                        index_rows.push(&mut transaction, row).await?;
+                        entry += 1;
+                    }
+                    report_inserted(&self.options, progress, inserted)?;
+                }
+                KeyBatch::Blocks(blocks) => {
+                    for (i, v) in blocks.iter().enumerate() {
+                        let row = vec![
+                            (i as i32).into(),
+                            (v.offset as i64).into(),
+                            (v.comp_size as i64).into(),
+                            (v.uncomp_size as i64).into(),
+                        ];
+                        block_rows.push(&mut transaction, row).await?;
+                    }
+                }
+            }
         }
-        transaction.commit().await?;
-        info!("Build mdx block index in {:?}", now.elapsed());
-        let mut transaction = self.conn.begin().await?;
-        let now = std::time::Instant::now();
-        for (k, v) in self.index.mdx_index.iter() {
-            let mdx_index = MdxIndex {
-                keyword: String::from_utf8(k).unwrap(),
-                block_index: v.block as i32,
-                record_offset: v.offset as i32,
-                record_size: v.len as i32,
-            };
-            sqlx::query!(
-                r"
-                    insert into mdx_index (keyword, block_index, record_offset, record_size)
-                    values ( ?1, ?2, ?3 , ?4)
-                ",
-                mdx_index.keyword,
-                mdx_index.block_index,
-                mdx_index.record_offset,
-                mdx_index.record_size
-            )
-            .execute(&mut transaction)
-            .await?;
+        let mut rows = 0;
+        for table in [
+            &mut fts_rows,
+            &mut normalized_rows,
+            &mut pinyin_rows,
+            &mut phonetic_rows,
+            &mut index_rows,
+            &mut block_rows,
+        ] {
+            table.flush(&mut transaction).await?;
+            rows += table.inserted;
         }
         transaction.commit().await?;
-        info!("Build mdx keyword index in {:?}", now.elapsed());
+        log_inserted("mdx", rows, now.elapsed());
         Ok(())
     }
     async fn write_mdd(&mut self) -> sqlx::Result<()> {
-        let now = std::time::Instant::now();
-        let mut transaction = self.conn.begin().await?;
-        for (i, file) in self.index.mdd_blocks.iter().enumerate() {
-            for (j, block) in file.iter().enumerate() {
-                let mdd_block = MddBlock {
-                    file_index: i as i32,
-                    block_index: j as i32,
-                    block_offset: block.offset as i64,
-                    block_size: block.comp_size as i64,
-                };
-                sqlx::query!(
-                    r"
-                        insert into mdd_block (file_index, block_index, block_offset, block_size)
-                        values ( ?1, ?2, ?3, ?4 )
-                    ",
-                    mdd_block.file_index,
-                    mdd_block.block_index,
-                    mdd_block.block_offset,
-                    mdd_block.block_size
-                )
-                .execute(&mut transaction)
-                .await?;
+        for (i, file) in self.mdd_files.iter().enumerate() {
+            let now = std::time::Instant::now();
+            let mut batches = read_index(file.clone(), MDictMode::Mdd, &self.options);
+            let mut transaction = self.conn.begin().await?;
+            let mut index_rows = BulkInsert::new(
+                "mdd_index",
+                &[
+                    "keyword",
+                    "file_index",
+                    "block_index",
+                    "record_offset",
+                    "record_size",
+                ],
+            );
+            let mut block_rows = BulkInsert::new(
+                "mdd_block",
+                &[
+                    "file_index",
+                    "block_index",
+                    "block_offset",
+                    "block_size",
+                    "block_uncomp_size",
+                ],
+            );
+            let mut inserted = 0;
+            while let Some(batch) = batches.next().await {
+                match batch? {
+                    KeyBatch::Keys(keys, progress) => {
+                        inserted += keys.len() as u64;
+                        for (key, index, _) in keys {
+                            let row = vec![
+                                resource_name(&key)?.into(),
+                                (i as i32).into(),
+                                (index.block as i32).into(),
+                                (index.offset as i32).into(),
+                                (index.len as i32).into(),
+                            ];
+                            index_rows.push(&mut transaction, row).await?;
+                        }
+                        report_inserted(&self.options, progress, inserted)?;
+                    }
+                    KeyBatch::Blocks(blocks) => {
+                        for (j, block) in blocks.iter().enumerate() {
+                            let row = vec![
+                                (i as i32).into(),
+                                (j as i32).into(),
+                                (block.offset as i64).into(),
+                                (block.comp_size as i64).into(),
+                                (block.uncomp_size as i64).into(),
+                            ];
+                            block_rows.push(&mut transaction, row).await?;
+                        }
+                    }
+                }
             }
+            index_rows.flush(&mut transaction).await?;
+            block_rows.flush(&mut transaction).await?;
+            transaction.commit().await?;
+            log_inserted(
+                &format!("mdd {}", i),
+                index_rows.inserted + block_rows.inserted,
+                now.elapsed(),
+            );
         }
-        transaction.commit().await?;
-        info!("Build mdd block index in {:?}", now.elapsed());
-        let mut transaction = self.conn.begin().await?;
-        let now = std::time::Instant::now();
-        for (ref key, (file, index)) in self.index.mdd_index.iter() {
-            let mdd_index = MddIndex {
-                keyword: String::from_utf8(key.to_vec()).unwrap(),
-                file_index: *file as i32,
-                block_index: index.block as i32,
-                record_offset: index.offset as i32,
-                record_size: index.len as i32,
-            };
-            sqlx::query!(
-                r"
-                    insert into mdd_index (keyword, file_index, block_index, record_offset, record_size)
-                    values ( ?1, ?2, ?3, ?4, ?5 )
-                ",
-                mdd_index.keyword,
-                mdd_index.file_index,
-                mdd_index.block_index,
-                mdd_index.record_offset,
-                mdd_index.record_size
-            )
-            .execute(&mut transaction)
-            .await?;
-        }
-        transaction.commit().await?;
-        info!("Build mdd keyword index in {:?}", now.elapsed());
         Ok(())
     }
 }
 
-async fn open_db(file: impl AsRef<Path>) -> Option<SqlitePool> {
-    let db_file = file.as_ref().with_extension("db");
+// A resource may be in many mdd files or twice in a mdd file, only keep the last one like
+// `MDictMemIndex`. Rows are inserted in the order of mdd files and their keywords, so the last
+// one has the largest rowid.
+async fn dedup_resources(transaction: &mut Transaction<'_, Sqlite>) -> sqlx::Result<()> {
+    let deleted = transaction
+        .execute(
+            r"
+                DELETE FROM mdd_index WHERE rowid NOT IN
+                (SELECT max(rowid) FROM mdd_index GROUP BY keyword)
+            ",
+        )
+        .await?
+        .rows_affected();
+    if deleted > 0 {
+        info!("Skip {} duplicate resources in mdd files", deleted);
+    }
+    Ok(())
+}
+
+// Log the speed of inserting rows of a file.
+fn log_inserted(what: &str, rows: u64, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    let speed = if seconds > 0.0 {
+        rows as f64 / seconds
+    } else {
+        0.0
+    };
+    info!(
+        "Build {} index in {:?}, {} rows, {:.0} rows/s",
+        what, elapsed, rows, speed
+    );
+}
+
+// Report the progress after a batch of keywords is inserted.
+fn report_inserted(
+    options: &MDictBuildOptions,
+    progress: Option<MDictProgress>,
+    inserted: u64,
+) -> io::Result<()> {
+    match progress {
+        Some(mut progress) => {
+            progress.phase = MDictBuildPhase::DatabaseInsert;
+            progress.entries_done = inserted;
+            options.report(&progress)
+        }
+        None => Ok(()),
+    }
+}
+
+// Identities of files by keys in `meta`, like `identity.mdx` and `identity.mdd.0`
+fn file_identities(mdx_file: &Path, mdd_files: &[PathBuf]) -> io::Result<Vec<(String, String)>> {
+    let mut identities = vec![(
+        format!("{}mdx", IDENTITY_KEY),
+        MDictFileIdentity::new(mdx_file)?.to_string(),
+    )];
+    for (i, mdd_file) in mdd_files.iter().enumerate() {
+        identities.push((
+            format!("{}mdd.{}", IDENTITY_KEY, i),
+            MDictFileIdentity::new(mdd_file)?.to_string(),
+        ));
+    }
+    identities.sort();
+    Ok(identities)
+}
+
+async fn open_db(file: impl AsRef<Path>, build_options: &MDictBuildOptions) -> Option<SqlitePool> {
+    let db_file = match build_options.cache_file(file.as_ref(), "db") {
+        Ok(db_file) => db_file,
+        Err(e) => {
+            error!("Failed to find index: {}", e);
+            return None;
+        }
+    };
     if !db_file.exists() {
         info!("Index not exists");
         return None;
@@ -224,6 +638,48 @@ async fn open_db(file: impl AsRef<Path>) -> Option<SqlitePool> {
             if v.as_str() != env!("CARGO_PKG_VERSION") {
                 return None;
             }
+            let schema: Option<String> =
+                sqlx::query_scalar("select value from meta where key = \"schema\"")
+                    .fetch_optional(&db)
+                    .await
+                    .ok()
+                    .flatten();
+            if schema.as_deref() != Some(DB_SCHEMA) {
+                info!("Index schema is outdated, need rebuilt");
+                return None;
+            }
+            if build_options.full_text && !has_full_text(&db).await {
+                info!("Full-text index is not built, need rebuilt");
+                return None;
+            }
+            if build_options.chinese.is_some() && meta_value(&db, "chinese").await.is_none() {
+                info!("Chinese keywords are not indexed, need rebuilt");
+                return None;
+            }
+            if build_options.pinyin.is_some() && meta_value(&db, "pinyin").await.is_none() {
+                info!("Pinyin of keywords is not indexed, need rebuilt");
+                return None;
+            }
+            if let Some(encoder) = &build_options.phonetic {
+                if meta_value(&db, "phonetic").await.as_deref() != Some(encoder.name()) {
+                    info!(
+                        "Keywords are not indexed by {}, need rebuilt",
+                        encoder.name()
+                    );
+                    return None;
+                }
+            }
+            let identities = file_identities(file.as_ref(), &find_mdd_files(file.as_ref())).ok();
+            let built: Option<Vec<(String, String)>> =
+                sqlx::query_as("select key, value from meta where key like ?1 order by key")
+                    .bind(format!("{}%", IDENTITY_KEY))
+                    .fetch_all(&db)
+                    .await
+                    .ok();
+            if identities.is_none() || identities != built {
+                info!("Dictionary files are changed, need rebuilt");
+                return None;
+            }
             info!("Find index DB for mdict_index {}", v.as_str());
             Some(db)
         }
@@ -234,35 +690,233 @@ async fn open_db(file: impl AsRef<Path>) -> Option<SqlitePool> {
     }
 }
 
-async fn build_db(mdx_file: impl AsRef<Path>) -> sqlx::Result<SqlitePool> {
-    let db_file = mdx_file.as_ref().with_extension("db");
+async fn build_db(
+    mdx_file: impl AsRef<Path>,
+    mdd_files: &[PathBuf],
+    build_options: &MDictBuildOptions,
+) -> sqlx::Result<SqlitePool> {
+    let db_file = build_options.cache_file(mdx_file.as_ref(), "db")?;
+    // the old index may be used until the new one is built
+    let build_file = db_file.with_extension("db.build");
     info!("Build index to {}", &db_file.to_string_lossy());
-    if db_file.exists() {
-        fs::remove_file(&db_file)?;
+    if let Some(dir) = db_file.parent() {
+        fs::create_dir_all(dir)?;
     }
+    if build_file.exists() {
+        fs::remove_file(&build_file)?;
+    }
+    // records blocks are inserted after keywords, so disable foreign keys when building
     let options = SqliteConnectOptions::new()
-        .filename(&db_file)
-        .foreign_keys(true)
+        .filename(&build_file)
+        .foreign_keys(false)
         .read_only(false)
-        .journal_mode(SqliteJournalMode::Wal)
+        .journal_mode(SqliteJournalMode::Off)
         .create_if_missing(true);
-    let conn = options.connect().await?;
-    let index = MDictMemIndex::new(&mdx_file).map_err(sqlx::Error::Io)?;
-    let builder = MDictSqliteBuilder { conn, index };
-    builder.build().await?;
-    // open in writeable + journal mode = delete to remove db-wal file
+    let mut conn = options.connect().await?;
+    // a temporary file is not used if the build is failed, so it's not needed to be safe from
+    // crashes while building
+    conn.execute(
+        r"
+            PRAGMA synchronous = OFF;
+            PRAGMA temp_store = MEMORY;
+            PRAGMA cache_size = -65536;
+        ",
+    )
+    .await?;
+    let builder = MDictSqliteBuilder {
+        conn,
+        mdx_file: mdx_file.as_ref().to_owned(),
+        mdd_files: mdd_files.to_vec(),
+        options: build_options.clone(),
+    };
+    if let Err(e) = builder.build().await {
+        // a cancelled or failed build is not left in the cache directory
+        let _ = fs::remove_file(&build_file);
+        return Err(e);
+    }
+    // it's written without syncing, so sync it before it replaces the old index
+    File::open(&build_file)?.sync_all()?;
+    fs::rename(&build_file, &db_file)?;
+    remove_stale_indexes(mdx_file.as_ref(), &db_file, build_options).await;
+    remove_legacy_index(mdx_file.as_ref()).await;
+    // the identities of files don't match the index if they are changed while building
+    open_db(mdx_file, build_options).await.ok_or_else(|| {
+        sqlx::Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            "Failed to open the index after building, files may be changed while building",
+        ))
+    })
+}
+
+// Indexes of `mdx_file` before it's changed, which are named by the old identities.
+// Indexes of other dictionaries with the same name are kept by checking the path in `meta`.
+async fn remove_stale_indexes(mdx_file: &Path, db_file: &Path, build_options: &MDictBuildOptions) {
+    let path = mdx_file.to_string_lossy();
+    for stale in build_options.stale_cache_files(mdx_file, "db", db_file) {
+        let db = match connect_read_only(&stale).await {
+            Some(db) => db,
+            None => continue,
+        };
+        let built_from = meta_value(&db, "path").await;
+        db.close().await;
+        if built_from.as_deref() == Some(path.as_ref()) && fs::remove_file(&stale).is_ok() {
+            info!("Remove old index {}", stale.to_string_lossy());
+        }
+    }
+}
+
+// Indexes were built next to the mdx file before the cache directory, they are not used anymore.
+// Only files which have the tables of this crate are removed, so a database of the same name
+// from elsewhere is kept. Errors are ignored, like the mdx file is on read-only media.
+async fn remove_legacy_index(mdx_file: &Path) {
+    for ext in &["db", "db.build"] {
+        let file = mdx_file.with_extension(ext);
+        if !file.is_file() {
+            continue;
+        }
+        let db = match connect_read_only(&file).await {
+            Some(db) => db,
+            None => continue,
+        };
+        // a build which was interrupted may have no rows in `meta`, so only tables are checked
+        let tables: Vec<String> = sqlx::query_scalar(
+            r"
+                select name from sqlite_master
+                where type = 'table' and name in ('meta', 'mdx_index', 'mdx_block')
+            ",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap_or_default();
+        db.close().await;
+        if tables.len() == 3 && fs::remove_file(&file).is_ok() {
+            info!("Remove old index {}", file.to_string_lossy());
+        }
+    }
+}
+
+async fn connect_read_only(db_file: &Path) -> Option<SqlitePool> {
     let options = SqliteConnectOptions::new()
         .filename(db_file)
-        .foreign_keys(true)
-        .read_only(false)
-        .journal_mode(SqliteJournalMode::Delete)
+        .read_only(true)
         .create_if_missing(false);
-    let conn = options.connect().await?;
-    conn.close().await?;
-    let pool = open_db(mdx_file)
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .ok()
+}
+
+// Collect blocks from `rows` which starts with the block of `index`, until they contain the record.
+async fn record_blocks<S>(
+    mut rows: S,
+    index: &MDictRecordIndex,
+) -> io::Result<Vec<MDictRecordBlockIndex>>
+where
+    S: Stream<Item = sqlx::Result<BlockQuery>> + Unpin,
+{
+    let end = index.offset as i64 + index.len as i64;
+    let mut blocks = Vec::new();
+    let mut size = 0;
+    while let Some(row) = rows.next().await {
+        let row = row.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        blocks.push(MDictRecordBlockIndex {
+            offset: row.block_offset as u64,
+            comp_size: row.block_size as u64,
+            uncomp_size: row.block_uncomp_size as u64,
+        });
+        size += row.block_uncomp_size;
+        if size >= end {
+            break;
+        }
+    }
+    Ok(blocks)
+}
+
+async fn has_full_text(db: &SqlitePool) -> bool {
+    let table: Option<String> = sqlx::query_scalar(
+        "select name from sqlite_master where type = 'table' and name = 'mdx_fts'",
+    )
+    .fetch_optional(db)
+    .await
+    .ok()
+    .flatten();
+    table.is_some()
+}
+
+// The value of `key` in the meta table, optional indexes are recorded in it
+async fn meta_value(db: &SqlitePool, key: &str) -> Option<String> {
+    sqlx::query_scalar("select value from meta where key = ?1")
+        .bind(key)
+        .fetch_optional(db)
         .await
-        .expect("Failed to open DB after build");
-    Ok(pool)
+        .ok()
+        .flatten()
+}
+
+// The least string greater than all strings starting with `prefix`.
+//
+// The order of UTF-8 bytes is the same as the order of code points, so increase the last char
+// which isn't the max char. Returns `None` if there is no such char.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(c) = chars.pop() {
+        let next = match c {
+            // skip surrogates
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => std::char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+// The shortest piece found by the trigram index
+const MIN_TRIGRAM_PIECE: usize = 3;
+
+// Split `key` into pieces, a keyword within `max_distance` contains at least one of them.
+//
+// An edit changes at most one of `max_distance + 1` pieces. A transposition may change two
+// adjacent pieces, so the pieces are separated by one char for the Damerau distance, then a
+// transposition with the char changes at most one piece. Returns no piece if `key` is too short.
+fn fuzzy_pieces(key: &str, max_distance: usize, metric: MDictDistance) -> Vec<String> {
+    let chars: Vec<char> = key.chars().collect();
+    let num = max_distance + 1;
+    let gap = match metric {
+        MDictDistance::Levenshtein => 0,
+        MDictDistance::Damerau => 1,
+    };
+    let len = match chars.len().checked_sub(gap * max_distance) {
+        Some(len) if len >= num => len,
+        _ => return Vec::new(),
+    };
+    (0..num)
+        .map(|i| {
+            let start = len * i / num + gap * i;
+            let end = len * (i + 1) / num + gap * i;
+            chars[start..end].iter().collect()
+        })
+        .collect()
+}
+
+// `*piece*` of GLOB, special chars in `piece` are matched literally.
+fn glob_contains(piece: &str) -> String {
+    let mut glob = String::from("*");
+    for c in piece.chars() {
+        match c {
+            '*' | '?' | '[' => {
+                glob.push('[');
+                glob.push(c);
+                glob.push(']');
+            }
+            c => glob.push(c),
+        }
+    }
+    glob.push('*');
+    glob
 }
 
 pub struct MDictSqliteIndex {
@@ -270,10 +924,23 @@ pub struct MDictSqliteIndex {
     mdx_file: PathBuf,
     mdd_files: Vec<PathBuf>,
     header: MDictHeader,
+    full_text: bool,
+    // whether keywords are indexed by trigrams for fuzzy search
+    trigram: bool,
+    chinese: Option<Arc<MDictChineseConverter>>,
+    phonetic: Option<Arc<dyn MDictPhoneticEncoder>>,
 }
 
 impl MDictSqliteIndex {
     pub async fn new<P: AsRef<Path>>(path: P) -> sqlx::Result<MDictSqliteIndex> {
+        MDictSqliteIndex::with_options(path, &MDictBuildOptions::default()).await
+    }
+    /// Open the index, or build it with progress reporting or cancellation if it's not built,
+    /// see [`MDictBuildOptions`].
+    pub async fn with_options<P: AsRef<Path>>(
+        path: P,
+        options: &MDictBuildOptions,
+    ) -> sqlx::Result<MDictSqliteIndex> {
         let mdx_file = path.as_ref().canonicalize()?;
         if !mdx_file.is_file()
             || mdx_file
@@ -289,38 +956,74 @@ impl MDictSqliteIndex {
             )));
         }
         info!("mdx: {}", mdx_file.to_string_lossy());
-        let pool = match open_db(&mdx_file).await {
+        let mdd_files = find_mdd_files(&mdx_file);
+        let db = if options.rebuild {
+            None
+        } else {
+            open_db(&mdx_file, options).await
+        };
+        let pool = match db {
             Some(db) => db,
-            None => build_db(&mdx_file).await?,
+            None => build_db(&mdx_file, &mdd_files, options).await?,
         };
         let header = MDictHeader::new(
             OpenOptions::new().read(true).open(&mdx_file)?,
             MDictMode::Mdx,
         )?;
-        let mut mdd_files = Vec::new();
-        let mdd0 = mdx_file.with_extension("mdd");
-        if mdd0.is_file() {
-            mdd_files.push(mdd0);
-            for i in 1.. {
-                let ext = format!("{}.mdd", i);
-                let mddi = mdx_file.with_extension(ext);
-                if mddi.is_file() {
-                    mdd_files.push(mddi);
-                } else {
-                    break;
-                }
-            }
-        }
-        for i in mdd_files.iter() {
-            info!("mdd: {}", i.to_string_lossy());
-        }
+        let full_text = has_full_text(&pool).await;
+        let trigram = meta_value(&pool, "fuzzy").await.as_deref() == Some("trigram");
+        // keywords in Simplified Chinese are not used if the option is not given
+        let chinese = options.chinese.clone();
+        let phonetic = options.phonetic.clone();
         Ok(MDictSqliteIndex {
             pool,
             mdx_file,
             mdd_files,
             header,
+            full_text,
+            trigram,
+            chinese,
+            phonetic,
         })
     }
+    /// Search records by the full-text index, which is built if
+    /// [`full_text`](MDictBuildOptions::full_text) of the build options is enabled.
+    ///
+    /// `query` is a full-text query of sqlite FTS5, such as `photosynthesis`, `"light reaction"`
+    /// or `plant AND cell`. At most `limit` best matches are returned.
+    pub async fn full_text_search(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> io::Result<Vec<MDictFullTextMatch>> {
+        if !self.full_text {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Full-text index is not built",
+            ));
+        }
+        let rows: Vec<(String, String, f64)> = sqlx::query_as(
+            r"
+                select keyword, snippet(mdx_fts, 1, ?1, ?2, '...', 16), rank from mdx_fts
+                where mdx_fts match ?3 order by rank limit ?4
+            ",
+        )
+        .bind(SNIPPET_START)
+        .bind(SNIPPET_END)
+        .bind(query)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(rows
+            .into_iter()
+            .map(|(keyword, snippet, rank)| MDictFullTextMatch {
+                keyword,
+                snippet: snippet_html(&snippet),
+                rank,
+            })
+            .collect())
+    }
     pub async fn close(&self) {
         info!("Shutdown ...");
         self.pool.close().await;
@@ -330,69 +1033,298 @@ impl MDictSqliteIndex {
 #[async_trait]
 impl MDictAsyncLookup for MDictSqliteIndex {
     async fn word_exists(&self, key: &str) -> io::Result<bool> {
-        let query = sqlx::query!("select keyword from mdx_index where keyword = ?1", key)
-            .fetch_optional(&self.pool)
-            .await
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let query = sqlx::query!(
+            "select keyword from mdx_index where keyword = ?1 limit 1",
+            key
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         Ok(query.is_some())
     }
-    async fn lookup_word(&self, key: &str) -> io::Result<String> {
-        let query: Option<MdxQuery> =
-            sqlx::query_as("select * from mdx_index natural join mdx_block where keyword = ?1")
-                .bind(key)
-                .fetch_optional(&self.pool)
-                .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        match query {
-            Some(result) => {
-                let file: tokio::fs::File = tokio::fs::OpenOptions::new()
-                    .read(true)
-                    .open(&self.mdx_file)
-                    .await?;
-                let key = MDictRecordIndex {
-                    block: result.block_index as u32,
-                    offset: result.record_offset as u32,
-                    len: result.record_size as u32,
-                };
-                let block = MDictRecordBlockIndex {
-                    offset: result.block_offset as u64,
-                    comp_size: result.block_size as u64,
-                };
-                let bytes = lookup(file, &key, &block).await?;
-                let decoded = self.header.decode_string(bytes)?;
-                Ok(decoded)
-            }
-            None => Err(io::Error::new(
+    async fn lookup_word(&self, key: &str) -> io::Result<Vec<String>> {
+        let query: Vec<MdxQuery> = sqlx::query_as(
+            r"
+                select block_index, record_offset, record_size from mdx_index
+                where keyword = ?1 order by entry
+            ",
+        )
+        .bind(key)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if query.is_empty() {
+            return Err(io::Error::new(
                 io::ErrorKind::NotFound,
                 "Not found in index",
-            )),
+            ));
+        }
+        let mut decoded = Vec::with_capacity(query.len());
+        for result in query {
+            let index = MDictRecordIndex {
+                block: result.block_index as u32,
+                offset: result.record_offset as u32,
+                len: result.record_size as u32,
+            };
+            let rows = sqlx::query_as(
+                r"
+                    select block_offset, block_size, block_uncomp_size from mdx_block
+                    where block_index >= ?1 order by block_index
+                ",
+            )
+            .bind(result.block_index)
+            .fetch(&self.pool);
+            let blocks = record_blocks(rows, &index).await?;
+            let record = MDictResource::new(self.mdx_file.clone(), index, blocks);
+            decoded.push(self.header.decode_string(record.read().await?)?);
         }
+        Ok(decoded)
     }
 
-    async fn lookup_resource(&self, key: &str) -> io::Result<Bytes> {
-        let query: Option<MddQuery> =
-            sqlx::query_as("select * from mdd_index natural join mdd_block where keyword = ?1")
-                .bind(key)
-                .fetch_optional(&self.pool)
-                .await
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    async fn normalized_candidates(&self, key: &str) -> io::Result<Vec<String>> {
+        // `union` removes duplicate keywords
+        sqlx::query_scalar(
+            r"
+                select keyword from mdx_index where keyword = ?1
+                union select keyword from mdx_normalized where normalized = ?1
+                order by keyword
+            ",
+        )
+        .bind(normalized_key(key, self.chinese.as_deref()))
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    async fn pinyin_search(&self, query: &str, limit: usize) -> io::Result<Vec<String>> {
+        let query = match PinyinQuery::parse(query) {
+            Some(query) => query,
+            None => return Ok(Vec::new()),
+        };
+        let rows: Vec<(String, String)> = sqlx::query_as(
+            "select tones, keyword from mdx_pinyin where pinyin = ?1 order by keyword",
+        )
+        .bind(query.toneless())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mut keywords: Vec<String> = rows
+            .into_iter()
+            .filter(|(tones, _)| query.matches(tones))
+            .map(|(_, keyword)| keyword)
+            .collect();
+        keywords.dedup();
+        keywords.truncate(limit);
+        Ok(keywords)
+    }
+
+    async fn phonetic_search(&self, key: &str, limit: usize) -> io::Result<Vec<(String, usize)>> {
+        let encoder = match &self.phonetic {
+            Some(encoder) => encoder,
+            None => return Ok(Vec::new()),
+        };
+        let mut keywords: Vec<String> = Vec::new();
+        let mut seen = HashSet::new();
+        for code in encoder.encode(key) {
+            let rows: Vec<String> =
+                sqlx::query_scalar("select keyword from mdx_phonetic where code = ?1")
+                    .bind(code)
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            for keyword in rows {
+                if seen.insert(keyword.clone()) {
+                    keywords.push(keyword);
+                }
+            }
+        }
+        Ok(phonetic::rank(key, keywords, limit))
+    }
+
+    async fn keywords_after(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        sqlx::query_scalar(
+            "select distinct keyword from mdx_index where keyword > ?1 order by keyword limit ?2",
+        )
+        .bind(key)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    async fn keywords_before(&self, key: &str, limit: usize) -> io::Result<Vec<String>> {
+        let mut keywords: Vec<String> = sqlx::query_scalar(
+            "select distinct keyword from mdx_index where keyword < ?1 order by keyword desc limit ?2",
+        )
+        .bind(key)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        keywords.reverse();
+        Ok(keywords)
+    }
+
+    async fn suggest(&self, prefix: &str, limit: usize) -> io::Result<Vec<String>> {
+        // `LIKE` is case insensitive and can't use the index, use a range of keyword instead.
+        // `length` counts chars of text, the exact match is the shortest one.
+        let query = match prefix_upper_bound(prefix) {
+            Some(upper) => sqlx::query_scalar(
+                r"
+                    select distinct keyword from mdx_index where keyword >= ?1 and keyword < ?2
+                    order by length(keyword), keyword limit ?3
+                ",
+            )
+            .bind(prefix)
+            .bind(upper),
+            None => sqlx::query_scalar(
+                r"
+                    select distinct keyword from mdx_index where keyword >= ?1
+                    order by length(keyword), keyword limit ?2
+                ",
+            )
+            .bind(prefix),
+        };
+        query
+            .bind(limit as i64)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    async fn fuzzy_search(
+        &self,
+        key: &str,
+        max_distance: usize,
+        metric: MDictDistance,
+        limit: usize,
+    ) -> io::Result<Vec<(String, usize)>> {
+        // Filter candidates in sqlite by the length and the pieces of `key`,
+        // then calculate the distance of them.
+        let len = key.chars().count();
+        let pieces = fuzzy_pieces(key, max_distance, metric);
+        let mut sql =
+            String::from("select keyword from mdx_keyword where length between ?1 and ?2");
+        let mut params = Vec::new();
+        if self.trigram
+            && !pieces.is_empty()
+            && pieces
+                .iter()
+                .all(|p| p.chars().count() >= MIN_TRIGRAM_PIECE)
+        {
+            // keywords containing the pieces are found by the trigram index, keywords of
+            // similar length are scanned if the pieces are too short for trigrams.
+            // Each piece is a query, sqlite may scan the whole table for `OR`.
+            let filter: Vec<String> = (0..pieces.len())
+                .map(|i| {
+                    format!(
+                        "select rowid from mdx_trigram where keyword glob ?{}",
+                        i + 3
+                    )
+                })
+                .collect();
+            sql.push_str(&format!(" and id in ({})", filter.join(" union ")));
+            params.extend(pieces.iter().map(|p| glob_contains(p)));
+        } else if !pieces.is_empty() {
+            let filter: Vec<String> = (0..pieces.len())
+                .map(|i| format!("instr(keyword, ?{}) > 0", i + 3))
+                .collect();
+            sql.push_str(&format!(" and ({})", filter.join(" or ")));
+            params = pieces;
+        }
+        let mut query = sqlx::query_scalar(&sql)
+            .bind(len.saturating_sub(max_distance) as i64)
+            .bind(len.saturating_add(max_distance).min(i64::MAX as usize) as i64);
+        for param in params.iter() {
+            query = query.bind(param.as_str());
+        }
+        let mut rows = query.fetch(&self.pool);
+        let mut distance = fuzzy::EditDistance::new(key, max_distance, metric);
+        let mut result = Vec::new();
+        while let Some(row) = rows.next().await {
+            let keyword: String = row.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            if let Some(d) = distance.matches(&keyword) {
+                result.push((keyword, d));
+            }
+        }
+        Ok(fuzzy::rank(result, limit))
+    }
+
+    async fn search_pattern(
+        &self,
+        pattern: &MDictPattern,
+        after: Option<&str>,
+        limit: usize,
+    ) -> io::Result<Vec<String>> {
+        let matcher = pattern.compile()?;
+        // The range of the literal prefix uses the index, `GLOB` is done by sqlite.
+        // sqlite doesn't provide `REGEXP`, so regex is matched here.
+        let prefix = matcher.prefix();
+        let upper = prefix_upper_bound(prefix);
+        // The next page starts after the last keyword, sqlite only seeks to one lower bound.
+        let (mut conditions, lower) = match after {
+            Some(after) if after >= prefix => (vec!["keyword > ?"], after),
+            _ => (vec!["keyword >= ?"], prefix),
+        };
+        if upper.is_some() {
+            conditions.push("keyword < ?");
+        }
+        if let MDictPattern::Glob(_) = pattern {
+            conditions.push("keyword glob ?");
+        }
+        let sql = format!(
+            "select distinct keyword from mdx_index where {} order by keyword",
+            conditions.join(" and ")
+        );
+        let mut query = sqlx::query_scalar(&sql).bind(lower);
+        if let Some(upper) = upper.as_deref() {
+            query = query.bind(upper);
+        }
+        if let MDictPattern::Glob(glob) = pattern {
+            query = query.bind(glob.as_str());
+        }
+        let mut rows = query.fetch(&self.pool);
+        let mut keywords = Vec::new();
+        while keywords.len() < limit {
+            match rows.next().await {
+                Some(row) => {
+                    let keyword: String =
+                        row.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    if matcher.is_match(&keyword) {
+                        keywords.push(keyword);
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(keywords)
+    }
+
+    async fn open_resource(&self, key: &str) -> io::Result<MDictResource> {
+        let query: Option<MddQuery> = sqlx::query_as("select * from mdd_index where keyword = ?1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
         match query {
             Some(result) => {
-                let file: tokio::fs::File = tokio::fs::OpenOptions::new()
-                    .read(true)
-                    .open(&self.mdd_files[result.file_index as usize])
-                    .await?;
-                let key = MDictRecordIndex {
+                let index = MDictRecordIndex {
                     block: result.block_index as u32,
                     offset: result.record_offset as u32,
                     len: result.record_size as u32,
                 };
-                let block = MDictRecordBlockIndex {
-                    offset: result.block_offset as u64,
-                    comp_size: result.block_size as u64,
-                };
-                let data = lookup(file, &key, &block).await?;
-                Ok(data)
+                let rows = sqlx::query_as(
+                    r"
+                        select block_offset, block_size, block_uncomp_size from mdd_block
+                        where file_index = ?1 and block_index >= ?2 order by block_index
+                    ",
+                )
+                .bind(result.file_index)
+                .bind(result.block_index)
+                .fetch(&self.pool);
+                let blocks = record_blocks(rows, &index).await?;
+                let file = self.mdd_files[result.file_index as usize].clone();
+                Ok(MDictResource::new(file, index, blocks))
             }
             None => Err(io::Error::new(
                 io::ErrorKind::NotFound,
@@ -401,3 +1333,113 @@ impl MDictAsyncLookup for MDictSqliteIndex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_last_duplicate_resource() {
+        futures_executor::block_on(async {
+            let mut conn = "sqlite::memory:"
+                .parse::<SqliteConnectOptions>()
+                .unwrap()
+                .foreign_keys(false)
+                .connect()
+                .await
+                .unwrap();
+            conn.execute(DB_INIT).await.unwrap();
+            let mut transaction = conn.begin().await.unwrap();
+            // `a.png` is twice in the first mdd file and once in the second
+            let mut rows = BulkInsert::new(
+                "mdd_index",
+                &[
+                    "keyword",
+                    "file_index",
+                    "block_index",
+                    "record_offset",
+                    "record_size",
+                ],
+            );
+            for (keyword, file_index, record_offset) in [
+                ("a.png", 0, 0),
+                ("b.png", 0, 10),
+                ("a.png", 0, 20),
+                ("a.png", 1, 0),
+            ] {
+                let row = vec![
+                    keyword.to_owned().into(),
+                    file_index.into(),
+                    0.into(),
+                    record_offset.into(),
+                    10.into(),
+                ];
+                rows.push(&mut transaction, row).await.unwrap();
+            }
+            rows.flush(&mut transaction).await.unwrap();
+            dedup_resources(&mut transaction).await.unwrap();
+            transaction
+                .execute("CREATE UNIQUE INDEX mdd_key ON mdd_index (keyword)")
+                .await
+                .unwrap();
+            transaction.commit().await.unwrap();
+            let found: Vec<(String, i32, i32)> = sqlx::query_as(
+                "select keyword, file_index, record_offset from mdd_index order by keyword",
+            )
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+            assert_eq!(
+                found,
+                vec![("a.png".to_owned(), 1, 0), ("b.png".to_owned(), 0, 10)]
+            );
+        });
+    }
+
+    #[test]
+    fn split_fuzzy_pieces() {
+        let pieces = fuzzy_pieces("cheatoushing", 1, MDictDistance::Levenshtein);
+        assert_eq!(pieces, vec!["cheato", "ushing"]);
+        // `a` and `s` are skipped, so a transposition changes one piece
+        let pieces = fuzzy_pieces("cheatoushing", 2, MDictDistance::Damerau);
+        assert_eq!(pieces, vec!["che", "tou", "hing"]);
+        assert!(fuzzy_pieces("zu", 2, MDictDistance::Levenshtein).is_empty());
+        assert_eq!(
+            fuzzy_pieces("abc", 1, MDictDistance::Damerau),
+            vec!["a", "c"]
+        );
+        assert!(fuzzy_pieces("ab", 1, MDictDistance::Damerau).is_empty());
+    }
+
+    #[test]
+    fn find_keywords_by_trigrams() {
+        futures_executor::block_on(async {
+            let mut conn = "sqlite::memory:"
+                .parse::<SqliteConnectOptions>()
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            conn.execute(DB_INIT).await.unwrap();
+            for keyword in ["apple", "pineapple", "a*ppl?", "banana"] {
+                sqlx::query("insert into mdx_keyword (keyword, length) values (?1, ?2)")
+                    .bind(keyword)
+                    .bind(keyword.chars().count() as i64)
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+            }
+            conn.execute(DB_TRIGRAM).await.unwrap();
+            let search = |piece: &str| {
+                sqlx::query_scalar(
+                    "select keyword from mdx_trigram where keyword glob ? order by keyword",
+                )
+                .bind(glob_contains(piece))
+            };
+            let found: Vec<String> = search("ppl").fetch_all(&mut conn).await.unwrap();
+            assert_eq!(found, vec!["a*ppl?", "apple", "pineapple"]);
+            let found: Vec<String> = search("*ppl?").fetch_all(&mut conn).await.unwrap();
+            assert_eq!(found, vec!["a*ppl?"]);
+        });
+    }
+}
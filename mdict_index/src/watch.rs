@@ -0,0 +1,515 @@
+use crate::{
+    scan::{find_mdx_files, scan_in},
+    MDictLibraryEntry, MDictScanned, MDictSharedLibrary,
+};
+use log::{error, info, warn};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::TryInto,
+    ffi::{CString, OsStr},
+    fs::{self, File},
+    io::{self, Read},
+    mem,
+    os::unix::{
+        ffi::OsStrExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+// Changes are collected until no file is changed in this time, so a dictionary copied with its
+// mdd files is rebuilt once.
+const DEBOUNCE_MS: i32 = 1000;
+const EVENT_BUF_SIZE: usize = 64 * 1024;
+const EVENT_HEADER_SIZE: usize = mem::size_of::<libc::inotify_event>();
+// files are changed after they are closed, but they are changing as soon as they are written
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_MODIFY
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_CREATE
+    | libc::IN_DELETE;
+
+/// A change of dictionaries found by [`MDictWatcher`]
+#[derive(Debug)]
+pub enum MDictChange {
+    /// The mdx file of a dictionary whose files are being changed, or a directory being moved.
+    /// Its old index shouldn't be used, because the offsets of records may be changed.
+    Changing(PathBuf),
+    /// A new dictionary, or a dictionary whose mdx or mdd files are changed
+    Updated(MDictScanned),
+    /// A removed mdx file, or a removed directory with all dictionaries in it
+    Removed(PathBuf),
+}
+
+/// Watch dictionaries in a directory and its subdirectories by inotify.
+///
+/// Files are found like [`mdict_scan`](crate::mdict_scan), hidden files are ignored, so a
+/// dictionary downloaded to a hidden temporary file and renamed is found once.
+pub struct MDictWatcher {
+    inotify: File,
+    root: PathBuf,
+    // watched directories by watch descriptors
+    dirs: HashMap<i32, PathBuf>,
+    // changed paths waiting until no file is changed
+    pending: BTreeSet<PathBuf>,
+    // pending paths which are reported as changing
+    changing: HashSet<PathBuf>,
+}
+
+impl MDictWatcher {
+    pub fn new<P: AsRef<Path>>(dir: P) -> io::Result<MDictWatcher> {
+        let root = dir.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Expect a directory",
+            ));
+        }
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watcher = MDictWatcher {
+            inotify: unsafe { File::from_raw_fd(fd) },
+            root: root.clone(),
+            dirs: HashMap::new(),
+            pending: BTreeSet::new(),
+            changing: HashSet::new(),
+        };
+        watcher.watch_dir(&root)?;
+        info!(
+            "Watch {} directories in {}",
+            watcher.dirs.len(),
+            root.to_string_lossy()
+        );
+        Ok(watcher)
+    }
+
+    /// Block until dictionaries are changing or changed.
+    ///
+    /// [`MDictChange::Changing`] is returned as soon as a file is changed, then other changes are
+    /// returned when no file is changed for a while. The headers of changed files are checked,
+    /// a dictionary which is broken after changing is skipped until it's fixed.
+    pub fn wait(&mut self) -> io::Result<Vec<MDictChange>> {
+        loop {
+            let timeout = if self.pending.is_empty() {
+                -1
+            } else {
+                DEBOUNCE_MS
+            };
+            if self.poll(timeout)? {
+                let mut pending = mem::take(&mut self.pending);
+                self.read_events(&mut pending)?;
+                self.pending = pending;
+                let changing: Vec<PathBuf> = self
+                    .pending
+                    .iter()
+                    .filter(|path| !self.changing.contains(*path))
+                    .cloned()
+                    .collect();
+                if !changing.is_empty() {
+                    self.changing.extend(changing.iter().cloned());
+                    return Ok(changing.into_iter().map(MDictChange::Changing).collect());
+                }
+            } else if !self.pending.is_empty() {
+                break;
+            }
+        }
+        self.changing.clear();
+        Ok(mem::take(&mut self.pending)
+            .into_iter()
+            .filter_map(|path| self.change(path))
+            .collect())
+    }
+
+    // Whether events can be read in `timeout` milliseconds, -1 waits forever.
+    fn poll(&self, timeout: i32) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        loop {
+            let n = unsafe { libc::poll(&mut fds, 1, timeout) };
+            if n >= 0 {
+                return Ok(n > 0);
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+    }
+
+    fn read_events(&mut self, changed: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+        let mut buf = vec![0; EVENT_BUF_SIZE];
+        let len = loop {
+            match self.inotify.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result?,
+            }
+        };
+        let mut offset = 0;
+        while offset + EVENT_HEADER_SIZE <= len {
+            let event = &buf[offset..len];
+            let field = |i: usize| event[i * 4..i * 4 + 4].try_into().unwrap();
+            let wd = i32::from_ne_bytes(field(0));
+            let mask = u32::from_ne_bytes(field(1));
+            let name_len = u32::from_ne_bytes(field(3)) as usize;
+            let name = &event[EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + name_len];
+            // the name is padded by 0
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name_len)];
+            self.handle(wd, mask, OsStr::from_bytes(name), changed);
+            offset += EVENT_HEADER_SIZE + name_len;
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, wd: i32, mask: u32, name: &OsStr, changed: &mut BTreeSet<PathBuf>) {
+        if mask & libc::IN_Q_OVERFLOW != 0 {
+            warn!("Too many changes, rescan {}", self.root.to_string_lossy());
+            let root = self.root.clone();
+            self.add_dir(&root, changed);
+            return;
+        }
+        // the directory is removed
+        if mask & libc::IN_IGNORED != 0 {
+            self.dirs.remove(&wd);
+            return;
+        }
+        let path = match self.dirs.get(&wd) {
+            Some(dir) if !name.is_empty() && !name.to_string_lossy().starts_with('.') => {
+                dir.join(name)
+            }
+            _ => return,
+        };
+        let created = mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0;
+        if mask & libc::IN_ISDIR != 0 {
+            if created {
+                self.add_dir(&path, changed);
+            } else {
+                // a moved directory is watched again by its new path
+                let moved: Vec<i32> = self
+                    .dirs
+                    .iter()
+                    .filter(|(_, dir)| dir.starts_with(&path))
+                    .map(|(wd, _)| *wd)
+                    .collect();
+                for wd in moved {
+                    unsafe { libc::inotify_rm_watch(self.inotify.as_raw_fd(), wd) };
+                    self.dirs.remove(&wd);
+                }
+                changed.insert(path);
+            }
+        } else if mask & libc::IN_CREATE != 0 && !is_symlink(&path) {
+            // wait until the new file is written and closed
+        } else if let Some(mdx_file) = dictionary_of(&path) {
+            changed.insert(mdx_file);
+        }
+    }
+
+    // Watch a new directory, and all dictionaries in it are changed.
+    fn add_dir(&mut self, dir: &Path, changed: &mut BTreeSet<PathBuf>) {
+        if let Err(e) = self.watch_dir(dir) {
+            warn!("Failed to watch {}: {}", dir.to_string_lossy(), e);
+        }
+        let mut mdx_files = Vec::new();
+        let mut errors = Vec::new();
+        find_mdx_files(
+            &self.root,
+            dir,
+            &mut HashSet::new(),
+            &mut mdx_files,
+            &mut errors,
+        );
+        changed.extend(mdx_files);
+    }
+
+    fn watch_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let c_path = CString::new(dir.as_os_str().as_bytes())?;
+        let wd = unsafe {
+            libc::inotify_add_watch(self.inotify.as_raw_fd(), c_path.as_ptr(), WATCH_MASK)
+        };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // a directory found by another path has the same watch descriptor
+        if self.dirs.get(&wd).map_or(false, |watched| watched != dir) {
+            return Ok(());
+        }
+        self.dirs.insert(wd, dir.to_owned());
+        let mut subdirs: Vec<PathBuf> = fs::read_dir(dir)?
+            .flatten()
+            .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect();
+        subdirs.sort();
+        for subdir in subdirs {
+            // a symbolic link in `root` is watched by its real path, like `mdict_scan`
+            if is_symlink(&subdir)
+                && subdir
+                    .canonicalize()
+                    .map_or(false, |real| real.starts_with(&self.root))
+            {
+                continue;
+            }
+            if let Err(e) = self.watch_dir(&subdir) {
+                warn!("Failed to watch {}: {}", subdir.to_string_lossy(), e);
+            }
+        }
+        Ok(())
+    }
+
+    fn change(&self, path: PathBuf) -> Option<MDictChange> {
+        if path.is_file() {
+            match scan_in(&self.root, &path) {
+                Ok(dict) => Some(MDictChange::Updated(dict)),
+                Err(e) => {
+                    warn!("Skip {}: {}", e.path.to_string_lossy(), e.error);
+                    None
+                }
+            }
+        } else if !path.exists() {
+            Some(MDictChange::Removed(path))
+        } else {
+            None
+        }
+    }
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink())
+}
+
+// The mdx file of a changed mdx or mdd file, `{name}.mdd` and `{name}.1.mdd` are in `{name}.mdx`.
+fn dictionary_of(path: &Path) -> Option<PathBuf> {
+    let ext = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    if ext == "mdx" {
+        return Some(path.to_owned());
+    }
+    if ext != "mdd" {
+        return None;
+    }
+    let stem = path.with_extension("");
+    let mut names = vec![stem.clone()];
+    if stem
+        .extension()
+        .map_or(false, |n| n.to_string_lossy().parse::<u32>().is_ok())
+    {
+        names.push(stem.with_extension(""));
+    }
+    names
+        .iter()
+        .flat_map(|name| {
+            ["mdx", "MDX"]
+                .iter()
+                .map(move |ext| name.with_extension(ext))
+        })
+        .find(|mdx_file| mdx_file.is_file())
+}
+
+/// Watch dictionaries in `dir` by [`MDictWatcher`] and update `library` in a background thread.
+///
+/// A changing dictionary is disabled by
+/// [`MDictLibrary::disable_path`](crate::MDictLibrary::disable_path), so its old index isn't used
+/// with the new files. Then the index is built by `build` in the background, and it replaces the
+/// old one by [`MDictLibrary::reload`](crate::MDictLibrary::reload), lookups which are using the
+/// old index are not affected. If `build` fails, the old index is enabled again until the files
+/// are changed again.
+///
+/// The path of the entry is set to the mdx file, so dictionaries added before watching should be
+/// added [`with_path`](MDictLibraryEntry::with_path) of [`MDictScanned::mdx_file`] to be reloaded.
+pub fn mdict_watch<P, D, F>(
+    dir: P,
+    library: Arc<MDictSharedLibrary<D>>,
+    build: F,
+) -> io::Result<thread::JoinHandle<()>>
+where
+    P: AsRef<Path>,
+    D: Send + Sync + 'static,
+    F: Fn(&MDictScanned) -> io::Result<MDictLibraryEntry<D>> + Send + 'static,
+{
+    let mut watcher = MDictWatcher::new(dir)?;
+    thread::Builder::new()
+        .name("mdict_watch".to_owned())
+        .spawn(move || loop {
+            let changes = match watcher.wait() {
+                Ok(changes) => changes,
+                Err(e) => {
+                    error!("Stop watching {}: {}", watcher.root.to_string_lossy(), e);
+                    return;
+                }
+            };
+            for change in changes {
+                match change {
+                    MDictChange::Changing(path) => {
+                        for id in library.update(|library| library.disable_path(&path)) {
+                            info!("Disable dictionary {} until it's updated", id);
+                        }
+                    }
+                    MDictChange::Updated(dict) => {
+                        let now = Instant::now();
+                        let entry = match build(&dict) {
+                            Ok(entry) => entry.with_path(&dict.mdx_file),
+                            Err(e) => {
+                                error!("Failed to build {}: {}", dict.id, e);
+                                // the old index is better than nothing, it's reloaded when
+                                // the files are changed again
+                                let mdx_file = &dict.mdx_file;
+                                for id in library.update(|library| library.enable_path(mdx_file)) {
+                                    warn!("Enable dictionary {} with the old index", id);
+                                }
+                                continue;
+                            }
+                        };
+                        match library.update(|library| library.reload(entry)) {
+                            Ok(()) => info!("Update {} in {:?}", dict.id, now.elapsed()),
+                            Err(e) => error!("Failed to update {}: {}", dict.id, e),
+                        }
+                    }
+                    MDictChange::Removed(path) => {
+                        for entry in library.update(|library| library.remove_path(&path)) {
+                            info!("Remove dictionary {}", entry.id());
+                        }
+                    }
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scan::tests::{write_header, TempDir},
+        MDictLibrary,
+    };
+    use std::{
+        sync::{mpsc, Mutex},
+        time::Duration,
+    };
+
+    // Changing paths, then the changes after debouncing
+    fn wait_changes(watcher: &mut MDictWatcher) -> (Vec<PathBuf>, Vec<String>) {
+        let mut changing = Vec::new();
+        loop {
+            let mut changed = Vec::new();
+            for change in watcher.wait().unwrap() {
+                match change {
+                    MDictChange::Changing(path) => changing.push(path),
+                    MDictChange::Updated(dict) => changed.push(format!("updated {}", dict.id)),
+                    MDictChange::Removed(path) => {
+                        changed.push(format!("removed {}", path.to_string_lossy()))
+                    }
+                }
+            }
+            if watcher.changing.is_empty() {
+                return (changing, changed);
+            }
+        }
+    }
+
+    #[test]
+    fn find_dictionary_of_files() {
+        let dir = TempDir::new("watch_files");
+        let root = &dir.0;
+        for name in &[
+            "a.mdx", "a.mdd", "a.1.mdd", "b.MDX", "b.mdd", "c.mdd", "d.txt",
+        ] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let of = |name: &str| dictionary_of(&root.join(name));
+        assert_eq!(of("a.mdx"), Some(root.join("a.mdx")));
+        assert_eq!(of("a.mdd"), Some(root.join("a.mdx")));
+        assert_eq!(of("a.1.mdd"), Some(root.join("a.mdx")));
+        assert_eq!(of("b.mdd"), Some(root.join("b.MDX")));
+        // a removed mdx file is its own dictionary
+        assert_eq!(of("e.mdx"), Some(root.join("e.mdx")));
+        assert_eq!(of("c.mdd"), None);
+        assert_eq!(of("d.txt"), None);
+    }
+
+    #[test]
+    fn watch_changes() {
+        let dir = TempDir::new("watch_changes");
+        let root = &dir.0;
+        let mdx_file = root.join("English/a.mdx");
+        let mut watcher = MDictWatcher::new(root).unwrap();
+        // files in a new directory are found
+        write_header(&mdx_file, "A");
+        let (changing, changed) = wait_changes(&mut watcher);
+        assert_eq!(changing, std::slice::from_ref(&mdx_file));
+        assert_eq!(changed, ["updated English/a"]);
+
+        // a dictionary is updated once with its mdd file, hidden files are ignored
+        write_header(&root.join("English/a.mdd"), "");
+        write_header(&mdx_file, "A2");
+        fs::write(root.join("English/.b.mdx"), "").unwrap();
+        let (changing, changed) = wait_changes(&mut watcher);
+        assert_eq!(changing, std::slice::from_ref(&mdx_file));
+        assert_eq!(changed, ["updated English/a"]);
+
+        // a broken dictionary is skipped
+        fs::write(&mdx_file, "broken").unwrap();
+        let (changing, changed) = wait_changes(&mut watcher);
+        assert_eq!(changing, std::slice::from_ref(&mdx_file));
+        assert!(changed.is_empty());
+
+        fs::remove_file(&mdx_file).unwrap();
+        let (changing, changed) = wait_changes(&mut watcher);
+        assert_eq!(changing, std::slice::from_ref(&mdx_file));
+        assert_eq!(changed, [format!("removed {}", mdx_file.to_string_lossy())]);
+    }
+
+    #[test]
+    fn enable_old_index_if_build_fails() {
+        let dir = TempDir::new("watch_build");
+        let mdx_file = dir.0.join("a.mdx");
+        write_header(&mdx_file, "A");
+        let mut library = MDictLibrary::new();
+        library
+            .add(MDictLibraryEntry::new("a", "A", "old").with_path(&mdx_file))
+            .unwrap();
+        let library = Arc::new(MDictSharedLibrary::new(library));
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let building = library.clone();
+        mdict_watch(&dir.0, library.clone(), move |dict: &MDictScanned| {
+            // the dictionary is disabled while building
+            let disabled = building.load().contains("a") && building.load().get("a").is_none();
+            let sender = sender.lock().unwrap();
+            sender.send(disabled).unwrap();
+            if dict.name == "A2" {
+                Err(io::Error::new(io::ErrorKind::Other, "Failed"))
+            } else {
+                Ok(MDictLibraryEntry::new(&dict.id, &dict.name, "new"))
+            }
+        })
+        .unwrap();
+        // the index after building, the library is updated after `build` returns
+        let index = || {
+            for _ in 0..100 {
+                if let Some(d) = library.load().get("a") {
+                    return Some((d.name().to_owned(), *d.index()));
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            None
+        };
+
+        write_header(&mdx_file, "A2");
+        let disabled: bool = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(disabled);
+        assert_eq!(index(), Some(("A".to_owned(), "old")));
+
+        write_header(&mdx_file, "A3");
+        let disabled: bool = receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(disabled);
+        assert_eq!(index(), Some(("A3".to_owned(), "new")));
+    }
+}
@@ -1,25 +1,29 @@
 use bytes::Bytes;
-use mdict_index::{MDictAsyncLookup, MDictSqliteIndex};
+#[cfg(target_os = "linux")]
+use mdict_index::{mdict_watch, MDictBuildOptions};
+use mdict_index::{
+    mdict_scan, mdict_scan_file, MDictAsyncLookup, MDictLibrary, MDictLibraryEntry,
+    MDictLookupOptions, MDictScanned, MDictSharedLibrary, MDictSqliteIndex,
+};
 use regex::Regex;
 use std::{
     env,
     fmt::Write,
     fs::File,
-    io::Read,
-    path::{Path, PathBuf},
+    io::{self, Read},
+    path::Path,
     sync::Arc,
 };
 use tokio::prelude::*;
 use warp::{filters::path::Tail, http::Response, Filter};
 
 const MDICT_JS: &str = include_str!("../static/mdict.js");
+// redirects followed in a row by `@@@LINK=`
+const MAX_REDIRECTS: usize = 5;
 
 #[tokio::main]
 async fn main() {
     let config_path = env::args().nth(1).unwrap().to_owned();
-    let mut config_file = File::open(&config_path).unwrap();
-    let mut config = String::new();
-    config_file.read_to_string(&mut config).unwrap();
     if env::var_os("RUST_LOG").is_none() {
         env::set_var(
             "RUST_LOG",
@@ -28,42 +32,57 @@ async fn main() {
     }
     pretty_env_logger::init();
     let log = warp::log("main");
-    let mut indexes = Vec::new();
-    let mut paths = Vec::new();
-    for path in config.lines() {
-        let mdict = MDictSqliteIndex::new(&path).await.unwrap();
-        indexes.push(mdict);
-        let dir = Path::new(&path)
-            .canonicalize()
-            .unwrap()
-            .parent()
-            .unwrap()
-            .to_owned();
-        paths.push(dir);
+    // the config is a directory of dictionaries, or a file of mdx files one per line
+    let watch = Path::new(&config_path).is_dir();
+    let dictionaries = if watch {
+        let report = mdict_scan(&config_path).unwrap();
+        for e in &report.errors {
+            log::error!("skip {} : {}", e.path.to_string_lossy(), e.error);
+        }
+        report.dictionaries
+    } else {
+        let mut config_file = File::open(&config_path).unwrap();
+        let mut config = String::new();
+        config_file.read_to_string(&mut config).unwrap();
+        config
+            .lines()
+            .map(|path| mdict_scan_file(path).map_err(|e| e.error).unwrap())
+            .collect()
+    };
+    let mut library = MDictLibrary::new();
+    for dict in dictionaries {
+        let mdict = MDictSqliteIndex::new(&dict.mdx_file).await.unwrap();
+        library.add(library_entry(&library, &dict, mdict)).unwrap();
     }
-    let indexes = Arc::new(indexes);
-    let paths = Arc::new(paths);
-    let indexes_clone = indexes.clone();
-    let indexes_shared = warp::any().map(move || indexes_clone.clone());
-    let indexes_shared2 = warp::any().map(move || indexes.clone());
-    let paths_shared = warp::any().map(move || paths.clone());
+    let library = Arc::new(MDictSharedLibrary::new(library));
+    if watch {
+        watch_library(&config_path, library.clone());
+    }
+    let library_clone = library.clone();
+    let library_shared = warp::any().map(move || library_clone.clone());
+    let library_clone = library.clone();
+    let library_shared2 = warp::any().map(move || library_clone.clone());
+    let library_shared3 = warp::any().map(move || library.clone());
     let mdict_server = warp::path::param()
         .and(warp::path::tail())
-        .and(indexes_shared)
+        .and(library_shared)
         .and_then(
-            |i: usize, path: Tail, mdict: Arc<Vec<MDictSqliteIndex>>| async move {
-                if i >= mdict.len() {
-                    return Err(warp::reject::not_found());
-                }
+            |id: String, path: Tail, library: Arc<MDictSharedLibrary<MDictSqliteIndex>>| async move {
+                // lookups use the library when they start, even if a dictionary is replaced
+                let library = library.load();
+                let dict = match library.get(&id) {
+                    Some(dict) => dict,
+                    None => return Err(warp::reject::not_found()),
+                };
                 let path = path.as_str();
-                log::info!("load: {:?}/{:?}", i, path);
+                log::info!("load: {:?}/{:?}", id, path);
                 let mime = mime_guess::from_path(path)
                     .first()
                     .unwrap_or(mime::TEXT_HTML_UTF_8);
-                match mdict[i].lookup_resource(path).await {
+                match dict.index().lookup_resource(path).await {
                     Ok(mut data) => {
                         if mime == mime::TEXT_CSS || mime == mime::TEXT_CSS_UTF_8 {
-                            data = fix_css(i, data);
+                            data = fix_css(&id, data);
                         }
                         Ok(Response::builder()
                             .header("content-type", mime.to_string())
@@ -79,16 +98,18 @@ async fn main() {
                 }
             },
         );
-    let files = warp::path!(usize / String)
+    let files = warp::path!(String / String)
         .and(warp::path::end())
-        .and(paths_shared)
+        .and(library_shared2)
         .and_then(
-            |i: usize, uri: String, paths: Arc<Vec<PathBuf>>| async move {
-                if i >= paths.len() {
-                    return Err(warp::reject::not_found());
-                }
-                log::info!("load files: {:?}/{:?}", i, uri);
-                let mut file = paths[i].clone();
+            |id: String, uri: String, library: Arc<MDictSharedLibrary<MDictSqliteIndex>>| async move {
+                let library = library.load();
+                let dir = library.get(&id).and_then(|d| d.path()).and_then(|p| p.parent());
+                let mut file = match dir {
+                    Some(dir) => dir.to_owned(),
+                    None => return Err(warp::reject::not_found()),
+                };
+                log::info!("load files: {:?}/{:?}", id, uri);
                 file.push(&uri);
                 if file.exists() {
                     let mut file = tokio::fs::File::open(&file)
@@ -101,7 +122,7 @@ async fn main() {
                     let mime = mime_guess::from_path(uri).first();
                     let mime = mime.unwrap_or(mime::TEXT_HTML_UTF_8);
                     let data = if mime == mime::TEXT_CSS || mime == mime::TEXT_CSS_UTF_8 {
-                        fix_css(i, data.into())
+                        fix_css(&id, data.into())
                     } else {
                         data.into()
                     };
@@ -114,23 +135,17 @@ async fn main() {
                 }
             },
         );
-    let lookup = warp::path::param().and( warp::path::end()).and(indexes_shared2).and_then(
-        |keyword: String, mdict: Arc<Vec<MDictSqliteIndex>>| async move {
+    let lookup = warp::path::param().and( warp::path::end()).and(library_shared3).and_then(
+        |keyword: String, library: Arc<MDictSharedLibrary<MDictSqliteIndex>>| async move {
+            let library = library.load();
             let key = urlencoding::decode(&keyword).unwrap();
             log::info!("lookup: {:?}", key);
             let mut body = format!(r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>{}</title></head><body>"#, keyword);
             let mut no_result = true;
-            for (i,dict) in mdict.iter().enumerate() {
-                let result = dict.lookup_word(&key).await;
-                let content = match result {
-                    Ok(result) => result,
-                    Err(e) => {
-                        if e.kind() != std::io::ErrorKind::NotFound {
-                            log::error!("lookup {} failed : {}", key, e);
-                        }
-                        continue;
-                    },
-                };
+            // broken redirects and keywords which differ in case, accents or width are shown as links
+            let options = MDictLookupOptions::new().redirects(MAX_REDIRECTS).candidates(true);
+            for found in library.lookup_with(&key, None, |dict| dict.lookup_word_with(&key, &options)).await {
+                let (id, content) = (found.id, found.result.join("\n"));
                 no_result = false;
                 let content = Regex::new(r#"(src|href)\s*=\s*"(file://|sound:/|entry:/)?/?([^"]+)""#)
                     .unwrap()
@@ -142,24 +157,25 @@ async fn main() {
                             Some(m) => {
                                 let proto = m.as_str();
                                 match proto {
-                                    "sound:/" => format!(r#"{}="sound://{}/{}""#,&link[1], i, &link[3]),
+                                    "sound:/" => format!(r#"{}="sound://{}/{}""#,&link[1], id, &link[3]),
                                     "entry:/" => format!(r#"{}="/{}""#,&link[1], &link[3]),
-                                    _ =>format!(r#"{}="/{}/{}""#,&link[1], i, &link[3])
+                                    _ =>format!(r#"{}="/{}/{}""#,&link[1], id, &link[3])
                                 }
                             }
-                            None => format!(r#"{}="/{}/{}""#,&link[1], i, &link[3])
+                            None => format!(r#"{}="/{}/{}""#,&link[1], id, &link[3])
                         }
                     });
-                let content = Regex::new("@@@LINK=([\\w\\s]+)").unwrap().replace_all(
+                let content = Regex::new("@@@LINK=([^\\r\\n\\x00<]+)").unwrap().replace_all(
                     &content,
                     |link: &regex::Captures| {
                         format!(
                             "<a href=\"/{}\" >See also: {}</a>",
-                            &link[1], &link[1]
+                            urlencoding::encode(&link[1]),
+                            html_escape::encode_text(&link[1])
                         )
                     },
                 );
-                write!(body, r#"<div id="mdict_rs_{}">{}</div>"#,i,content).unwrap();
+                write!(body, r#"<div id="mdict_rs_{}">{}</div>"#,id,content).unwrap();
             }
             if no_result {
                 return Err(warp::reject::not_found())
@@ -174,8 +190,58 @@ async fn main() {
     warp::serve(routes).run(([0, 0, 0, 0], 8080)).await;
 }
 
+// Changed dictionaries are rebuilt in the watching thread.
+#[cfg(target_os = "linux")]
+fn watch_library(dir: &str, library: Arc<MDictSharedLibrary<MDictSqliteIndex>>) {
+    let watched = library.clone();
+    mdict_watch(dir, library, move |dict: &MDictScanned| {
+        let options = MDictBuildOptions::new().rebuild(true);
+        let mdict = futures_executor::block_on(MDictSqliteIndex::with_options(
+            &dict.mdx_file,
+            &options,
+        ))
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(library_entry(&watched.load(), dict, mdict))
+    })
+    .unwrap();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_library(dir: &str, _library: Arc<MDictSharedLibrary<MDictSqliteIndex>>) {
+    log::warn!("Changes of dictionaries in {} are not watched on this platform", dir);
+}
+
+fn library_entry(
+    library: &MDictLibrary<MDictSqliteIndex>,
+    dict: &MDictScanned,
+    mdict: MDictSqliteIndex,
+) -> MDictLibraryEntry<MDictSqliteIndex> {
+    let id = dict_id(library, &dict.id);
+    let mut entry = MDictLibraryEntry::new(&id, &dict.name, mdict).with_path(&dict.mdx_file);
+    if let Some(group) = &dict.group {
+        entry = entry.with_group(group);
+    }
+    entry
+}
+
+// A stable ID of a dictionary from its path, which is used in URLs and CSS selectors,
+// so only ASCII letters, digits, '-' and '_' are kept.
+fn dict_id(library: &MDictLibrary<MDictSqliteIndex>, name: &str) -> String {
+    let id: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    let mut unique = id.clone();
+    let mut n = 1;
+    while library.contains(&unique) {
+        n += 1;
+        unique = format!("{}_{}", id, n);
+    }
+    unique
+}
+
 // from flask-mdict
-fn fix_css(id: usize, css: Bytes) -> Bytes {
+fn fix_css(id: &str, css: Bytes) -> Bytes {
     let css = std::str::from_utf8(&css).unwrap();
     // remove comments, https://stackoverflow.com/questions/9329552/explain-regex-that-finds-css-comments
     let css = Regex::new(r#"(/\*[^*]*\*+([^/*][^*]*\*+)*/)"#)
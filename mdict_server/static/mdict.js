@@ -3,8 +3,10 @@ function click_sound(event) {
     event.preventDefault();
     // prevent other event listener
     event.stopPropagation();
-    let url = this.href;
-    url = url.replace("sound://", "");
+    // the link is "sound://{id}/{path}", where the id of a dictionary is a string like
+    // "Oxford_Dictionary", it's loaded from "/{id}/{path}" of the server
+    let url = this.getAttribute('href');
+    url = '/' + url.replace("sound://", "");
     let mdict_player = document.createElement("audio");
     mdict_player.id = 'mdict_player';
     mdict_player.src = url;
@@ -12,10 +14,8 @@ function click_sound(event) {
 }
 
 for (let element of document.getElementsByTagName('A')) {
-    if (element.href) {
-        let url = element.href;
-        if (element.href.startsWith('sound://')) {
-            element.addEventListener('click', click_sound);
-        }
+    let url = element.getAttribute('href');
+    if (url && url.startsWith('sound://')) {
+        element.addEventListener('click', click_sound);
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,11 @@
-use mdict_index::{MDictAsyncLookup, MDictSqliteIndex};
+use mdict_index::{MDictAsyncLookup, MDictLookupOptions, MDictSqliteIndex};
 use regex::Regex;
 use std::{env, path::Path, sync::Arc};
 use warp::{filters::path::Tail, http::Response, Filter};
 
+// redirects followed in a row by `@@@LINK=`
+const MAX_REDIRECTS: usize = 5;
+
 #[tokio::main]
 async fn main() {
     let file = env::args().nth(1).unwrap().to_owned();
@@ -31,13 +34,23 @@ async fn main() {
                 Some(_) => mdict.lookup_resource(path).await,
                 None => {
                     let key = urlencoding::decode(path).unwrap();
-                    let result = mdict.lookup_word(&key).await;
+                    // broken redirects and keywords which differ in case, accents or width
+                    // are shown as links
+                    let options = MDictLookupOptions::new()
+                        .redirects(MAX_REDIRECTS)
+                        .candidates(true);
+                    let result = mdict.lookup_word_with(&key, &options).await;
                     result.map(|opt| {
+                        let opt = opt.join("\n");
                         let string = Regex::new("(entry|sound):/").unwrap().replace_all(&opt, "");
-                        let string = Regex::new("@@@LINK=([\\w]+)").unwrap().replace_all(
+                        let string = Regex::new("@@@LINK=([^\\r\\n\\x00<]+)").unwrap().replace_all(
                             &string,
                             |link: &regex::Captures| {
-                                format!("<a href=\"/{}\" >See also: {}</a>", &link[1], &link[1])
+                                format!(
+                                    "<a href=\"/{}\" >See also: {}</a>",
+                                    urlencoding::encode(&link[1]),
+                                    html_escape::encode_text(&link[1])
+                                )
                             },
                         );
                         string.to_string().into()